@@ -0,0 +1,81 @@
+use tux_io_encoding::{
+    CompressionTypes,
+    compression_types::{GzipCompressionType, NoCompression, ZStdCompressionType},
+};
+
+/// zstd's own default level.
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+/// The level `gzip` itself uses when not given one.
+const DEFAULT_GZIP_LEVEL: u32 = 6;
+
+/// Parses a codec given as `name` or `name:level`.
+pub fn parse_compression(value: &str) -> Result<CompressionTypes, String> {
+    let (name, level) = match value.split_once(':') {
+        Some((name, level)) => (name, Some(level)),
+        None => (value, None),
+    };
+    match name.to_ascii_lowercase().as_str() {
+        "none" => match level {
+            None => Ok(CompressionTypes::None(NoCompression)),
+            Some(_) => Err("`none` does not take a level".to_owned()),
+        },
+        "zstd" => {
+            let level = match level {
+                Some(level) => level
+                    .parse::<i32>()
+                    .map_err(|_| format!("invalid zstd level `{level}`"))?,
+                None => DEFAULT_ZSTD_LEVEL,
+            };
+            Ok(CompressionTypes::ZSTD(ZStdCompressionType(level)))
+        }
+        "gzip" => {
+            let level = match level {
+                Some(level) => level
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|level| *level <= 9)
+                    .ok_or_else(|| format!("invalid gzip level `{level}`, expected 0 to 9"))?,
+                None => DEFAULT_GZIP_LEVEL,
+            };
+            Ok(CompressionTypes::Gzip(GzipCompressionType(level)))
+        }
+        other => Err(format!(
+            "unknown codec `{other}`, expected `none`, `zstd` or `gzip`"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codecs_parse_with_and_without_a_level() {
+        assert_eq!(
+            parse_compression("zstd:19"),
+            Ok(CompressionTypes::ZSTD(ZStdCompressionType(19)))
+        );
+        assert_eq!(
+            parse_compression("zstd"),
+            Ok(CompressionTypes::ZSTD(ZStdCompressionType(
+                DEFAULT_ZSTD_LEVEL
+            )))
+        );
+        assert_eq!(
+            parse_compression("GZIP:9"),
+            Ok(CompressionTypes::Gzip(GzipCompressionType(9)))
+        );
+        assert_eq!(
+            parse_compression("none"),
+            Ok(CompressionTypes::None(NoCompression))
+        );
+    }
+
+    #[test]
+    fn bad_codecs_are_rejected() {
+        assert!(parse_compression("lz4").is_err());
+        assert!(parse_compression("gzip:10").is_err());
+        assert!(parse_compression("zstd:fast").is_err());
+        assert!(parse_compression("none:1").is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use std::{path::Path, process::ExitCode};
+
+use tux_io_encoding::{
+    RawDateTime,
+    fs::lifecycle::{self, ExpirationPolicy},
+};
+
+/// Expires the objects under `dir` that `policy` finds expired now, printing each one.
+pub fn run(dir: &Path, policy: &ExpirationPolicy) -> ExitCode {
+    match lifecycle::sweep(dir, RawDateTime::now(), policy) {
+        Ok(report) => {
+            let verb = if policy.dry_run {
+                "would expire"
+            } else {
+                "expired"
+            };
+            for path in &report.expired {
+                println!("{verb} {}", path.display());
+            }
+            for path in &report.unreadable {
+                eprintln!("{}: not readable as an object", path.display());
+            }
+            println!(
+                "{verb} {} objects ({} bytes), {} retained, {} unreadable",
+                report.expired.len(),
+                report.expired_bytes,
+                report.retained,
+                report.unreadable.len()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}: {err}", dir.display());
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::{path::Path, process::ExitCode, time::Duration};
+
+use tux_io_encoding::fs::sweep_temp_files;
+
+/// Sweeps `dir` for abandoned temp files, printing each one removed.
+pub fn run(dir: &Path, older_than: Duration) -> ExitCode {
+    match sweep_temp_files(dir, older_than) {
+        Ok(report) => {
+            for path in &report.removed {
+                println!("removed {}", path.display());
+            }
+            println!(
+                "removed {} temp files ({} bytes), {} still being written, {} too recent",
+                report.removed.len(),
+                report.removed_bytes,
+                report.in_use,
+                report.too_recent
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}: {err}", dir.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses an age such as `90s`, `15m`, `2h` or `7d`.
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("`{value}` needs a unit: `s`, `m`, `h` or `d`"))?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid age `{value}`"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        other => {
+            return Err(format!(
+                "unknown unit `{other}`, expected `s`, `m`, `h` or `d`"
+            ));
+        }
+    };
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("age `{value}` is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_parse_with_a_unit() {
+        assert_eq!(parse_age("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_age("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_age("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_age("0d"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn bad_ages_are_rejected() {
+        assert!(parse_age("12").is_err());
+        assert!(parse_age("h").is_err());
+        assert!(parse_age("3w").is_err());
+        assert!(parse_age("-1h").is_err());
+    }
+}
@@ -1,3 +1,75 @@
-fn main() {
-    println!("Hello, world!");
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use clap::{Parser, Subcommand};
+use tux_io_encoding::fs::lifecycle::ExpirationPolicy;
+
+mod codec;
+mod expire;
+mod gc;
+mod recompress;
+
+/// Debugging and maintenance for TuxIO objects.
+#[derive(Debug, Parser)]
+#[command(name = "tuxio", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Re-encode every object under a directory with another codec or level.
+    Recompress {
+        /// The codec to move to: `none`, `zstd`, `gzip`, or one of the latter with a level, such as
+        /// `zstd:19` or `gzip:9`.
+        #[arg(long, value_parser = codec::parse_compression)]
+        to: tux_io_encoding::CompressionTypes,
+        /// Directory to walk. Objects in subdirectories are included.
+        dir: PathBuf,
+    },
+    /// Remove the temp files writers left behind under a directory when they were killed.
+    Gc {
+        /// Only remove files last modified longer ago than this, given as a number with a unit of
+        /// `s`, `m`, `h` or `d`.
+        #[arg(long, value_parser = gc::parse_age, default_value = "1h")]
+        older_than: Duration,
+        /// Directory to sweep. Subdirectories are included.
+        dir: PathBuf,
+    },
+    /// Delete the objects under a directory whose `x-tuxio-expires-at` metadata has passed.
+    Expire {
+        /// Print what would be deleted, without deleting anything.
+        #[arg(long)]
+        dry_run: bool,
+        /// Only expire objects of this storage class. May be given more than once.
+        #[arg(long = "storage-class", value_name = "CLASS")]
+        storage_classes: Vec<String>,
+        /// Expire objects without an expiry of their own this long after they were last modified,
+        /// given as a number with a unit of `s`, `m`, `h` or `d`.
+        #[arg(long, value_parser = gc::parse_age)]
+        default_lifetime: Option<Duration>,
+        /// Directory to sweep. Subdirectories are included.
+        dir: PathBuf,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Recompress { to, dir } => recompress::run(&dir, to),
+        Command::Gc { older_than, dir } => gc::run(&dir, older_than),
+        Command::Expire {
+            dry_run,
+            storage_classes,
+            default_lifetime,
+            dir,
+        } => {
+            let policy = ExpirationPolicy {
+                dry_run,
+                storage_classes,
+                default_lifetime,
+            };
+            expire::run(&dir, &policy)
+        }
+    }
 }
@@ -0,0 +1,62 @@
+use std::{path::Path, process::ExitCode};
+
+use tux_io_encoding::{
+    CompressionTypes,
+    fs::{ObjectFileResult, TuxObject, object_files},
+};
+
+/// Recompresses every object under `dir`, carrying on past objects that fail.
+pub fn run(dir: &Path, compression: CompressionTypes) -> ExitCode {
+    let files = match object_files(dir) {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("{}: {err}", dir.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut recompressed = 0usize;
+    let mut unchanged = 0usize;
+    let mut failed = 0usize;
+    let mut bytes_before = 0u64;
+    let mut bytes_after = 0u64;
+    for path in &files {
+        match recompress_one(path, compression) {
+            Ok(Some((before, after))) => {
+                recompressed += 1;
+                bytes_before += before;
+                bytes_after += after;
+                println!("{}: {before} -> {after} bytes", path.display());
+            }
+            Ok(None) => unchanged += 1,
+            Err(err) => {
+                failed += 1;
+                eprintln!("{}: {err}", path.display());
+            }
+        }
+    }
+
+    println!(
+        "recompressed {recompressed} objects ({bytes_before} -> {bytes_after} bytes), \
+         {unchanged} already {compression:?}, {failed} failed"
+    );
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Returns the file size before and after, or `None` when the object already uses `compression`.
+fn recompress_one(
+    path: &Path,
+    compression: CompressionTypes,
+) -> ObjectFileResult<Option<(u64, u64)>> {
+    let mut object = TuxObject::open_writable(path)?;
+    if object.compression() == compression {
+        return Ok(None);
+    }
+    let before = object.file_size()?;
+    object.recompress(compression)?;
+    Ok(Some((before, object.file_size()?)))
+}
@@ -0,0 +1,59 @@
+use syn::{LitStr, Result, parse::Parse};
+mod keywords {
+    use syn::custom_keyword;
+    custom_keyword!(skip);
+    custom_keyword!(with);
+    custom_keyword!(key);
+}
+/// `#[tux(...)]` on one field. Shared by the derives so one struct can take several of them; each
+/// reads the attributes that mean something to it.
+#[derive(Default)]
+pub struct FieldAttributes {
+    pub skip: bool,
+    pub with: Option<syn::Path>,
+    pub key: Option<LitStr>,
+}
+impl FieldAttributes {
+    /// The attributes on `field`, or the defaults when it has none.
+    pub fn of(field: &syn::Field) -> Result<Self> {
+        let mut attributes = FieldAttributes::default();
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("tux"))
+        {
+            let parsed = attr.parse_args::<FieldAttributes>()?;
+            attributes.skip |= parsed.skip;
+            attributes.with = parsed.with.or(attributes.with);
+            attributes.key = parsed.key.or(attributes.key);
+        }
+        Ok(attributes)
+    }
+}
+impl Parse for FieldAttributes {
+    fn parse(input: syn::parse::ParseStream) -> Result<Self> {
+        let mut attributes = FieldAttributes::default();
+        while !input.is_empty() {
+            if input.peek(keywords::skip) {
+                input.parse::<keywords::skip>()?;
+                attributes.skip = true;
+            } else if input.peek(keywords::with) {
+                input.parse::<keywords::with>()?;
+                input.parse::<syn::Token![=]>()?;
+                attributes.with = Some(input.parse()?);
+            } else if input.peek(keywords::key) {
+                input.parse::<keywords::key>()?;
+                input.parse::<syn::Token![=]>()?;
+                attributes.key = Some(input.parse()?);
+            } else {
+                return Err(input.error("Expected skip, with, or key attribute"));
+            }
+            if input.peek(syn::Token![,]) {
+                input.parse::<syn::Token![,]>()?;
+            } else {
+                break;
+            }
+        }
+        Ok(attributes)
+    }
+}
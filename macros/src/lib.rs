@@ -3,7 +3,10 @@
 //! Not useful on their own — see the [`tux-io-encoding`](https://docs.rs/tux-io-encoding) crate, which
 //! re-exports both and defines the traits they implement.
 
+mod attributes;
+mod metadata;
 mod object_type;
+mod record;
 mod value_enum;
 
 /// Implements `TuxIOType`, `TypedObjectType` and `ConstTypedObjectType` for a fixed-size type.
@@ -53,18 +56,151 @@ pub fn object_type(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     proc_macro::TokenStream::from(expanded)
 }
 
-/// Implements the encoding traits for a dynamically-typed value enum.
+/// Implements `TuxIOType` and `WritableObjectType` for a struct, writing its fields one after another
+/// in declaration order, each through its own `WritableObjectType` impl.
+///
+/// `size()` is the sum of the fields' sizes. `const_size()` is their sum too, and `None` as soon as
+/// any field's is — so a record of fixed-size fields is fixed-size. There is no framing of any kind:
+/// no type key, no length prefix, no field count. Reordering, adding or removing a field changes the
+/// encoding, so treat the declaration order of a record already on disk as part of the format.
+///
+/// Pair it with [`TuxDecode`] for the reading half.
+///
+/// # Field attributes
+///
+/// `#[tux(...)]` on a field takes either of:
+///
+/// | Attribute | Effect |
+/// | --------- | ------ |
+/// | `skip` | the field is not encoded; [`TuxDecode`] fills it in with `Default::default()` |
+/// | `with = path` | the field is encoded by the functions in the module at `path` rather than its own impls |
+///
+/// A `with` module provides the four functions the traits would, for the field's type `T`:
+///
+/// ```ignore
+/// pub fn size(value: &T) -> usize;
+/// pub fn write<W: std::io::Write>(value: &T, writer: &mut W) -> Result<(), EncodingError>;
+/// pub fn read<R: std::io::Read>(reader: &mut R) -> Result<T, EncodingError>;
+/// pub fn read_size<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<usize, EncodingError>;
+/// ```
+///
+/// A record with a `with` field has no `const_size()`, since the module has no way to report one.
+///
+/// ```ignore
+/// #[derive(Debug, TuxEncode, TuxDecode)]
+/// pub struct PartRecord {
+///     pub number: u32,
+///     pub etag: String,
+///     pub uploaded: RawDateTime,
+///     #[tux(skip)]
+///     pub cached_path: Option<PathBuf>,
+/// }
+/// ```
+///
+/// The generated code names the traits through `::tux_io_encoding`, so the crate must be a
+/// dependency under that name.
+#[proc_macro_derive(TuxEncode, attributes(tux))]
+pub fn tux_encode(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    let expanded = record::expand_encode(input).unwrap_or_else(|err| err.to_compile_error());
+    proc_macro::TokenStream::from(expanded)
+}
+
+/// Implements `ReadableObjectType` for a struct, reading the fields [`TuxEncode`] writes in the same
+/// order. `read_size()` sums the fields' own `read_size()`, and leaves the cursor at the end of the
+/// record whether or not each of those leaves it at the end of its field.
+///
+/// `ReadableObjectType` requires `TuxIOType`, so this needs [`TuxEncode`] or a hand-written impl
+/// beside it. It takes the same `#[tux(...)]` field attributes.
+///
+/// It also generates `assert_encoding_invariants(&self)`, compiled only under `cfg(test)`, which
+/// panics unless the value upholds the size invariant documented on `TuxIOType` and decodes from
+/// exactly the bytes it wrote. Call it from a test with a few representative values; it needs
+/// `WritableObjectType` as well.
+///
+/// ```ignore
+/// #[test]
+/// fn part_records_agree() {
+///     PartRecord { number: 1, etag: "abc".to_owned(), ..Default::default() }
+///         .assert_encoding_invariants();
+/// }
+/// ```
+#[proc_macro_derive(TuxDecode, attributes(tux))]
+pub fn tux_decode(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    let expanded = record::expand_decode(input).unwrap_or_else(|err| err.to_compile_error());
+    proc_macro::TokenStream::from(expanded)
+}
+
+/// Maps a struct's fields to the keys of a `MetadataMap` or `Tags`, so the key names and value types
+/// are written down once instead of at every lookup.
 ///
-/// Applied to `ValueType` in `tux-io-encoding`, which is the value half of every metadata and tag map.
 /// Generates:
 ///
+/// - `into_metadata_map(self)` and `into_tags(self)`, with one entry per field
+/// - `TryFrom<&MetadataMap>` and `TryFrom<&Tags>`, failing with `MetadataError::MissingKey` for an
+///   absent key and `MetadataError::WrongType` for a value of another `ValueType` variant
+///
+/// A field's type must be one a `ValueType` variant holds, or an `Option` of one. An `Option` field
+/// is left out when it is `None` and reads back as `None` when its key is absent; any other field's
+/// key is required. Keys not named by a field are ignored when reading.
+///
+/// # Field attributes
+///
+/// `#[tux(...)]` on a field takes either of:
+///
+/// | Attribute | Effect |
+/// | --------- | ------ |
+/// | `key = "name"` | the key the field is stored under. Without it, the field's name with `_` replaced by `-` |
+/// | `skip` | the field is not stored, and reads back as `Default::default()` |
+///
+/// Every key must be a valid HTTP header name in lowercase, as metadata keys are — a key that is not
+/// is rejected here, at compile time, instead of panicking the first time it is used. So are two
+/// stored fields with the same key, one of which would silently overwrite the other, and `with`,
+/// which only the record derives use.
+///
+/// ```ignore
+/// #[derive(Debug, TuxMetadata)]
+/// pub struct ObjectMetadata {
+///     #[tux(key = "content-type")]
+///     pub content_type: String,
+///     pub etag: Option<String>,
+///     #[tux(key = "x-tuxio-size")]
+///     pub size: u64,
+/// }
+///
+/// let metadata = ObjectMetadata::try_from(object.metadata())?;
+/// ```
+#[proc_macro_derive(TuxMetadata, attributes(tux))]
+pub fn tux_metadata(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    let expanded = metadata::expand(input).unwrap_or_else(|err| err.to_compile_error());
+    proc_macro::TokenStream::from(expanded)
+}
+
+/// Implements the encoding traits for a dynamically-typed value enum.
+///
+/// Applied to `ValueType` in `tux-io-encoding`, which is the value half of every metadata and tag map,
+/// and to any enum of your own that holds some of the same types — one that only lets users put
+/// strings and numbers in their tags, say. Every such enum shares the wire format, so a value written
+/// through one reads back through any other that has a variant for its type. Generates:
+///
 /// - `TuxIOType`, `WritableObjectType` and `ReadableObjectType`, dispatching on a one-byte type key
+/// - `type_key(&self)`, the key a value is written with
 /// - `From<Inner>` for each variant, so `"text".to_owned().into()` builds one
-/// - `From<ValueType> for Option<Inner>`, for getting back out
+/// - `From<Enum> for Option<Inner>`, for getting back out
+/// - unless the enum is `ValueType` itself, `From<Enum> for ValueType` and
+///   `TryFrom<ValueType> for Enum`, which hands the value back as its error when the enum has no
+///   variant for its type
 ///
 /// Every variant must be a tuple variant with exactly one field, and that field's type must implement
-/// `ConstTypedObjectType` — its `TYPE_KEY` is what the generated reader matches on, so two variants
-/// whose inner types share a key make the second unreachable.
+/// `ConstTypedObjectType` — its `TYPE_KEY` is what the generated reader matches on. Two variants
+/// whose inner types share a key are a compile-time error, since the second could never be read
+/// back. The conversions with `ValueType` additionally need each inner type to be one a `ValueType`
+/// variant holds.
 ///
 /// The generated `size()` includes the type-key byte, matching what the generated writer emits and
 /// what `read_size` counts. That is the invariant documented on `TuxIOType`, and it was violated here:
@@ -72,16 +208,18 @@ pub fn object_type(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 ///
 /// ```ignore
 /// #[derive(Debug, Clone, PartialEq, ValueEnum)]
-/// pub enum ValueType {
-///     String(String),
-///     U32(u32),
-///     // ...
+/// pub enum PublicTagValue {
+///     Text(String),
+///     Number(u64),
 /// }
+///
+/// let value = PublicTagValue::try_from(tags.get("team").cloned().unwrap())?;
 /// ```
 ///
-/// Takes no attributes. The name `ValueType` is currently hardcoded in the generated `From` impls, so
-/// the derive only works for an enum of that name.
-#[proc_macro_derive(ValueEnum)]
+/// `#[value_enum(crate_value_type)]` marks the enum as the crate's own `ValueType`, which gets no
+/// conversions with itself. It is for `tux-io-encoding` alone; any other enum, whatever it is named,
+/// gets them.
+#[proc_macro_derive(ValueEnum, attributes(value_enum))]
 pub fn value_enum(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
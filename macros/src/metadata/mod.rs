@@ -0,0 +1,225 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Ident, LitStr, Result};
+
+use crate::attributes::FieldAttributes;
+/// One field of the schema and the key it is stored under.
+struct MetadataField {
+    ident: Ident,
+    /// The field's type with any `Option` taken off.
+    value_type: syn::Type,
+    optional: bool,
+    key: LitStr,
+    skip: bool,
+}
+impl MetadataField {
+    fn parse(field: &syn::Field) -> Result<Self> {
+        let attributes = FieldAttributes::of(field)?;
+        if let Some(with) = &attributes.with {
+            return Err(syn::Error::new_spanned(
+                with,
+                "TuxMetadata stores each field as a ValueType and has no use for `with`",
+            ));
+        }
+        let ident = field
+            .ident
+            .clone()
+            .expect("only named fields reach this point");
+        let key = match attributes.key {
+            Some(key) => key,
+            None => LitStr::new(
+                &ident.to_string().trim_start_matches("r#").replace('_', "-"),
+                ident.span(),
+            ),
+        };
+        if !is_metadata_key(&key.value()) {
+            return Err(syn::Error::new_spanned(
+                &key,
+                "a metadata key must be a valid HTTP header name, in lowercase",
+            ));
+        }
+        let (value_type, optional) = match option_inner(&field.ty) {
+            Some(inner) => (inner.clone(), true),
+            None => (field.ty.clone(), false),
+        };
+        Ok(MetadataField {
+            ident,
+            value_type,
+            optional,
+            key,
+            skip: attributes.skip,
+        })
+    }
+    /// Statements inserting the field into `map` with `insert`, which is handed the key and value.
+    fn insert(&self, insert: impl Fn(&LitStr, TokenStream) -> TokenStream) -> TokenStream {
+        let ident = &self.ident;
+        if self.optional {
+            let insert = insert(
+                &self.key,
+                quote! { ::tux_io_encoding::ValueType::from(value) },
+            );
+            quote! {
+                if let ::core::option::Option::Some(value) = self.#ident {
+                    #insert
+                }
+            }
+        } else {
+            insert(
+                &self.key,
+                quote! { ::tux_io_encoding::ValueType::from(self.#ident) },
+            )
+        }
+    }
+    /// The field's initializer, reading the value `get` looks up by key.
+    fn read(&self, get: impl Fn(&LitStr) -> TokenStream) -> TokenStream {
+        let (ident, value_type, key) = (&self.ident, &self.value_type, &self.key);
+        if self.skip {
+            return quote! { #ident: ::core::default::Default::default() };
+        }
+        let read = if self.optional {
+            quote! { optional }
+        } else {
+            quote! { required }
+        };
+        let value = get(key);
+        quote! {
+            #ident: ::tux_io_encoding::__private::#read::<#value_type>(#key, #value)?
+        }
+    }
+}
+/// Whether `key` is a header name `http::HeaderName::from_static` accepts: lowercase token
+/// characters only.
+fn is_metadata_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.bytes().all(|byte| {
+            byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"!#$%&'*+-.^_`|~".contains(&byte)
+        })
+}
+/// `T` when `ty` is spelled `Option<T>`.
+fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return None;
+    };
+    match arguments.args.first()? {
+        syn::GenericArgument::Type(inner) if arguments.args.len() == 1 => Some(inner),
+        _ => None,
+    }
+}
+pub fn expand(input: DeriveInput) -> Result<TokenStream> {
+    let DeriveInput {
+        ident,
+        generics,
+        data,
+        ..
+    } = input;
+    let fields = match data {
+        Data::Struct(syn::DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => fields
+            .named
+            .iter()
+            .map(MetadataField::parse)
+            .collect::<Result<Vec<_>>>()?,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "TuxMetadata can only be derived for structs with named fields",
+            ));
+        }
+    };
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let stored: Vec<&MetadataField> = fields.iter().filter(|field| !field.skip).collect();
+    for (index, field) in stored.iter().enumerate() {
+        let key = field.key.value();
+        if stored[..index]
+            .iter()
+            .any(|earlier| earlier.key.value() == key)
+        {
+            return Err(syn::Error::new_spanned(
+                &field.key,
+                format!("another field is already stored under the key {key:?}"),
+            ));
+        }
+    }
+
+    let metadata_inserts = stored.iter().map(|field| {
+        field.insert(|key, value| {
+            quote! {
+                map.insert_header(
+                    ::tux_io_encoding::__private::HeaderName::from_static(#key),
+                    #value,
+                );
+            }
+        })
+    });
+    let tag_inserts = stored.iter().map(|field| {
+        field.insert(|key, value| {
+            quote! {
+                tags.insert(::std::string::String::from(#key), #value);
+            }
+        })
+    });
+    let metadata_reads = fields.iter().map(|field| {
+        field.read(|key| {
+            quote! {
+                map.get_header(&::tux_io_encoding::__private::HeaderName::from_static(#key))
+            }
+        })
+    });
+    let tag_reads = fields
+        .iter()
+        .map(|field| field.read(|key| quote! { tags.get(#key) }));
+
+    Ok(quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// This value as metadata, one key per field. `None` fields are left out.
+            #[allow(unused_mut)]
+            pub fn into_metadata_map(self) -> ::tux_io_encoding::MetadataMap {
+                let mut map = ::tux_io_encoding::MetadataMap::new();
+                #(#metadata_inserts)*
+                map
+            }
+            /// This value as tags, one key per field. `None` fields are left out.
+            #[allow(unused_mut)]
+            pub fn into_tags(self) -> ::tux_io_encoding::Tags {
+                let mut tags = ::tux_io_encoding::Tags::new();
+                #(#tag_inserts)*
+                tags
+            }
+        }
+        impl #impl_generics ::core::convert::TryFrom<&::tux_io_encoding::MetadataMap>
+            for #ident #ty_generics #where_clause
+        {
+            type Error = ::tux_io_encoding::MetadataError;
+            #[allow(unused_variables)]
+            fn try_from(
+                map: &::tux_io_encoding::MetadataMap,
+            ) -> ::core::result::Result<Self, Self::Error> {
+                ::core::result::Result::Ok(Self {
+                    #(#metadata_reads,)*
+                })
+            }
+        }
+        impl #impl_generics ::core::convert::TryFrom<&::tux_io_encoding::Tags>
+            for #ident #ty_generics #where_clause
+        {
+            type Error = ::tux_io_encoding::MetadataError;
+            #[allow(unused_variables)]
+            fn try_from(
+                tags: &::tux_io_encoding::Tags,
+            ) -> ::core::result::Result<Self, Self::Error> {
+                ::core::result::Result::Ok(Self {
+                    #(#tag_reads,)*
+                })
+            }
+        }
+    })
+}
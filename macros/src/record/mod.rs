@@ -0,0 +1,271 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, Generics, Ident, Result};
+
+use crate::attributes::FieldAttributes;
+/// One field of the record, in declaration order.
+struct RecordField {
+    /// How the field is reached from `self`: its name, or its index in a tuple struct.
+    member: syn::Member,
+    /// What the decoder binds the field to before building the record.
+    binding: Ident,
+    ty: syn::Type,
+    attributes: FieldAttributes,
+}
+impl RecordField {
+    fn is_encoded(&self) -> bool {
+        !self.attributes.skip
+    }
+    fn size(&self) -> TokenStream {
+        let member = &self.member;
+        match &self.attributes.with {
+            Some(with) => quote! { #with::size(&self.#member) },
+            None => quote! { ::tux_io_encoding::TuxIOType::size(&self.#member) },
+        }
+    }
+    fn write(&self) -> TokenStream {
+        let member = &self.member;
+        match &self.attributes.with {
+            Some(with) => quote! { #with::write(&self.#member, writer)?; },
+            None => quote! {
+                ::tux_io_encoding::WritableObjectType::write_to_writer(&self.#member, writer)?;
+            },
+        }
+    }
+    /// Measures the field and moves past it. Not every `read_size` leaves the cursor at the end of
+    /// what it measured — the fixed-size types do not move it at all — so the next field is found
+    /// from the size rather than from wherever the cursor was left.
+    fn read_size(&self) -> TokenStream {
+        let ty = &self.ty;
+        let measure = match &self.attributes.with {
+            Some(with) => quote! { #with::read_size(reader)? },
+            None => quote! { <#ty as ::tux_io_encoding::ReadableObjectType>::read_size(reader)? },
+        };
+        quote! {
+            size += #measure;
+            ::std::io::Seek::seek(reader, ::std::io::SeekFrom::Start(start + size as u64))?;
+        }
+    }
+    fn read(&self) -> TokenStream {
+        let (binding, ty) = (&self.binding, &self.ty);
+        let value = if self.attributes.skip {
+            quote! { ::core::default::Default::default() }
+        } else if let Some(with) = &self.attributes.with {
+            quote! { #with::read(reader)? }
+        } else {
+            quote! { <#ty as ::tux_io_encoding::ReadableObjectType>::read_from_reader(reader)? }
+        };
+        quote! { let #binding: #ty = #value; }
+    }
+}
+struct Record {
+    ident: Ident,
+    generics: Generics,
+    fields: Vec<RecordField>,
+    /// Whether the fields are named, unnamed, or absent — which decides how the decoder builds it.
+    shape: Fields,
+}
+impl Record {
+    fn parse(input: DeriveInput) -> Result<Self> {
+        let DeriveInput {
+            ident,
+            generics,
+            data,
+            ..
+        } = input;
+        let Data::Struct(data) = data else {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "TuxEncode and TuxDecode can only be derived for structs",
+            ));
+        };
+        let mut fields = Vec::with_capacity(data.fields.len());
+        for (index, field) in data.fields.iter().enumerate() {
+            let attributes = FieldAttributes::of(field)?;
+            if attributes.skip && attributes.with.is_some() {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "a skipped field is not encoded, so it has no use for `with`",
+                ));
+            }
+            let (member, binding) = match &field.ident {
+                Some(name) => (syn::Member::Named(name.clone()), name.clone()),
+                None => (
+                    syn::Member::Unnamed(index.into()),
+                    format_ident!("__field{}", index),
+                ),
+            };
+            fields.push(RecordField {
+                member,
+                binding,
+                ty: field.ty.clone(),
+                attributes,
+            });
+        }
+        Ok(Record {
+            ident,
+            generics,
+            fields,
+            shape: data.fields,
+        })
+    }
+    /// The record's generics, with every field type bound by `bound` when there are type parameters
+    /// for it to constrain.
+    fn generics_with(&self, bound: TokenStream, default: bool) -> Generics {
+        let mut generics = self.generics.clone();
+        if generics.type_params().next().is_none() {
+            return generics;
+        }
+        let where_clause = generics.make_where_clause();
+        for field in &self.fields {
+            let ty = &field.ty;
+            if field.attributes.skip {
+                if default {
+                    where_clause
+                        .predicates
+                        .push(syn::parse_quote!(#ty: ::core::default::Default));
+                }
+            } else if field.attributes.with.is_none() {
+                where_clause.predicates.push(syn::parse_quote!(#ty: #bound));
+            }
+        }
+        generics
+    }
+    fn construct(&self) -> TokenStream {
+        let bindings = self.fields.iter().map(|field| &field.binding);
+        match &self.shape {
+            Fields::Named(_) => quote! { Self { #(#bindings),* } },
+            Fields::Unnamed(_) => quote! { Self(#(#bindings),*) },
+            Fields::Unit => quote! { Self },
+        }
+    }
+}
+/// `TuxIOType` and `WritableObjectType`, for `#[derive(TuxEncode)]`.
+pub fn expand_encode(input: DeriveInput) -> Result<TokenStream> {
+    let record = Record::parse(input)?;
+    let ident = &record.ident;
+    // Each impl asks of the fields only what it uses, so a record of fields that are merely
+    // readable still has the `TuxIOType` that `ReadableObjectType` needs.
+    let sized = record.generics_with(quote! { ::tux_io_encoding::TuxIOType }, false);
+    let (impl_generics, ty_generics, where_clause) = sized.split_for_impl();
+    let writable = record.generics_with(quote! { ::tux_io_encoding::WritableObjectType }, false);
+    let (write_impl_generics, _, write_where_clause) = writable.split_for_impl();
+    let encoded: Vec<&RecordField> = record
+        .fields
+        .iter()
+        .filter(|field| field.is_encoded())
+        .collect();
+
+    let sizes = encoded.iter().map(|field| field.size());
+    // A field written through `with` has no `const_size` to ask, so it leaves the record without one.
+    let const_size = if encoded.iter().all(|field| field.attributes.with.is_none()) {
+        let const_sizes = encoded.iter().map(|field| {
+            let member = &field.member;
+            quote! { + ::tux_io_encoding::TuxIOType::const_size(&self.#member)? }
+        });
+        quote! {
+            fn const_size(&self) -> ::core::option::Option<usize> {
+                ::core::option::Option::Some(0usize #(#const_sizes)*)
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let writes = encoded.iter().map(|field| field.write());
+    Ok(quote! {
+        impl #impl_generics ::tux_io_encoding::TuxIOType for #ident #ty_generics #where_clause {
+            #const_size
+            fn size(&self) -> usize {
+                0usize #(+ #sizes)*
+            }
+        }
+        impl #write_impl_generics ::tux_io_encoding::WritableObjectType for #ident #ty_generics #write_where_clause {
+            #[allow(unused_variables)]
+            fn write_to_writer<__W: ::std::io::Write>(
+                &self,
+                writer: &mut __W,
+            ) -> ::core::result::Result<(), ::tux_io_encoding::EncodingError> {
+                #(#writes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    })
+}
+/// `ReadableObjectType` and the invariant test helper, for `#[derive(TuxDecode)]`.
+pub fn expand_decode(input: DeriveInput) -> Result<TokenStream> {
+    let record = Record::parse(input)?;
+    let ident = &record.ident;
+    let generics = record.generics_with(quote! { ::tux_io_encoding::ReadableObjectType }, true);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    // The helper writes the value as well as reading it back.
+    let tested = record.generics_with(
+        quote! { ::tux_io_encoding::ReadableObjectType + ::tux_io_encoding::WritableObjectType },
+        true,
+    );
+    let (test_impl_generics, _, test_where_clause) = tested.split_for_impl();
+
+    let read_sizes = record
+        .fields
+        .iter()
+        .filter(|field| field.is_encoded())
+        .map(|field| field.read_size());
+    let reads = record.fields.iter().map(|field| field.read());
+    let construct = record.construct();
+    Ok(quote! {
+        impl #impl_generics ::tux_io_encoding::ReadableObjectType for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn read_size<__R: ::std::io::Read + ::std::io::Seek>(
+                reader: &mut __R,
+            ) -> ::core::result::Result<usize, ::tux_io_encoding::EncodingError> {
+                let start = ::std::io::Seek::stream_position(reader)?;
+                #[allow(unused_mut)]
+                let mut size = 0usize;
+                #(#read_sizes)*
+                ::core::result::Result::Ok(size)
+            }
+            #[allow(unused_variables)]
+            fn read_from_reader<__R: ::std::io::Read>(
+                reader: &mut __R,
+            ) -> ::core::result::Result<Self, ::tux_io_encoding::EncodingError> {
+                #(#reads)*
+                ::core::result::Result::Ok(#construct)
+            }
+        }
+        impl #test_impl_generics #ident #ty_generics #test_where_clause {
+            /// Panics unless this value's `size()`, the bytes it writes, the `read_size()` of those
+            /// bytes, its `const_size()` when it has one, and what decoding them consumes are all
+            /// one number. Generated by `#[derive(TuxDecode)]` for tests.
+            #[cfg(test)]
+            #[allow(dead_code)]
+            #[track_caller]
+            pub(crate) fn assert_encoding_invariants(&self) {
+                let name = ::core::any::type_name::<Self>();
+                let size = ::tux_io_encoding::TuxIOType::size(self);
+                let encoded = ::tux_io_encoding::WritableObjectType::write_to_bytes(self)
+                    .unwrap_or_else(|err| panic!("{name} failed to encode: {err}"));
+                assert_eq!(encoded.len(), size, "{name}: size() disagrees with the bytes written");
+
+                let measured = <Self as ::tux_io_encoding::ReadableObjectType>::read_size(
+                    &mut ::std::io::Cursor::new(&encoded),
+                )
+                .unwrap_or_else(|err| panic!("{name} failed to measure: {err}"));
+                assert_eq!(measured, size, "{name}: read_size() disagrees with size()");
+
+                if let ::core::option::Option::Some(const_size) =
+                    ::tux_io_encoding::TuxIOType::const_size(self)
+                {
+                    assert_eq!(const_size, size, "{name}: const_size() disagrees with size()");
+                }
+
+                let mut reader = ::std::io::Cursor::new(&encoded);
+                <Self as ::tux_io_encoding::ReadableObjectType>::read_from_reader(&mut reader)
+                    .unwrap_or_else(|err| panic!("{name} failed to decode: {err}"));
+                assert_eq!(
+                    reader.position() as usize,
+                    size,
+                    "{name}: decoding consumed a different number of bytes than size()"
+                );
+            }
+        }
+    })
+}
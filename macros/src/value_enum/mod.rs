@@ -1,6 +1,35 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{DeriveInput, Fields, Result, Variant};
+use syn::{DeriveInput, Fields, Ident, Result, Variant, parse::Parse};
+mod keywords {
+    use syn::custom_keyword;
+    custom_keyword!(crate_value_type);
+}
+/// `#[value_enum(...)]` on the enum.
+#[derive(Default)]
+struct ValueEnumAttributes {
+    /// The enum is `tux_io_encoding::ValueType` itself, which has no conversions with itself.
+    crate_value_type: bool,
+}
+impl Parse for ValueEnumAttributes {
+    fn parse(input: syn::parse::ParseStream) -> Result<Self> {
+        let mut attributes = ValueEnumAttributes::default();
+        while !input.is_empty() {
+            if input.peek(keywords::crate_value_type) {
+                input.parse::<keywords::crate_value_type>()?;
+                attributes.crate_value_type = true;
+            } else {
+                return Err(input.error("Expected crate_value_type attribute"));
+            }
+            if input.peek(syn::Token![,]) {
+                input.parse::<syn::Token![,]>()?;
+            } else {
+                break;
+            }
+        }
+        Ok(attributes)
+    }
+}
 pub struct ValueVariant {
     pub variant: Variant,
 }
@@ -30,6 +59,11 @@ impl ValueVariant {
             unimplemented!("ValueEnum variants must have exactly one unnamed field");
         }
     }
+    /// The inner type's `TYPE_KEY`, as an expression.
+    fn type_key(&self) -> TokenStream {
+        let inner_type = self.inner_type();
+        quote! { <#inner_type as ::tux_io_encoding::ConstTypedObjectType>::TYPE_KEY }
+    }
     /// `const_size` for one variant, including the type-key byte.
     ///
     /// `+ 1` for the same reason as [ValueVariant::size]: the encoding of a value is its type key
@@ -37,7 +71,7 @@ impl ValueVariant {
     pub fn const_size(&self) -> TokenStream {
         let ident = &self.variant.ident;
         quote! {
-            Self::#ident(v) => v.const_size().map(|size| size + 1),
+            Self::#ident(v) => ::tux_io_encoding::TuxIOType::const_size(v).map(|size| size + 1),
         }
     }
 
@@ -49,73 +83,141 @@ impl ValueVariant {
     pub fn size(&self) -> TokenStream {
         let ident = &self.variant.ident;
         quote! {
-            Self::#ident(v) => v.size() + 1,
+            Self::#ident(v) => ::tux_io_encoding::TuxIOType::size(v) + 1,
         }
     }
     #[allow(clippy::wrong_self_convention)]
-    pub fn from_impl(&self) -> TokenStream {
+    pub fn from_impl(&self, enum_ident: &Ident) -> TokenStream {
         let inner_type = self.inner_type();
         let ident = &self.variant.ident;
 
         quote! {
-            impl core::convert::From<#inner_type> for ValueType {
+            impl ::core::convert::From<#inner_type> for #enum_ident {
                 fn from(value: #inner_type) -> Self {
-                    ValueType::#ident(value)
+                    #enum_ident::#ident(value)
                 }
             }
         }
     }
     #[allow(clippy::wrong_self_convention)]
-    pub fn into_option(&self) -> TokenStream {
+    pub fn into_option(&self, enum_ident: &Ident) -> TokenStream {
         let inner_type = self.inner_type();
         let ident = &self.variant.ident;
 
         quote! {
-            impl core::convert::From<ValueType> for core::option::Option<#inner_type> {
-                fn from(value: ValueType) -> Self {
+            impl ::core::convert::From<#enum_ident> for ::core::option::Option<#inner_type> {
+                fn from(value: #enum_ident) -> Self {
                     match value {
-                        ValueType::#ident(v) => core::option::Option::Some(v),
-                        _ => core::option::Option::None,
+                        #enum_ident::#ident(v) => ::core::option::Option::Some(v),
+                        #[allow(unreachable_patterns)]
+                        _ => ::core::option::Option::None,
                     }
                 }
             }
         }
     }
+    pub fn type_key_arm(&self) -> TokenStream {
+        let ident = &self.variant.ident;
+        let type_key = self.type_key();
+        quote! {
+            Self::#ident(_) => #type_key,
+        }
+    }
     pub fn read_from_reader(&self) -> TokenStream {
         let inner_type = self.inner_type();
         let ident = &self.variant.ident;
+        let type_key = self.type_key();
 
         quote! {
-            <#inner_type as ConstTypedObjectType>::TYPE_KEY => {
-                let value = <#inner_type as ReadableObjectType>::read_from_reader(reader)?;
-                Ok(ValueType::#ident(value))
+            #type_key => {
+                let value =
+                    <#inner_type as ::tux_io_encoding::ReadableObjectType>::read_from_reader(reader)?;
+                ::core::result::Result::Ok(Self::#ident(value))
             }
         }
     }
     pub fn read_size(&self) -> TokenStream {
         let inner_type = self.inner_type();
+        let type_key = self.type_key();
 
         quote! {
-            <#inner_type as ConstTypedObjectType>::TYPE_KEY => {
-                let value = <#inner_type as ReadableObjectType>::read_size(reader)?;
-                Ok(value + 1)
+            #type_key => {
+                let value =
+                    <#inner_type as ::tux_io_encoding::ReadableObjectType>::read_size(reader)?;
+                ::core::result::Result::Ok(value + 1)
             }
         }
     }
     pub fn write_to_writer(&self) -> TokenStream {
-        let inner_type = self.inner_type();
         let ident = &self.variant.ident;
+        let type_key = self.type_key();
 
         quote! {
-            ValueType::#ident(v) => {
-                writer.write_all(&[<#inner_type as ConstTypedObjectType>::TYPE_KEY])?;
-                v.write_to_writer(writer)
+            Self::#ident(v) => {
+                writer.write_all(&[#type_key])?;
+                ::tux_io_encoding::WritableObjectType::write_to_writer(v, writer)
             }
         }
     }
+    /// The arm of `From<Self> for ValueType` for this variant.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn into_value_type(&self, enum_ident: &Ident) -> TokenStream {
+        let ident = &self.variant.ident;
+        quote! {
+            #enum_ident::#ident(v) => ::tux_io_encoding::ValueType::from(v),
+        }
+    }
+    /// The arm of `TryFrom<ValueType> for Self` for this variant.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_value_type(&self) -> TokenStream {
+        let ident = &self.variant.ident;
+        let inner_type = self.inner_type();
+        let type_key = self.type_key();
+        quote! {
+            #type_key => match ::core::option::Option::<#inner_type>::from(value) {
+                ::core::option::Option::Some(v) => ::core::result::Result::Ok(Self::#ident(v)),
+                ::core::option::Option::None => {
+                    unreachable!("a ValueType holds the one type its type key names")
+                }
+            },
+        }
+    }
+}
+/// A compile-time assertion that no two variants' inner types share a `TYPE_KEY`.
+///
+/// The reader matches on the key, so the second of two variants sharing one would never be read
+/// back — a value written as it would come back as the first.
+fn distinct_type_keys(variants: &[ValueVariant]) -> TokenStream {
+    let mut assertions = Vec::new();
+    for (index, first) in variants.iter().enumerate() {
+        for second in &variants[index + 1..] {
+            let message = format!(
+                "ValueEnum variants `{}` and `{}` share a type key, so `{}` could never be read back",
+                first.variant.ident, second.variant.ident, second.variant.ident
+            );
+            let (first_key, second_key) = (first.type_key(), second.type_key());
+            assertions.push(quote! {
+                assert!(#first_key != #second_key, #message);
+            });
+        }
+    }
+    quote! {
+        const _: () = {
+            #(#assertions)*
+        };
+    }
 }
 pub fn expand(input: DeriveInput) -> Result<TokenStream> {
-    let DeriveInput { ident, data, .. } = input;
+    let DeriveInput {
+        ident, attrs, data, ..
+    } = input;
+    let mut attributes = ValueEnumAttributes::default();
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("value_enum"))
+    {
+        attributes.crate_value_type |= attr.parse_args::<ValueEnumAttributes>()?.crate_value_type;
+    }
 
     let variants = match data {
         syn::Data::Enum(data_enum) => data_enum
@@ -132,6 +234,10 @@ pub fn expand(input: DeriveInput) -> Result<TokenStream> {
     };
     let const_size_variants = variants.iter().map(|v| v.const_size()).collect::<Vec<_>>();
     let size_variants = variants.iter().map(|v| v.size()).collect::<Vec<_>>();
+    let type_key_variants = variants
+        .iter()
+        .map(|v| v.type_key_arm())
+        .collect::<Vec<_>>();
     let read_from_reader_variants = variants
         .iter()
         .map(|v| v.read_from_reader())
@@ -142,14 +248,60 @@ pub fn expand(input: DeriveInput) -> Result<TokenStream> {
         .collect::<Vec<_>>();
     let read_size_variants = variants.iter().map(|v| v.read_size()).collect::<Vec<_>>();
 
-    let from_impl = variants.iter().map(|v| v.from_impl()).collect::<Vec<_>>();
-    let into_option_impl = variants.iter().map(|v| v.into_option()).collect::<Vec<_>>();
+    let from_impl = variants
+        .iter()
+        .map(|v| v.from_impl(&ident))
+        .collect::<Vec<_>>();
+    let into_option_impl = variants
+        .iter()
+        .map(|v| v.into_option(&ident))
+        .collect::<Vec<_>>();
+    let distinct_type_keys = distinct_type_keys(&variants);
+
+    // The crate's own `ValueType` would be converting to and from itself.
+    let value_type_conversions = if attributes.crate_value_type {
+        quote! {}
+    } else {
+        let into_value_type = variants.iter().map(|v| v.into_value_type(&ident));
+        let from_value_type = variants.iter().map(|v| v.from_value_type());
+        quote! {
+            impl ::core::convert::From<#ident> for ::tux_io_encoding::ValueType {
+                fn from(value: #ident) -> Self {
+                    match value {
+                        #(#into_value_type)*
+                    }
+                }
+            }
+            impl ::core::convert::TryFrom<::tux_io_encoding::ValueType> for #ident {
+                /// The value, handed back, when it is of a type this enum has no variant for.
+                type Error = ::tux_io_encoding::ValueType;
+                fn try_from(
+                    value: ::tux_io_encoding::ValueType,
+                ) -> ::core::result::Result<Self, Self::Error> {
+                    match value.type_key() {
+                        #(#from_value_type)*
+                        _ => ::core::result::Result::Err(value),
+                    }
+                }
+            }
+        }
+    };
     let expanded = quote! {
         #(#from_impl)*
         #(#into_option_impl)*
+        #distinct_type_keys
+        #value_type_conversions
 
-        impl TuxIOType for ValueType {
-            fn const_size(&self) -> Option<usize> {
+        impl #ident {
+            /// The type key this value is written with, ahead of the value itself.
+            pub fn type_key(&self) -> u8 {
+                match self {
+                    #(#type_key_variants)*
+                }
+            }
+        }
+        impl ::tux_io_encoding::TuxIOType for #ident {
+            fn const_size(&self) -> ::core::option::Option<usize> {
                 match self {
                     #(#const_size_variants)*
                 }
@@ -160,27 +312,38 @@ pub fn expand(input: DeriveInput) -> Result<TokenStream> {
                 }
             }
         }
-        impl ReadableObjectType for ValueType {
-            fn read_size<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<usize, EncodingError> {
-                let type_key = u8::read_from_reader(reader)?;
+        impl ::tux_io_encoding::ReadableObjectType for #ident {
+            fn read_size<R: ::std::io::Read + ::std::io::Seek>(
+                reader: &mut R,
+            ) -> ::core::result::Result<usize, ::tux_io_encoding::EncodingError> {
+                let type_key = <u8 as ::tux_io_encoding::ReadableObjectType>::read_from_reader(reader)?;
                 match type_key {
                     #(#read_size_variants)*
-                    _ => Err(EncodingError::UnknownTypeKey(type_key)),
+                    _ => ::core::result::Result::Err(
+                        ::tux_io_encoding::EncodingError::UnknownTypeKey(type_key),
+                    ),
                 }
             }
-            fn read_from_reader<R: std::io::Read>(reader: &mut R) -> Result<Self, EncodingError>
+            fn read_from_reader<R: ::std::io::Read>(
+                reader: &mut R,
+            ) -> ::core::result::Result<Self, ::tux_io_encoding::EncodingError>
             where
                 Self: Sized,
             {
-                let type_key = u8::read_from_reader(reader)?;
+                let type_key = <u8 as ::tux_io_encoding::ReadableObjectType>::read_from_reader(reader)?;
                 match type_key {
                     #(#read_from_reader_variants)*
-                    _ => Err(EncodingError::UnknownTypeKey(type_key)),
+                    _ => ::core::result::Result::Err(
+                        ::tux_io_encoding::EncodingError::UnknownTypeKey(type_key),
+                    ),
                 }
             }
         }
-        impl WritableObjectType for ValueType {
-            fn write_to_writer<W: std::io::Write>(&self, writer: &mut W) -> Result<(), EncodingError> {
+        impl ::tux_io_encoding::WritableObjectType for #ident {
+            fn write_to_writer<W: ::std::io::Write>(
+                &self,
+                writer: &mut W,
+            ) -> ::core::result::Result<(), ::tux_io_encoding::EncodingError> {
                 match self {
                     #(#write_to_writer_variants)*
                 }
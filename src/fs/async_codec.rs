@@ -0,0 +1,349 @@
+//! Streaming compression for the async object layer.
+//!
+//! The counterparts of [crate::fs::ContentEncoder] and [crate::fs::DecodedContentReader], built on
+//! `async-compression` so compressed content streams under tokio in both directions instead of being
+//! buffered whole and handed to a blocking thread.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{
+    CompressionTypes,
+    fs::{
+        AsyncContentReader, AsyncObjectWriter, AsyncOwnedContentReader, ObjectFileError,
+        ObjectFileResult, compression::judge_sample, ensure_supported, uncompressed_length_key,
+    },
+};
+
+/// Compresses content on its way into an [AsyncObjectWriter].
+///
+/// Obtained from [AsyncObjectWriter::content_encoder]. As with the blocking encoder it is a
+/// pass-through for an uncompressed object, and [AsyncContentEncoder::finish] must be called to flush
+/// the codec — dropping it loses the tail of the content.
+pub struct AsyncContentEncoder<'writer> {
+    codec: AsyncCodec<'writer>,
+    uncompressed_length: u64,
+    /// The sample a [crate::fs::CompressionPolicy::Auto] decision was made on, still to be fed through
+    /// the codec it decided on. Drained before any further content is accepted.
+    pending: Vec<u8>,
+    pending_written: usize,
+}
+
+enum AsyncCodec<'writer> {
+    /// Collecting the sample that decides whether to keep the codec.
+    Sampling {
+        /// Only `None` for the moment the encoder is switching over to its decided codec.
+        writer: Option<&'writer mut AsyncObjectWriter>,
+        compression: CompressionTypes,
+        sample: Vec<u8>,
+        sample_bytes: usize,
+        min_ratio: f64,
+    },
+    Stored(&'writer mut AsyncObjectWriter),
+    #[cfg(feature = "zstd")]
+    Zstd(Box<async_compression::tokio::write::ZstdEncoder<&'writer mut AsyncObjectWriter>>),
+    #[cfg(feature = "gzip")]
+    Gzip(Box<async_compression::tokio::write::GzipEncoder<&'writer mut AsyncObjectWriter>>),
+}
+
+impl<'writer> AsyncCodec<'writer> {
+    fn new(
+        writer: &'writer mut AsyncObjectWriter,
+        compression: CompressionTypes,
+    ) -> ObjectFileResult<Self> {
+        ensure_supported(compression)?;
+        #[cfg(feature = "zstd")]
+        let workers = writer.compression_workers();
+        match compression {
+            CompressionTypes::None(_) => Ok(AsyncCodec::Stored(writer)),
+            #[cfg(feature = "zstd")]
+            CompressionTypes::ZSTD(level) => {
+                let level = async_compression::Level::Precise(level.0);
+                let encoder = if workers > 1 {
+                    async_compression::tokio::write::ZstdEncoder::with_quality_and_params(
+                        writer,
+                        level,
+                        &[async_compression::zstd::CParameter::nb_workers(workers)],
+                    )
+                } else {
+                    async_compression::tokio::write::ZstdEncoder::with_quality(writer, level)
+                };
+                Ok(AsyncCodec::Zstd(Box::new(encoder)))
+            }
+            // Left single-threaded: compressing a batch of blocks side by side would block the
+            // runtime for as long as the batch takes.
+            #[cfg(feature = "gzip")]
+            CompressionTypes::Gzip(level) => Ok(AsyncCodec::Gzip(Box::new(
+                async_compression::tokio::write::GzipEncoder::with_quality(
+                    writer,
+                    async_compression::Level::Precise(i32::try_from(level.0).unwrap_or(i32::MAX)),
+                ),
+            ))),
+            #[allow(unreachable_patterns)]
+            other => Err(ObjectFileError::UnsupportedCompression(other)),
+        }
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self {
+            AsyncCodec::Sampling { .. } => unreachable!("sampling is handled by the encoder"),
+            AsyncCodec::Stored(writer) => Pin::new(&mut **writer).poll_write(cx, buf),
+            #[cfg(feature = "zstd")]
+            AsyncCodec::Zstd(encoder) => {
+                let written = ready!(Pin::new(&mut **encoder).poll_write(cx, buf))?;
+                encoder.get_mut().sniff(&buf[..written]);
+                Poll::Ready(Ok(written))
+            }
+            #[cfg(feature = "gzip")]
+            AsyncCodec::Gzip(encoder) => {
+                let written = ready!(Pin::new(&mut **encoder).poll_write(cx, buf))?;
+                encoder.get_mut().sniff(&buf[..written]);
+                Poll::Ready(Ok(written))
+            }
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self {
+            // Nothing has reached the writer yet, and flushing must not force an early decision.
+            AsyncCodec::Sampling { .. } => Poll::Ready(Ok(())),
+            AsyncCodec::Stored(writer) => Pin::new(&mut **writer).poll_flush(cx),
+            #[cfg(feature = "zstd")]
+            AsyncCodec::Zstd(encoder) => Pin::new(&mut **encoder).poll_flush(cx),
+            #[cfg(feature = "gzip")]
+            AsyncCodec::Gzip(encoder) => Pin::new(&mut **encoder).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self {
+            AsyncCodec::Sampling { .. } => unreachable!("decided before shutting down"),
+            AsyncCodec::Stored(writer) => Pin::new(&mut **writer).poll_shutdown(cx),
+            #[cfg(feature = "zstd")]
+            AsyncCodec::Zstd(encoder) => Pin::new(&mut **encoder).poll_shutdown(cx),
+            #[cfg(feature = "gzip")]
+            AsyncCodec::Gzip(encoder) => Pin::new(&mut **encoder).poll_shutdown(cx),
+        }
+    }
+}
+
+impl<'writer> AsyncContentEncoder<'writer> {
+    pub(crate) fn new(
+        writer: &'writer mut AsyncObjectWriter,
+        compression: CompressionTypes,
+    ) -> ObjectFileResult<Self> {
+        Ok(Self::from_codec(AsyncCodec::new(writer, compression)?))
+    }
+
+    /// Buffers the start of the content until there is enough of it to judge the codec by.
+    pub(crate) fn sampling(
+        writer: &'writer mut AsyncObjectWriter,
+        compression: CompressionTypes,
+        min_ratio: f64,
+        sample_bytes: usize,
+    ) -> ObjectFileResult<Self> {
+        ensure_supported(compression)?;
+        Ok(Self::from_codec(AsyncCodec::Sampling {
+            writer: Some(writer),
+            compression,
+            sample: Vec::with_capacity(sample_bytes),
+            sample_bytes,
+            min_ratio,
+        }))
+    }
+
+    fn from_codec(codec: AsyncCodec<'writer>) -> Self {
+        Self {
+            codec,
+            uncompressed_length: 0,
+            pending: Vec::new(),
+            pending_written: 0,
+        }
+    }
+
+    /// Content bytes fed in so far, before compression.
+    pub fn uncompressed_length(&self) -> u64 {
+        self.uncompressed_length
+    }
+
+    /// Judges the codec by the sample and switches to it. The sample is left in `pending`, to go
+    /// through the chosen codec ahead of anything written after it.
+    fn decide(&mut self) -> ObjectFileResult<()> {
+        let AsyncCodec::Sampling {
+            writer,
+            compression,
+            sample,
+            min_ratio,
+            ..
+        } = &mut self.codec
+        else {
+            return Ok(());
+        };
+        let writer = writer
+            .take()
+            .expect("a sampling encoder always holds its writer");
+        let sample = std::mem::take(sample);
+
+        let compression = judge_sample(*compression, &sample, *min_ratio)?;
+        writer.commit_compression(compression);
+        self.codec = AsyncCodec::new(writer, compression)?;
+        self.pending = sample;
+        self.pending_written = 0;
+        Ok(())
+    }
+
+    /// Feeds the decided-on sample through the codec.
+    fn poll_drain_pending(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.pending_written < self.pending.len() {
+            let written = ready!(
+                self.codec
+                    .poll_write(cx, &self.pending[self.pending_written..])
+            )?;
+            if written == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pending_written += written;
+        }
+        self.pending = Vec::new();
+        self.pending_written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Flushes the codec and records the uncompressed length in the object's metadata.
+    ///
+    /// Returns the uncompressed content length.
+    pub async fn finish(mut self) -> ObjectFileResult<u64> {
+        // Content shorter than the sample never filled it; `shutdown` makes the decision.
+        self.shutdown().await?;
+        let uncompressed_length = self.uncompressed_length;
+        let writer = match self.codec {
+            AsyncCodec::Sampling { .. } => unreachable!("decided by the shutdown above"),
+            AsyncCodec::Stored(writer) => writer,
+            #[cfg(feature = "zstd")]
+            AsyncCodec::Zstd(encoder) => encoder.into_inner(),
+            #[cfg(feature = "gzip")]
+            AsyncCodec::Gzip(encoder) => encoder.into_inner(),
+        };
+        // Only worth recording when the stored length differs from the real one.
+        if writer.content_length() != uncompressed_length {
+            writer
+                .metadata_mut()
+                .insert(uncompressed_length_key(), uncompressed_length.into());
+        }
+        Ok(uncompressed_length)
+    }
+}
+
+impl AsyncWrite for AsyncContentEncoder<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_pending(cx))?;
+
+        if let AsyncCodec::Sampling {
+            sample,
+            sample_bytes,
+            ..
+        } = &mut this.codec
+        {
+            // Take no more than fills the sample; the caller comes back with the rest, which then
+            // goes to whichever codec the sample decided on.
+            let taken = sample_bytes.saturating_sub(sample.len()).min(buf.len());
+            sample.extend_from_slice(&buf[..taken]);
+            let full = sample.len() >= *sample_bytes;
+            this.uncompressed_length += taken as u64;
+            if full {
+                this.decide().map_err(std::io::Error::other)?;
+            }
+            return Poll::Ready(Ok(taken));
+        }
+
+        let written = ready!(this.codec.poll_write(cx, buf))?;
+        this.uncompressed_length += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_pending(cx))?;
+        this.codec.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if matches!(this.codec, AsyncCodec::Sampling { .. }) {
+            this.decide().map_err(std::io::Error::other)?;
+        }
+        ready!(this.poll_drain_pending(cx))?;
+        this.codec.poll_shutdown(cx)
+    }
+}
+
+/// Streams a content section through a decompressor.
+///
+/// Generic over the section it reads, like [crate::fs::ContentSection], so the borrowing and owning
+/// readers share one implementation. For an uncompressed object it is a thin pass-through.
+pub enum DecodedSection<S> {
+    Stored(S),
+    #[cfg(feature = "zstd")]
+    Zstd(Box<async_compression::tokio::bufread::ZstdDecoder<tokio::io::BufReader<S>>>),
+    #[cfg(feature = "gzip")]
+    Gzip(Box<async_compression::tokio::bufread::GzipDecoder<tokio::io::BufReader<S>>>),
+}
+
+/// A decompressing reader borrowing the object it reads from.
+pub type AsyncDecodedContentReader<'object> = DecodedSection<AsyncContentReader<'object>>;
+/// A decompressing reader that owns its file, for a body that outlives the object handle.
+pub type AsyncOwnedDecodedContentReader = DecodedSection<AsyncOwnedContentReader>;
+
+impl<S: AsyncRead + Unpin> DecodedSection<S> {
+    pub(crate) fn new(section: S, compression: CompressionTypes) -> ObjectFileResult<Self> {
+        ensure_supported(compression)?;
+        match compression {
+            CompressionTypes::None(_) => Ok(DecodedSection::Stored(section)),
+            #[cfg(feature = "zstd")]
+            CompressionTypes::ZSTD(_) => {
+                let mut decoder = async_compression::tokio::bufread::ZstdDecoder::new(
+                    tokio::io::BufReader::new(section),
+                );
+                // The blocking decoder reads on through concatenated frames, so this one must too.
+                decoder.multiple_members(true);
+                Ok(DecodedSection::Zstd(Box::new(decoder)))
+            }
+            #[cfg(feature = "gzip")]
+            CompressionTypes::Gzip(_) => {
+                let mut decoder = async_compression::tokio::bufread::GzipDecoder::new(
+                    tokio::io::BufReader::new(section),
+                );
+                // Parallel gzip writes one member per block.
+                decoder.multiple_members(true);
+                Ok(DecodedSection::Gzip(Box::new(decoder)))
+            }
+            #[allow(unreachable_patterns)]
+            other => Err(ObjectFileError::UnsupportedCompression(other)),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DecodedSection<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            DecodedSection::Stored(reader) => Pin::new(reader).poll_read(cx, buf),
+            #[cfg(feature = "zstd")]
+            DecodedSection::Zstd(reader) => Pin::new(&mut **reader).poll_read(cx, buf),
+            #[cfg(feature = "gzip")]
+            DecodedSection::Gzip(reader) => Pin::new(&mut **reader).poll_read(cx, buf),
+        }
+    }
+}
@@ -2,7 +2,8 @@
 //!
 //! The header, metadata and tag sections are small and are read in one shot into a buffer, then
 //! decoded with the crate's ordinary synchronous codecs — there is nothing to gain from decoding
-//! those incrementally. Only the content is genuinely streamed.
+//! those incrementally. Only the content is genuinely streamed, through the async codecs when the
+//! object is compressed.
 //!
 //! ```no_run
 //! use tokio::io::AsyncWriteExt;
@@ -23,21 +24,78 @@ use std::{
     path::{Path, PathBuf},
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use tokio::{
     fs::{File, OpenOptions},
-    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf},
 };
 
 use crate::{
     CompressionTypes, MetadataMap, ObjectHeader, ReadableObjectType, Tags, TuxIOType, ValueType,
     fs::{
-        HEADER_SIZE, LayoutOptions, ObjectFileError, ObjectFileResult, SectionLayout,
-        ensure_supported, writer::encode_prefix,
+        AsyncContentEncoder, AsyncDecodedContentReader, AsyncOwnedDecodedContentReader,
+        CompressionPolicy, DecodedSection, HEADER_SIZE, LayoutOptions, LockMode, ObjectFileError,
+        ObjectFileResult, SectionLayout, carry_encryption_metadata, ensure_supported,
+        lock::{LockWait, MAX_POLL_INTERVAL, lock_file, same_file},
+        reader::content_seek_target,
+        sniff::ContentSniffer,
+        versions::{
+            is_kept_version, keep_published, stamp_version_id, stamped_version_id, versions_dir,
+        },
+        writer::{Precondition, encode_prefix, generation_under_lock, next_generation},
     },
 };
 
+/// See [crate::fs::versions::is_versioned].
+async fn is_versioned(path: &Path) -> bool {
+    tokio::fs::metadata(versions_dir(path))
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// See [crate::fs::writer::current_generation].
+async fn current_generation(path: &Path) -> ObjectFileResult<Option<u64>> {
+    let mut file = match File::open(path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut header = [0u8; HEADER_SIZE];
+    file.read_exact(&mut header).await?;
+    Ok(Some(
+        <ObjectHeader as ReadableObjectType>::read_from_bytes(&header)?.generation,
+    ))
+}
+
+/// Locks `file`, as [lock_file] does, without tying up a thread: each attempt is a non-blocking one,
+/// with tokio's timer between them. Dropping the future stops the wait, so a cancelled call never
+/// goes on to take a lock nothing records.
+async fn lock_async(file: &File, mode: LockMode, wait: LockWait) -> std::io::Result<bool> {
+    // Shares the open file description, so the lock lands on `file` too.
+    let file = file.try_clone().await?.into_std().await;
+    let mut interval = Duration::from_millis(1);
+    loop {
+        if lock_file(&file, mode, LockWait::Never)? {
+            return Ok(true);
+        }
+        let pause = match wait {
+            LockWait::Never => return Ok(false),
+            LockWait::Forever => interval,
+            LockWait::Until(deadline) => match deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+            {
+                Some(remaining) => interval.min(remaining),
+                None => return Ok(false),
+            },
+        };
+        tokio::time::sleep(pause).await;
+        interval = (interval * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
 /// The async counterpart of [crate::fs::TuxObject].
 #[derive(Debug)]
 pub struct AsyncTuxObject {
@@ -46,6 +104,8 @@ pub struct AsyncTuxObject {
     header: ObjectHeader,
     metadata: MetadataMap,
     writable: bool,
+    /// The advisory lock this handle holds, if any.
+    lock: Option<LockMode>,
 }
 
 impl AsyncTuxObject {
@@ -85,6 +145,19 @@ impl AsyncTuxObject {
     }
 
     async fn from_file(mut file: File, path: PathBuf, writable: bool) -> ObjectFileResult<Self> {
+        let (header, metadata) = Self::read_prefix(&mut file).await?;
+        Ok(Self {
+            file,
+            path,
+            header,
+            metadata,
+            writable,
+            lock: None,
+        })
+    }
+
+    /// Reads the header and the metadata section.
+    async fn read_prefix(file: &mut File) -> ObjectFileResult<(ObjectHeader, MetadataMap)> {
         file.rewind().await?;
 
         let mut header_bytes = [0u8; HEADER_SIZE];
@@ -99,19 +172,16 @@ impl AsyncTuxObject {
             file.read_exact(&mut buffer).await?;
             MetadataMap::read_from_reader(&mut Cursor::new(&buffer))?
         };
-
-        Ok(Self {
-            file,
-            path,
-            header,
-            metadata,
-            writable,
-        })
+        Ok((header, metadata))
     }
 
     pub fn header(&self) -> &ObjectHeader {
         &self.header
     }
+    /// See [ObjectHeader::generation].
+    pub fn generation(&self) -> u64 {
+        self.header.generation
+    }
     pub fn path(&self) -> &Path {
         &self.path
     }
@@ -137,6 +207,77 @@ impl AsyncTuxObject {
     pub async fn file_size(&self) -> ObjectFileResult<u64> {
         Ok(self.file.metadata().await?.len())
     }
+    /// Drops the file handle, keeping what was read when the object was opened.
+    pub fn into_head(self) -> crate::fs::store::ObjectHead {
+        crate::fs::store::ObjectHead {
+            header: self.header,
+            metadata: self.metadata,
+        }
+    }
+
+    // -- locking -------------------------------------------------------------------------------
+
+    /// Takes an advisory lock on the object. See [crate::fs::TuxObject::lock]. The lock is polled
+    /// for rather than waited on in a thread, so cancelling the call gives up the attempt.
+    pub async fn lock(&mut self, mode: LockMode) -> ObjectFileResult<()> {
+        self.acquire_lock(mode, LockWait::Forever).await?;
+        Ok(())
+    }
+
+    /// See [crate::fs::TuxObject::try_lock].
+    pub async fn try_lock(&mut self, mode: LockMode) -> ObjectFileResult<bool> {
+        self.acquire_lock(mode, LockWait::Never).await
+    }
+
+    /// See [crate::fs::TuxObject::lock_timeout].
+    pub async fn lock_timeout(
+        &mut self,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> ObjectFileResult<bool> {
+        self.acquire_lock(mode, LockWait::timeout(timeout)).await
+    }
+
+    pub async fn release_lock(&mut self) -> ObjectFileResult<()> {
+        if self.lock.take().is_some() {
+            let file = self.file.try_clone().await?.into_std().await;
+            file.unlock()?;
+        }
+        Ok(())
+    }
+
+    pub fn lock_mode(&self) -> Option<LockMode> {
+        self.lock
+    }
+
+    async fn acquire_lock(&mut self, mode: LockMode, wait: LockWait) -> ObjectFileResult<bool> {
+        loop {
+            if !lock_async(&self.file, mode, wait).await? {
+                return Ok(false);
+            }
+            self.lock = Some(mode);
+            let current = match tokio::fs::metadata(&self.path).await {
+                Ok(current) => current,
+                Err(err) => {
+                    self.release_lock().await?;
+                    return Err(err.into());
+                }
+            };
+            if same_file(&self.file.metadata().await?, &current) {
+                let (header, metadata) = Self::read_prefix(&mut self.file).await?;
+                self.header = header;
+                self.metadata = metadata;
+                return Ok(true);
+            }
+            // Replaced while waiting; move to the new file, dropping the old one and its lock.
+            let file = OpenOptions::new()
+                .read(true)
+                .write(self.writable)
+                .open(&self.path)
+                .await?;
+            *self = Self::from_file(file, self.path.clone(), self.writable).await?;
+        }
+    }
 
     // -- tags ----------------------------------------------------------------------------------
 
@@ -193,6 +334,62 @@ impl AsyncTuxObject {
         self.rewrite(metadata, tags).await
     }
 
+    /// See [crate::fs::TuxObject::set_metadata_if].
+    pub async fn set_metadata_if<F>(
+        &mut self,
+        metadata: MetadataMap,
+        header_matches: F,
+    ) -> ObjectFileResult<bool>
+    where
+        F: FnOnce(&ObjectHeader, &MetadataMap) -> bool,
+    {
+        self.ensure_writable()?;
+        let held = self.lock;
+        self.lock(LockMode::Exclusive).await?;
+        let result = if header_matches(&self.header, &self.metadata) {
+            self.set_metadata(metadata).await.map(|()| true)
+        } else {
+            Ok(false)
+        };
+        // Back to the lock the caller held, whether or not the update went through.
+        let restored = self.restore_lock(held).await;
+        let updated = result?;
+        restored?;
+        Ok(updated)
+    }
+
+    /// See [crate::fs::TuxObject::set_sections_if_generation].
+    pub async fn set_sections_if_generation(
+        &mut self,
+        expected: u64,
+        metadata: MetadataMap,
+        tags: Tags,
+    ) -> ObjectFileResult<()> {
+        self.ensure_writable()?;
+        let held = self.lock;
+        self.lock(LockMode::Exclusive).await?;
+        let found = self.header.generation;
+        let result = if found == expected {
+            self.set_sections(metadata, tags).await
+        } else {
+            Err(ObjectFileError::PreconditionFailed {
+                expected: Some(expected),
+                found: Some(found),
+            })
+        };
+        let restored = self.restore_lock(held).await;
+        result.and(restored)
+    }
+
+    /// Goes back to the lock held before an update took an exclusive one.
+    async fn restore_lock(&mut self, held: Option<LockMode>) -> ObjectFileResult<()> {
+        match held {
+            None => self.release_lock().await,
+            Some(LockMode::Shared) => self.lock(LockMode::Shared).await,
+            Some(LockMode::Exclusive) => Ok(()),
+        }
+    }
+
     /// Replaces the tag section, keeping the metadata. Atomic.
     pub async fn set_tags(&mut self, tags: Tags) -> ObjectFileResult<()> {
         let metadata = self.metadata.clone();
@@ -210,13 +407,18 @@ impl AsyncTuxObject {
 
     /// Overwrites both sections without moving the content.
     ///
-    /// Cheap but not crash safe — see [crate::fs::TuxObject::set_sections_in_place].
+    /// Cheap but not crash safe, and refused for versioned objects — see
+    /// [crate::fs::TuxObject::set_sections_in_place].
     pub async fn set_sections_in_place(
         &mut self,
-        metadata: MetadataMap,
+        mut metadata: MetadataMap,
         tags: Tags,
     ) -> ObjectFileResult<()> {
         self.ensure_writable()?;
+        if is_kept_version(&self.path) || is_versioned(&self.path).await {
+            return Err(ObjectFileError::InPlaceUpdateOfVersionedObject);
+        }
+        carry_encryption_metadata(&self.metadata, &mut metadata);
 
         let metadata_size = metadata.size();
         let tags_size = tags.size();
@@ -234,6 +436,7 @@ impl AsyncTuxObject {
         let mut header = self.header.clone();
         header.tags_start = layout.tags_start;
         header.content_start = layout.content_start;
+        header.generation = header.generation.saturating_add(1);
 
         let prefix = encode_prefix(&header, &metadata, &tags, layout)?;
         self.file.rewind().await?;
@@ -245,8 +448,9 @@ impl AsyncTuxObject {
         Ok(())
     }
 
-    async fn rewrite(&mut self, metadata: MetadataMap, tags: Tags) -> ObjectFileResult<()> {
+    async fn rewrite(&mut self, mut metadata: MetadataMap, tags: Tags) -> ObjectFileResult<()> {
         self.ensure_writable()?;
+        carry_encryption_metadata(&self.metadata, &mut metadata);
 
         let options = crate::fs::CreateOptions {
             metadata,
@@ -256,17 +460,39 @@ impl AsyncTuxObject {
                 ..LayoutOptions::default()
             },
             compression: self.header.compression_type,
+            compression_policy: CompressionPolicy::Fixed,
+            compression_workers: 0,
+            #[cfg(feature = "encryption")]
+            encryption: None,
             sync: true,
+            versioned: false,
+            sniff_content_type: false,
         };
         let mut writer = AsyncObjectWriter::create(&self.path, options).await?;
+        // Encrypted content is carried across as ciphertext, which needs no key.
+        writer.bit_flags = self.header.bit_flags;
+        writer.lock = self.lock;
+        if self.lock.is_some() {
+            writer.locked_file = Some(self.file.try_clone().await?.into_std().await);
+        }
         {
             let mut reader = self.stored_content_reader().await?;
-            tokio::io::copy(&mut reader, &mut writer).await?;
+            writer.copy_stored_content(&mut reader).await?;
         }
         *self = writer.finish().await?;
         Ok(())
     }
 
+    /// The async layer does not decrypt, so an encrypted object's content is only available here in
+    /// its stored form.
+    fn ensure_readable(&self) -> ObjectFileResult<()> {
+        if self.header.is_encrypted() {
+            Err(ObjectFileError::ContentLocked)
+        } else {
+            Ok(())
+        }
+    }
+
     fn ensure_writable(&self) -> ObjectFileResult<()> {
         if self.writable {
             Ok(())
@@ -279,11 +505,14 @@ impl AsyncTuxObject {
 
     /// Streams the content exactly as stored.
     pub async fn stored_content_reader(&mut self) -> ObjectFileResult<AsyncContentReader<'_>> {
-        let remaining = self.seek_to_content_start().await?;
-        Ok(ContentSection {
-            file: &mut self.file,
-            remaining,
-        })
+        let (offset, length) = self.seek_to_content_start().await?;
+        let content_start = self.header.content_start as u64;
+        Ok(ContentSection::new(
+            &mut self.file,
+            content_start,
+            offset,
+            length,
+        ))
     }
 
     /// Streams a byte range of the stored content. Rejects compressed objects.
@@ -292,11 +521,14 @@ impl AsyncTuxObject {
         offset: u64,
         length: Option<u64>,
     ) -> ObjectFileResult<AsyncContentReader<'_>> {
-        let remaining = self.seek_within_content(offset, length).await?;
-        Ok(ContentSection {
-            file: &mut self.file,
-            remaining,
-        })
+        let length = self.seek_within_content(offset, length).await?;
+        let content_start = self.header.content_start as u64;
+        Ok(ContentSection::new(
+            &mut self.file,
+            content_start,
+            offset,
+            length,
+        ))
     }
 
     /// Consumes the object and streams its content exactly as stored.
@@ -306,11 +538,14 @@ impl AsyncTuxObject {
     /// something that owns its source — an HTTP response body, say, which outlives the handler that
     /// opened the object. This variant takes the file with it.
     pub async fn into_content_reader(mut self) -> ObjectFileResult<AsyncOwnedContentReader> {
-        let remaining = self.seek_to_content_start().await?;
-        Ok(ContentSection {
-            file: self.file,
-            remaining,
-        })
+        let (offset, length) = self.seek_to_content_start().await?;
+        let content_start = self.header.content_start as u64;
+        Ok(ContentSection::new(
+            self.file,
+            content_start,
+            offset,
+            length,
+        ))
     }
 
     /// Consumes the object and streams a byte range of its content. Rejects compressed objects.
@@ -319,19 +554,23 @@ impl AsyncTuxObject {
         offset: u64,
         length: Option<u64>,
     ) -> ObjectFileResult<AsyncOwnedContentReader> {
-        let remaining = self.seek_within_content(offset, length).await?;
-        Ok(ContentSection {
-            file: self.file,
-            remaining,
-        })
+        let length = self.seek_within_content(offset, length).await?;
+        let content_start = self.header.content_start as u64;
+        Ok(ContentSection::new(
+            self.file,
+            content_start,
+            offset,
+            length,
+        ))
     }
 
-    /// Positions the file at the start of the content, returning the whole stored length.
-    async fn seek_to_content_start(&mut self) -> ObjectFileResult<u64> {
+    /// Positions the file at the start of the content, returning the range that covers all of the
+    /// stored content.
+    async fn seek_to_content_start(&mut self) -> ObjectFileResult<(u64, u64)> {
         self.file
             .seek(std::io::SeekFrom::Start(self.header.content_start as u64))
             .await?;
-        Ok(self.header.content_length)
+        Ok((0, self.header.content_length))
     }
 
     /// Positions the file `offset` bytes into the content, returning how much may be read.
@@ -340,6 +579,7 @@ impl AsyncTuxObject {
         offset: u64,
         length: Option<u64>,
     ) -> ObjectFileResult<u64> {
+        self.ensure_readable()?;
         // A compressed object's stored bytes do not correspond to content offsets, so a range over
         // them would be meaningless rather than merely inefficient.
         if self.is_compressed() {
@@ -371,64 +611,59 @@ impl AsyncTuxObject {
         Ok(length)
     }
 
-    /// Reads the whole content into memory.
+    /// Streams the content through the object's codec, or straight through when uncompressed.
+    pub async fn decompressed_content_reader(
+        &mut self,
+    ) -> ObjectFileResult<AsyncDecodedContentReader<'_>> {
+        self.ensure_readable()?;
+        let compression = self.header.compression_type;
+        let reader = self.stored_content_reader().await?;
+        DecodedSection::new(reader, compression)
+    }
+
+    /// Consumes the object and streams its content through the object's codec.
     ///
-    /// Compressed objects are decoded on a blocking thread, since the codecs are synchronous.
+    /// The decompressing counterpart of [AsyncTuxObject::into_content_reader], for a body that has to
+    /// outlive the object handle.
+    pub async fn into_decompressed_content_reader(
+        self,
+    ) -> ObjectFileResult<AsyncOwnedDecodedContentReader> {
+        self.ensure_readable()?;
+        let compression = self.header.compression_type;
+        let reader = self.into_content_reader().await?;
+        DecodedSection::new(reader, compression)
+    }
+
+    /// Reads the whole content into memory, decompressing when needed.
     pub async fn read_content_to_vec(&mut self) -> ObjectFileResult<Vec<u8>> {
-        let mut stored = Vec::with_capacity(self.header.content_length as usize);
-        self.stored_content_reader()
+        let mut buffer = Vec::with_capacity(self.header.content_length as usize);
+        self.decompressed_content_reader()
             .await?
-            .read_to_end(&mut stored)
+            .read_to_end(&mut buffer)
             .await?;
-
-        match self.header.compression_type {
-            CompressionTypes::None(_) => Ok(stored),
-            other => decode_blocking(stored, other).await,
-        }
+        Ok(buffer)
     }
 }
 
-/// Decodes a compressed content buffer on the blocking pool.
-async fn decode_blocking(
-    stored: Vec<u8>,
-    compression: CompressionTypes,
-) -> ObjectFileResult<Vec<u8>> {
-    ensure_supported(compression)?;
-    tokio::task::spawn_blocking(move || -> ObjectFileResult<Vec<u8>> {
-        #[allow(unused_imports)]
-        use std::io::Read;
-        #[allow(unused)]
-        let mut decoded = Vec::new();
-        match compression {
-            CompressionTypes::None(_) => decoded = stored,
-            #[cfg(feature = "zstd")]
-            CompressionTypes::ZSTD(_) => {
-                zstd::stream::read::Decoder::new(Cursor::new(stored))
-                    .map_err(ObjectFileError::IO)?
-                    .read_to_end(&mut decoded)?;
-            }
-            #[cfg(feature = "gzip")]
-            CompressionTypes::Gzip(_) => {
-                flate2::read::GzDecoder::new(Cursor::new(stored)).read_to_end(&mut decoded)?;
-            }
-            #[allow(unreachable_patterns)]
-            other => return Err(ObjectFileError::UnsupportedCompression(other)),
-        }
-        Ok(decoded)
-    })
-    .await
-    .map_err(|err| ObjectFileError::IO(std::io::Error::other(err)))?
-}
-
 /// An [AsyncRead] bounded to the content section of an object file.
 ///
 /// Generic over how the file is held so the borrowing and owning readers share one implementation of
 /// the bound — the bound is the part that must not be got wrong, and duplicating it would mean
 /// duplicating the `unsafe` in [ContentSection::poll_read].
+///
+/// Seeks like [crate::fs::ContentReader]: positions are offsets into the content, and a seek
+/// outside the reader's range stops at the nearer end of it.
 #[derive(Debug)]
 pub struct ContentSection<File> {
     file: File,
-    remaining: u64,
+    /// Where the content starts in the file.
+    content_start: u64,
+    /// The reader's range, as offsets into the content, and where in it the next read starts.
+    start: u64,
+    position: u64,
+    end: u64,
+    /// Where a seek started with [AsyncSeek::start_seek] will land.
+    seeking: Option<u64>,
 }
 
 /// A content reader borrowing the object it reads from.
@@ -437,12 +672,25 @@ pub type AsyncContentReader<'object> = ContentSection<&'object mut File>;
 pub type AsyncOwnedContentReader = ContentSection<File>;
 
 impl<F> ContentSection<F> {
+    /// A reader over `length` bytes starting `offset` bytes into content that starts at
+    /// `content_start` in the file. `file` must already be positioned at that offset.
+    fn new(file: F, content_start: u64, offset: u64, length: u64) -> Self {
+        Self {
+            file,
+            content_start,
+            start: offset,
+            position: offset,
+            end: offset + length,
+            seeking: None,
+        }
+    }
+
     /// Bytes still available from this reader.
     pub fn remaining(&self) -> u64 {
-        self.remaining
+        self.end - self.position
     }
     pub fn is_empty(&self) -> bool {
-        self.remaining == 0
+        self.position == self.end
     }
 }
 
@@ -452,12 +700,12 @@ impl<F: AsyncRead + Unpin> AsyncRead for ContentSection<F> {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        if self.remaining == 0 {
+        if self.is_empty() {
             return Poll::Ready(Ok(()));
         }
 
         // Never let the inner file read past the end of the content section.
-        let limit = self.remaining.min(buf.remaining() as u64) as usize;
+        let limit = self.remaining().min(buf.remaining() as u64) as usize;
         let mut limited = buf.take(limit);
 
         match Pin::new(&mut self.file).poll_read(cx, &mut limited) {
@@ -467,7 +715,7 @@ impl<F: AsyncRead + Unpin> AsyncRead for ContentSection<F> {
                 // reports as filled are initialised in `buf`'s backing storage too.
                 unsafe { buf.assume_init(read) };
                 buf.advance(read);
-                self.remaining -= read as u64;
+                self.position += read as u64;
                 Poll::Ready(Ok(()))
             }
             other => other,
@@ -475,11 +723,44 @@ impl<F: AsyncRead + Unpin> AsyncRead for ContentSection<F> {
     }
 }
 
+impl<F: AsyncSeek + Unpin> AsyncSeek for ContentSection<F> {
+    fn start_seek(mut self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        let target =
+            content_seek_target(position, self.position, self.end)?.clamp(self.start, self.end);
+        let file_position = self.content_start + target;
+        Pin::new(&mut self.file).start_seek(std::io::SeekFrom::Start(file_position))?;
+        self.seeking = Some(target);
+        Ok(())
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        let Some(target) = self.seeking else {
+            return Poll::Ready(Ok(self.position));
+        };
+        match Pin::new(&mut self.file).poll_complete(cx) {
+            Poll::Ready(Ok(_)) => {
+                self.seeking = None;
+                self.position = target;
+                Poll::Ready(Ok(target))
+            }
+            Poll::Ready(Err(err)) => {
+                self.seeking = None;
+                Poll::Ready(Err(err))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
 /// The async counterpart of [crate::fs::ObjectWriter].
 ///
 /// Same ordering as the sync writer: content first, prefix last, published by rename on
 /// [AsyncObjectWriter::finish]. Dropping without finishing leaves the temporary file behind — async
 /// drop cannot await the removal — so prefer [AsyncObjectWriter::abort] on the error path.
+///
+/// As with the sync writer, a compressed object's content goes through
+/// [AsyncObjectWriter::content_encoder]; writing to the [AsyncWrite] impl directly is rejected while
+/// a codec is configured.
 pub struct AsyncObjectWriter {
     file: File,
     temp_path: Option<PathBuf>,
@@ -487,10 +768,27 @@ pub struct AsyncObjectWriter {
     layout: SectionLayout,
     layout_options: LayoutOptions,
     compression: CompressionTypes,
+    compression_policy: CompressionPolicy,
+    compression_workers: u32,
     metadata: MetadataMap,
     tags: Tags,
     content_length: u64,
+    /// Only ever set for content copied across in its stored form; this writer does not encrypt.
+    bit_flags: u8,
+    /// See the field of the same name on [crate::fs::ObjectWriter].
+    lock: Option<LockMode>,
+    /// See the field of the same name on [crate::fs::ObjectWriter].
+    locked_file: Option<std::fs::File>,
+    /// See the field of the same name on [crate::fs::ObjectWriter].
+    precondition: Precondition,
+    generation: u64,
     sync: bool,
+    /// See the field of the same name on [crate::fs::ObjectWriter].
+    versioned: bool,
+    /// See the field of the same name on [crate::fs::ObjectWriter].
+    sniffer: Option<ContentSniffer>,
+    /// See the field of the same name on [crate::fs::ObjectWriter].
+    allow_raw_writes: bool,
 }
 
 impl AsyncObjectWriter {
@@ -501,16 +799,22 @@ impl AsyncObjectWriter {
     ) -> ObjectFileResult<Self> {
         let final_path = path.into();
         ensure_supported(options.compression)?;
-        if !matches!(options.compression, CompressionTypes::None(_)) {
-            // The codecs are synchronous; compressed content has to be produced with the blocking
-            // writer and handed over, rather than streamed through this type.
-            return Err(ObjectFileError::UnsupportedCompression(options.compression));
+        #[cfg(feature = "encryption")]
+        if options.encryption.is_some() {
+            return Err(ObjectFileError::AsyncEncryptionUnsupported);
         }
-        let layout = options
-            .layout
-            .compute(options.metadata.size(), options.tags.size())?;
+        let mut metadata = options.metadata;
+        let versioned = options.versioned || is_versioned(&final_path).await;
+        stamp_version_id(&mut metadata, versioned, &final_path);
+        let sniffer = options.sniff_content_type.then(ContentSniffer::default);
+        let mut layout_options = options.layout;
+        if sniffer.is_some() {
+            layout_options.metadata_reserve += ContentSniffer::reserve();
+        }
+        let layout = layout_options.compute(metadata.size(), options.tags.size())?;
 
         let (file, temp_path) = create_temp_file(&final_path).await?;
+        let allow_raw_writes = matches!(options.compression, CompressionTypes::None(_));
 
         let mut writer = Self {
             file,
@@ -519,10 +823,20 @@ impl AsyncObjectWriter {
             layout,
             layout_options: options.layout,
             compression: options.compression,
-            metadata: options.metadata,
+            compression_policy: options.compression_policy,
+            compression_workers: options.compression_workers,
+            metadata,
             tags: options.tags,
             content_length: 0,
+            bit_flags: 0,
+            lock: None,
+            locked_file: None,
+            precondition: Precondition::None,
+            generation: 1,
             sync: options.sync,
+            versioned,
+            sniffer,
+            allow_raw_writes,
         };
         writer
             .file
@@ -549,9 +863,80 @@ impl AsyncObjectWriter {
     pub fn path(&self) -> &Path {
         &self.final_path
     }
+    /// See [crate::fs::ObjectWriter::sniffed_content_type].
+    pub fn sniffed_content_type(&self) -> Option<&'static str> {
+        self.sniffer.as_ref()?.content_type()
+    }
+    pub(crate) fn sniff(&mut self, content: &[u8]) {
+        if let Some(sniffer) = &mut self.sniffer {
+            sniffer.feed(content);
+        }
+    }
+    /// The compression the finished object will declare. See [crate::fs::ObjectWriter::compression].
+    pub fn compression(&self) -> CompressionTypes {
+        self.compression
+    }
+
+    /// Threads the content encoder compresses with. See
+    /// [crate::fs::CreateOptions::compression_workers]; under tokio only zstd makes use of them.
+    pub fn compression_workers(&self) -> u32 {
+        self.compression_workers
+    }
+
+    /// An encoder that compresses content on its way into this writer.
+    ///
+    /// The async counterpart of [crate::fs::ObjectWriter::content_encoder], streaming through the
+    /// codec rather than buffering. [AsyncContentEncoder::finish] must be awaited to flush it.
+    pub fn content_encoder(&mut self) -> ObjectFileResult<AsyncContentEncoder<'_>> {
+        let compression = self.compression;
+        self.allow_raw_writes = true;
+        match self.compression_policy {
+            CompressionPolicy::Auto {
+                min_ratio,
+                sample_bytes,
+            } if sample_bytes > 0 && !matches!(compression, CompressionTypes::None(_)) => {
+                AsyncContentEncoder::sampling(self, compression, min_ratio, sample_bytes)
+            }
+            _ => AsyncContentEncoder::new(self, compression),
+        }
+    }
+
+    /// See [crate::fs::ObjectWriter::commit_compression].
+    pub(crate) fn commit_compression(&mut self, compression: CompressionTypes) {
+        self.compression = compression;
+        self.compression_policy = CompressionPolicy::Fixed;
+    }
+
+    /// Copies content already in its stored form straight into the file, past the raw-write guard.
+    /// See [crate::fs::ObjectWriter::copy_stored_content].
+    pub(crate) async fn copy_stored_content<R>(&mut self, source: &mut R) -> ObjectFileResult<u64>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let copied = tokio::io::copy(source, &mut self.file).await?;
+        self.content_length += copied;
+        Ok(copied)
+    }
+
+    /// See [crate::fs::ObjectWriter::finish_if_absent].
+    pub async fn finish_if_absent(mut self) -> ObjectFileResult<AsyncTuxObject> {
+        self.precondition = Precondition::Absent;
+        self.finish().await
+    }
+
+    /// See [crate::fs::ObjectWriter::finish_if_generation].
+    pub async fn finish_if_generation(mut self, expected: u64) -> ObjectFileResult<AsyncTuxObject> {
+        self.precondition = Precondition::Generation(expected);
+        self.finish().await
+    }
 
     /// Writes the prefix, publishes the object, and reopens it for reading.
     pub async fn finish(mut self) -> ObjectFileResult<AsyncTuxObject> {
+        self.versioned |= is_versioned(&self.final_path).await;
+        stamp_version_id(&mut self.metadata, self.versioned, &self.final_path);
+        if let Some(sniffer) = self.sniffer.take() {
+            sniffer.fill_in(&mut self.metadata);
+        }
         let metadata_size = self.metadata.size();
         let tags_size = self.tags.size();
 
@@ -566,13 +951,15 @@ impl AsyncObjectWriter {
         };
         self.layout = layout;
 
+        let replaced = self.check_precondition().await?;
         let header = ObjectHeader {
             version: 0,
             compression_type: self.compression,
             tags_start: layout.tags_start,
             content_start: layout.content_start,
             content_length: self.content_length,
-            bit_flags: 0,
+            bit_flags: self.bit_flags,
+            generation: self.generation,
         };
         let prefix = encode_prefix(&header, &self.metadata, &self.tags, layout)?;
 
@@ -585,6 +972,7 @@ impl AsyncObjectWriter {
         }
 
         self.publish().await?;
+        drop(replaced);
 
         let file = self.file.try_clone().await?;
         Ok(AsyncTuxObject {
@@ -593,6 +981,7 @@ impl AsyncObjectWriter {
             header,
             metadata: self.metadata.clone(),
             writable: true,
+            lock: self.lock,
         })
     }
 
@@ -608,11 +997,119 @@ impl AsyncObjectWriter {
         Ok(())
     }
 
+    /// See [crate::fs::ObjectWriter]'s method of the same name.
+    async fn check_precondition(&mut self) -> ObjectFileResult<Option<AsyncTuxObject>> {
+        match self.precondition {
+            Precondition::None => {
+                let replaced = match generation_under_lock(&self.final_path, &self.locked_file) {
+                    Ok(Some(generation)) => Some(generation),
+                    _ => current_generation(&self.final_path).await.ok().flatten(),
+                };
+                self.generation = next_generation(replaced);
+                Ok(None)
+            }
+            Precondition::Absent => {
+                if tokio::fs::try_exists(&self.final_path).await? {
+                    return Err(ObjectFileError::PreconditionFailed {
+                        expected: None,
+                        found: current_generation(&self.final_path).await.ok().flatten(),
+                    });
+                }
+                self.generation = 1;
+                Ok(None)
+            }
+            Precondition::Generation(expected) => {
+                // Only an exclusive lock keeps other lockers out from the check to the publish.
+                if self.lock == Some(LockMode::Shared) {
+                    return Err(ObjectFileError::ConditionalWriteUnderSharedLock);
+                }
+                if let Some(found) = generation_under_lock(&self.final_path, &self.locked_file)? {
+                    if found != expected {
+                        return Err(ObjectFileError::PreconditionFailed {
+                            expected: Some(expected),
+                            found: Some(found),
+                        });
+                    }
+                    self.generation = next_generation(Some(found));
+                    return Ok(None);
+                }
+                let missing = ObjectFileError::PreconditionFailed {
+                    expected: Some(expected),
+                    found: None,
+                };
+                let mut replaced = match AsyncTuxObject::open(&self.final_path).await {
+                    Ok(replaced) => replaced,
+                    Err(err) if err.is_not_found() => return Err(missing),
+                    Err(err) => return Err(err),
+                };
+                match replaced.lock(LockMode::Exclusive).await {
+                    Ok(()) => {}
+                    Err(err) if err.is_not_found() => return Err(missing),
+                    Err(err) => return Err(err),
+                }
+                let found = replaced.generation();
+                if found != expected {
+                    return Err(ObjectFileError::PreconditionFailed {
+                        expected: Some(expected),
+                        found: Some(found),
+                    });
+                }
+                self.generation = next_generation(Some(found));
+                Ok(Some(replaced))
+            }
+        }
+    }
+
     async fn publish(&mut self) -> ObjectFileResult<()> {
-        let Some(temp_path) = self.temp_path.take() else {
+        let Some(temp_path) = &self.temp_path else {
             return Ok(());
         };
-        tokio::fs::rename(&temp_path, &self.final_path).await?;
+        // Nobody else can have the temp file open, so this never waits.
+        if let Some(mode) = self.lock {
+            let file = self.file.try_clone().await?.into_std().await;
+            lock_file(&file, mode, LockWait::Forever)?;
+        }
+        let replacing = self.precondition != Precondition::Absent;
+        let kept = match stamped_version_id(&self.metadata) {
+            Some(version_id) if self.versioned => {
+                let (final_path, temp_path) = (self.final_path.clone(), temp_path.clone());
+                let version_id = version_id.to_owned();
+                // A handful of links, and a read of the object being replaced.
+                let kept = tokio::task::spawn_blocking(move || {
+                    keep_published(&final_path, &temp_path, &version_id, replacing)
+                })
+                .await
+                .map_err(std::io::Error::other)??;
+                Some(kept)
+            }
+            _ => None,
+        };
+        let published = if replacing {
+            tokio::fs::rename(temp_path, &self.final_path)
+                .await
+                .map_err(ObjectFileError::from)
+        } else {
+            match tokio::fs::hard_link(temp_path, &self.final_path).await {
+                Ok(()) => {
+                    let _ = tokio::fs::remove_file(temp_path).await;
+                    Ok(())
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Err(ObjectFileError::PreconditionFailed {
+                        expected: None,
+                        found: current_generation(&self.final_path).await.ok().flatten(),
+                    })
+                }
+                Err(err) => Err(err.into()),
+            }
+        };
+        if let Err(err) = published {
+            if let Some(kept) = kept {
+                let _ = tokio::fs::remove_file(kept).await;
+            }
+            return Err(err);
+        }
+        self.temp_path = None;
         if self.sync
             && let Some(parent) = self.final_path.parent()
             && let Ok(dir) = File::open(parent).await
@@ -629,22 +1126,34 @@ impl AsyncObjectWriter {
             tags: std::mem::take(&mut self.tags),
             layout: self.layout_options,
             compression: self.compression,
+            compression_policy: CompressionPolicy::Fixed,
+            compression_workers: 0,
+            #[cfg(feature = "encryption")]
+            encryption: None,
             sync: self.sync,
+            versioned: self.versioned,
+            sniff_content_type: false,
         };
         options.layout.metadata_reserve = options.layout.metadata_reserve.max(256);
         options.layout.tag_reserve = options.layout.tag_reserve.max(256);
 
         let mut replacement = AsyncObjectWriter::create(&self.final_path, options).await?;
+        replacement.bit_flags = self.bit_flags;
+        replacement.lock = self.lock;
+        replacement.locked_file = self.locked_file.take();
+        replacement.precondition = self.precondition;
 
         self.file.flush().await?;
         self.file
             .seek(std::io::SeekFrom::Start(self.layout.content_start as u64))
             .await?;
-        let mut source = AsyncContentReader {
-            file: &mut self.file,
-            remaining: self.content_length,
-        };
-        tokio::io::copy(&mut source, &mut replacement).await?;
+        let mut source = AsyncContentReader::new(
+            &mut self.file,
+            self.layout.content_start as u64,
+            0,
+            self.content_length,
+        );
+        replacement.copy_stored_content(&mut source).await?;
 
         // Boxed to break the `finish` -> `finish_by_rewriting` -> `finish` cycle. The replacement
         // was created with larger reserves, so it does not take this path again.
@@ -659,7 +1168,8 @@ impl AsyncObjectWriter {
 ///
 /// [AsyncObjectWriter::abort] is the ordinary way to give up and the one that reports a failure; this is
 /// the safety net for the path that cannot call it, a write abandoned by `?` on the way out. Without it,
-/// every upload that fails part way through leaves a `.tuxtmp` file behind for nobody to collect.
+/// every upload that fails part way through leaves a `.tuxtmp` file behind until [crate::fs::sweep_temp_files]
+/// gets to it.
 ///
 /// The unlink is synchronous because a [Drop] cannot await. That is acceptable for removing one small
 /// file, and it is what the blocking writer already does.
@@ -677,8 +1187,18 @@ impl AsyncWrite for AsyncObjectWriter {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
+        if !self.allow_raw_writes {
+            return Poll::Ready(Err(std::io::Error::other(
+                "this object declares a compression codec; write its content through \
+                 AsyncObjectWriter::content_encoder",
+            )));
+        }
         match Pin::new(&mut self.file).poll_write(cx, buf) {
             Poll::Ready(Ok(written)) => {
+                // Compressed content is sniffed by its encoder, before it is compressed.
+                if matches!(self.compression, CompressionTypes::None(_)) {
+                    self.sniff(&buf[..written]);
+                }
                 self.content_length += written as u64;
                 Poll::Ready(Ok(written))
             }
@@ -694,7 +1214,7 @@ impl AsyncWrite for AsyncObjectWriter {
 }
 
 /// Creates a uniquely named temp file next to `final_path`.
-async fn create_temp_file(final_path: &Path) -> ObjectFileResult<(File, PathBuf)> {
+pub(crate) async fn create_temp_file(final_path: &Path) -> ObjectFileResult<(File, PathBuf)> {
     let parent = final_path.parent().unwrap_or_else(|| Path::new("."));
     let stem = final_path
         .file_name()
@@ -704,7 +1224,10 @@ async fn create_temp_file(final_path: &Path) -> ObjectFileResult<(File, PathBuf)
 
     for _ in 0..32 {
         let counter = crate::fs::next_temp_counter();
-        let candidate = parent.join(format!(".{stem}.{pid}.{counter}.tuxtmp"));
+        let candidate = parent.join(format!(
+            ".{stem}.{pid}.{counter}.{}",
+            crate::fs::TEMP_FILE_EXTENSION
+        ));
         match OpenOptions::new()
             .read(true)
             .write(true)
@@ -725,35 +1248,13 @@ async fn create_temp_file(final_path: &Path) -> ObjectFileResult<(File, PathBuf)
 
 #[cfg(test)]
 mod tests {
+    use std::io::SeekFrom;
+
     use http::header::CONTENT_TYPE;
 
     use super::*;
     use crate::fs::CreateOptions;
-
-    struct TempDir(PathBuf);
-    impl TempDir {
-        fn new(name: &str) -> Self {
-            let path = std::env::temp_dir().join(format!(
-                "tux-io-encoding-async-{}-{}-{}",
-                name,
-                std::process::id(),
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos()
-            ));
-            std::fs::create_dir_all(&path).unwrap();
-            TempDir(path)
-        }
-        fn join(&self, name: &str) -> PathBuf {
-            self.0.join(name)
-        }
-    }
-    impl Drop for TempDir {
-        fn drop(&mut self) {
-            let _ = std::fs::remove_dir_all(&self.0);
-        }
-    }
+    use crate::fs::testing::TempDir;
 
     #[tokio::test]
     async fn create_and_read_back() {
@@ -882,6 +1383,46 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn content_readers_seek_within_their_range() {
+        let dir = TempDir::new("seek");
+        let path = dir.join("object.tuxio");
+
+        let mut writer = AsyncTuxObject::create(&path, CreateOptions::new())
+            .await
+            .unwrap();
+        writer.write_all(b"0123456789").await.unwrap();
+        writer.finish().await.unwrap();
+
+        let mut object = AsyncTuxObject::open(&path).await.unwrap();
+        let mut reader = object.stored_content_reader().await.unwrap();
+        let mut buffer = [0u8; 3];
+        assert_eq!(reader.seek(SeekFrom::End(-3)).await.unwrap(), 7);
+        reader.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"789");
+        assert_eq!(reader.seek(SeekFrom::Current(-8)).await.unwrap(), 2);
+        reader.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"234");
+        // Past the end stops at the end rather than reading what follows the content.
+        assert_eq!(reader.seek(SeekFrom::Start(100)).await.unwrap(), 10);
+        assert_eq!(reader.read(&mut buffer).await.unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-11)).await.is_err());
+
+        // A range keeps content offsets, and stays inside the range.
+        let mut ranged = AsyncTuxObject::open(&path)
+            .await
+            .unwrap()
+            .into_content_range_reader(3, Some(4))
+            .await
+            .unwrap();
+        assert_eq!(ranged.seek(SeekFrom::Start(0)).await.unwrap(), 3);
+        assert_eq!(ranged.seek(SeekFrom::Start(5)).await.unwrap(), 5);
+        let mut rest = Vec::new();
+        ranged.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"56");
+        assert_eq!(ranged.seek(SeekFrom::End(0)).await.unwrap(), 7);
+    }
+
     #[tokio::test]
     async fn larger_content_round_trips() {
         let dir = TempDir::new("large");
@@ -987,6 +1528,210 @@ mod tests {
         assert_eq!(object.read_content_to_vec().await.unwrap(), b"a,b,c");
     }
 
+    #[cfg(feature = "zstd")]
+    fn zstd() -> CompressionTypes {
+        CompressionTypes::ZSTD(crate::compression_types::ZStdCompressionType(3))
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn compressed_content_streams_in_both_directions() {
+        let dir = TempDir::new("zstd");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(64 * 1024);
+        let mut writer =
+            AsyncTuxObject::create(&path, CreateOptions::new().with_compression(zstd()))
+                .await
+                .unwrap();
+        // Raw bytes would land under a header claiming zstd.
+        assert!(writer.write_all(b"raw bytes").await.is_err());
+
+        let mut encoder = writer.content_encoder().unwrap();
+        for chunk in content.chunks(7000) {
+            encoder.write_all(chunk).await.unwrap();
+        }
+        assert_eq!(encoder.finish().await.unwrap(), content.len() as u64);
+        let object = writer.finish().await.unwrap();
+        assert!(object.content_length() < content.len() as u64);
+        assert_eq!(
+            object
+                .metadata()
+                .get_header(&crate::fs::UNCOMPRESSED_LENGTH),
+            Some(&ValueType::U64(content.len() as u64))
+        );
+        drop(object);
+
+        let mut object = AsyncTuxObject::open(&path).await.unwrap();
+        let mut decoded = Vec::new();
+        object
+            .decompressed_content_reader()
+            .await
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, content);
+
+        // The owned reader decodes the same way after the handle is gone.
+        decoded.clear();
+        AsyncTuxObject::open(&path)
+            .await
+            .unwrap()
+            .into_decompressed_content_reader()
+            .await
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, content);
+
+        // And the blocking layer reads what the async codec wrote.
+        let mut object = crate::fs::TuxObject::open(&path).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn sync_compressed_objects_decode_asynchronously() {
+        use std::io::Write;
+
+        let dir = TempDir::new("gzip-interop");
+        let path = dir.join("object.tuxio");
+
+        let content = b"written by the blocking codec ".repeat(512);
+        let mut writer = crate::fs::TuxObject::create(
+            &path,
+            CreateOptions::new().with_compression(CompressionTypes::Gzip(
+                crate::compression_types::GzipCompressionType(6),
+            )),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        writer.finish().unwrap();
+
+        let mut object = AsyncTuxObject::open(&path).await.unwrap();
+        assert_eq!(object.read_content_to_vec().await.unwrap(), content);
+    }
+
+    /// Parallel gzip is only written by the blocking encoder, but its members must all be read here.
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn parallel_gzip_decodes_asynchronously() {
+        use std::io::Write;
+
+        let dir = TempDir::new("parallel-gzip");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(crate::fs::PARALLEL_GZIP_BLOCK / 2);
+        let mut writer = crate::fs::TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(CompressionTypes::Gzip(
+                    crate::compression_types::GzipCompressionType(6),
+                ))
+                .with_compression_workers(2),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        writer.finish().unwrap();
+
+        let mut object = AsyncTuxObject::open(&path).await.unwrap();
+        assert_eq!(object.read_content_to_vec().await.unwrap(), content);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn multithreaded_zstd_streams_asynchronously() {
+        let dir = TempDir::new("zstdmt");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(512 * 1024);
+        let mut writer = AsyncTuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(zstd())
+                .with_compression_workers(4),
+        )
+        .await
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).await.unwrap();
+        encoder.finish().await.unwrap();
+        writer.finish().await.unwrap();
+
+        let mut object = AsyncTuxObject::open(&path).await.unwrap();
+        assert_eq!(object.read_content_to_vec().await.unwrap(), content);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn auto_policy_falls_back_for_incompressible_content() {
+        let dir = TempDir::new("auto");
+        let path = dir.join("object.tuxio");
+
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let content: Vec<u8> = (0..(48 * 1024))
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        let mut writer = AsyncTuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(zstd())
+                .with_compression_policy(CompressionPolicy::Auto {
+                    min_ratio: 1.1,
+                    sample_bytes: 16 * 1024,
+                }),
+        )
+        .await
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).await.unwrap();
+        encoder.finish().await.unwrap();
+        let object = writer.finish().await.unwrap();
+        assert!(!object.is_compressed());
+        assert_eq!(object.content_length(), content.len() as u64);
+        drop(object);
+
+        let mut object = AsyncTuxObject::open(&path).await.unwrap();
+        assert_eq!(object.read_content_to_vec().await.unwrap(), content);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn compressed_objects_survive_a_metadata_rewrite() {
+        let dir = TempDir::new("zstd-rewrite");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(4096);
+        let mut writer =
+            AsyncTuxObject::create(&path, CreateOptions::new().with_compression(zstd()))
+                .await
+                .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).await.unwrap();
+        encoder.finish().await.unwrap();
+        writer.finish().await.unwrap();
+
+        let mut object = AsyncTuxObject::open_writable(&path).await.unwrap();
+        let mut metadata = object.metadata().clone();
+        metadata.insert(CONTENT_TYPE.into(), "text/plain".to_owned().into());
+        object.set_metadata(metadata).await.unwrap();
+
+        assert!(object.is_compressed());
+        assert_eq!(object.read_content_to_vec().await.unwrap(), content);
+    }
+
     #[tokio::test]
     async fn async_written_objects_are_readable_synchronously() {
         let dir = TempDir::new("interop-reverse");
@@ -1001,4 +1746,165 @@ mod tests {
         let mut object = crate::fs::TuxObject::open(&path).unwrap();
         assert_eq!(object.read_content_to_vec().unwrap(), b"written by tokio");
     }
+
+    /// Encryption is blocking-only: the async writer refuses it, and the async reader refuses to
+    /// hand out ciphertext as content, yet a metadata update still carries an encrypted object over.
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn encrypted_content_is_refused_but_carried() {
+        use std::io::Write;
+
+        use crate::fs::{ContentEncryption, EncryptionAlgorithm, EncryptionKey};
+
+        let dir = TempDir::new("encrypted");
+        let path = dir.join("object.tuxio");
+        let encryption = ContentEncryption::new(
+            EncryptionAlgorithm::Aes256Gcm,
+            "primary",
+            EncryptionKey::new([7; 32]),
+        );
+
+        assert!(matches!(
+            AsyncTuxObject::create(
+                &path,
+                CreateOptions::new().with_encryption(encryption.clone())
+            )
+            .await,
+            Err(ObjectFileError::AsyncEncryptionUnsupported)
+        ));
+
+        let mut writer =
+            crate::fs::TuxObject::create(&path, CreateOptions::new().with_encryption(encryption))
+                .unwrap();
+        writer.write_all(b"secret").unwrap();
+        writer.finish().unwrap();
+
+        let mut object = AsyncTuxObject::open_writable(&path).await.unwrap();
+        assert!(matches!(
+            object.read_content_to_vec().await,
+            Err(ObjectFileError::ContentLocked)
+        ));
+        let mut metadata = MetadataMap::new();
+        metadata.insert(CONTENT_TYPE.into(), "text/plain".to_owned().into());
+        object.set_metadata(metadata).await.unwrap();
+        drop(object);
+
+        let mut object = crate::fs::TuxObject::open(&path).unwrap();
+        let keys =
+            std::collections::HashMap::from([("primary".to_owned(), EncryptionKey::new([7; 32]))]);
+        object.unlock(&keys).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), b"secret");
+    }
+
+    #[tokio::test]
+    async fn locks_are_kept_across_rewrites() {
+        let dir = TempDir::new("lock");
+        let path = dir.join("object.tuxio");
+        let mut writer = AsyncTuxObject::create(&path, CreateOptions::new())
+            .await
+            .unwrap();
+        writer.write_all(b"locked").await.unwrap();
+        writer.finish().await.unwrap();
+
+        let mut object = AsyncTuxObject::open_writable(&path).await.unwrap();
+        let mut other = AsyncTuxObject::open(&path).await.unwrap();
+        object.lock(LockMode::Exclusive).await.unwrap();
+        assert!(!other.try_lock(LockMode::Shared).await.unwrap());
+        assert!(
+            !other
+                .lock_timeout(LockMode::Shared, Duration::from_millis(20))
+                .await
+                .unwrap()
+        );
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert(CONTENT_TYPE.into(), "text/plain".to_owned().into());
+        assert!(
+            object
+                .set_metadata_if(metadata.clone(), |_, current| current.0.is_empty())
+                .await
+                .unwrap()
+        );
+        assert_eq!(object.lock_mode(), Some(LockMode::Exclusive));
+
+        // Still holding the lock on the replacement, so the other handle waits for the release and
+        // then follows the object onto the new file.
+        let waiting = tokio::spawn(async move {
+            other.lock(LockMode::Shared).await.unwrap();
+            other
+        });
+        object.release_lock().await.unwrap();
+        let other = waiting.await.unwrap();
+        assert_eq!(other.metadata(), &metadata);
+    }
+
+    /// A wait that is given up on must not go on to take the lock once it is free.
+    #[tokio::test]
+    async fn cancelled_lock_waits_take_no_lock() {
+        let dir = TempDir::new("lock-cancel");
+        let path = dir.join("object.tuxio");
+        let mut writer = AsyncTuxObject::create(&path, CreateOptions::new())
+            .await
+            .unwrap();
+        writer.write_all(b"locked").await.unwrap();
+        writer.finish().await.unwrap();
+
+        let mut holder = AsyncTuxObject::open(&path).await.unwrap();
+        holder.lock(LockMode::Exclusive).await.unwrap();
+        let mut waiter = AsyncTuxObject::open(&path).await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), waiter.lock(LockMode::Exclusive))
+                .await
+                .is_err()
+        );
+        holder.release_lock().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(waiter.lock_mode(), None);
+        let mut other = AsyncTuxObject::open(&path).await.unwrap();
+        assert!(other.try_lock(LockMode::Exclusive).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn conditional_finishes_check_the_object_they_replace() {
+        let dir = TempDir::new("conditional-finish");
+        let path = dir.join("object.tuxio");
+        let writer = async |content: &[u8]| {
+            let mut writer = AsyncTuxObject::create(&path, CreateOptions::new())
+                .await
+                .unwrap();
+            writer.write_all(content).await.unwrap();
+            writer
+        };
+
+        let object = writer(b"first").await.finish_if_absent().await.unwrap();
+        assert_eq!(object.generation(), 1);
+        assert!(matches!(
+            writer(b"second").await.finish_if_absent().await,
+            Err(ObjectFileError::PreconditionFailed {
+                expected: None,
+                found: Some(1)
+            })
+        ));
+        assert!(matches!(
+            writer(b"second").await.finish_if_generation(2).await,
+            Err(ObjectFileError::PreconditionFailed {
+                expected: Some(2),
+                found: Some(1)
+            })
+        ));
+        let mut object = writer(b"second")
+            .await
+            .finish_if_generation(1)
+            .await
+            .unwrap();
+        assert_eq!(object.generation(), 2);
+        object
+            .set_sections_if_generation(2, MetadataMap::new(), Tags::new())
+            .await
+            .unwrap();
+        assert_eq!(object.generation(), 3);
+        assert_eq!(crate::fs::TuxObject::open(&path).unwrap().generation(), 3);
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
 }
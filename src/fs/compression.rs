@@ -4,6 +4,7 @@ use http::HeaderName;
 
 use crate::{
     CompressionTypes, MetaKey,
+    compression_types::NoCompression,
     fs::{ObjectFileError, ObjectFileResult, ObjectWriter},
 };
 
@@ -36,6 +37,27 @@ pub fn ensure_supported(compression: CompressionTypes) -> ObjectFileResult<()> {
     }
 }
 
+/// How an [ObjectWriter] decides whether to keep the codec it was created with.
+///
+/// Set with [crate::fs::CreateOptions::with_compression_policy]. Only matters when a codec is
+/// configured; an uncompressed object has nothing to decide.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CompressionPolicy {
+    /// Always compress with the configured codec.
+    #[default]
+    Fixed,
+    /// Compress the first `sample_bytes` of content in memory and keep the codec only when the sample
+    /// shrinks by at least `min_ratio` (uncompressed size over compressed size). Otherwise the object
+    /// is stored uncompressed, and its header says so.
+    ///
+    /// Content that is already compressed — JPEGs, zip archives, video — typically comes out at a
+    /// ratio of about 1.0, or slightly below once the codec's framing is counted, so a `min_ratio` a
+    /// little above 1 is enough to catch it. The decision is made once the sample is full, or at
+    /// [ContentEncoder::finish] for content shorter than the sample. A `sample_bytes` of zero behaves
+    /// as [CompressionPolicy::Fixed].
+    Auto { min_ratio: f64, sample_bytes: usize },
+}
+
 /// Compresses content on its way into an [ObjectWriter].
 ///
 /// Obtained from [ObjectWriter::content_encoder]. For an uncompressed object it is a pass-through,
@@ -46,6 +68,16 @@ pub enum ContentEncoder<'writer> {
         writer: &'writer mut ObjectWriter,
         uncompressed_length: u64,
     },
+    /// Collecting the sample that decides, under [CompressionPolicy::Auto], whether to keep the
+    /// codec. Becomes one of the other variants once the sample is full.
+    Sampling {
+        /// Only `None` for the moment the encoder is switching over to its decided variant.
+        writer: Option<&'writer mut ObjectWriter>,
+        compression: CompressionTypes,
+        sample: Vec<u8>,
+        sample_bytes: usize,
+        min_ratio: f64,
+    },
     #[cfg(feature = "zstd")]
     Zstd {
         encoder: Box<zstd::stream::write::Encoder<'static, &'writer mut ObjectWriter>>,
@@ -56,25 +88,59 @@ pub enum ContentEncoder<'writer> {
         encoder: Box<flate2::write::GzEncoder<&'writer mut ObjectWriter>>,
         uncompressed_length: u64,
     },
+    /// gzip spread over [crate::fs::CreateOptions::compression_workers] threads: the content is cut
+    /// into [PARALLEL_GZIP_BLOCK] sized blocks, a batch of them is compressed side by side, and each
+    /// is written out as a gzip member of its own. Concatenated members are themselves valid gzip,
+    /// but only a reader that goes on past the first member reads them back whole — see
+    /// [crate::fs::CreateOptions::compression_workers].
+    #[cfg(feature = "gzip")]
+    ParallelGzip {
+        writer: &'writer mut ObjectWriter,
+        level: flate2::Compression,
+        workers: usize,
+        /// The batch being collected. Every block but the last is full.
+        blocks: Vec<Vec<u8>>,
+        uncompressed_length: u64,
+    },
 }
 
+/// Uncompressed size of each block under [ContentEncoder::ParallelGzip]. Large enough that the
+/// per-member header and the restarted dictionary cost next to nothing, small enough that a batch
+/// held in memory stays modest.
+#[cfg(feature = "gzip")]
+pub const PARALLEL_GZIP_BLOCK: usize = 1024 * 1024;
+
 impl<'writer> ContentEncoder<'writer> {
     pub(crate) fn new(
         writer: &'writer mut ObjectWriter,
         compression: CompressionTypes,
     ) -> ObjectFileResult<Self> {
         ensure_supported(compression)?;
+        #[cfg(any(feature = "zstd", feature = "gzip"))]
+        let workers = writer.compression_workers();
         match compression {
             CompressionTypes::None(_) => Ok(ContentEncoder::Stored {
                 writer,
                 uncompressed_length: 0,
             }),
             #[cfg(feature = "zstd")]
-            CompressionTypes::ZSTD(level) => Ok(ContentEncoder::Zstd {
-                encoder: Box::new(
-                    zstd::stream::write::Encoder::new(writer, level.0)
-                        .map_err(ObjectFileError::IO)?,
-                ),
+            CompressionTypes::ZSTD(level) => {
+                let mut encoder = zstd::stream::write::Encoder::new(writer, level.0)
+                    .map_err(ObjectFileError::IO)?;
+                if workers > 1 {
+                    encoder.multithread(workers).map_err(ObjectFileError::IO)?;
+                }
+                Ok(ContentEncoder::Zstd {
+                    encoder: Box::new(encoder),
+                    uncompressed_length: 0,
+                })
+            }
+            #[cfg(feature = "gzip")]
+            CompressionTypes::Gzip(level) if workers > 1 => Ok(ContentEncoder::ParallelGzip {
+                writer,
+                level: flate2::Compression::new(level.0),
+                workers: workers as usize,
+                blocks: Vec::new(),
                 uncompressed_length: 0,
             }),
             #[cfg(feature = "gzip")]
@@ -90,6 +156,23 @@ impl<'writer> ContentEncoder<'writer> {
         }
     }
 
+    /// Buffers the start of the content until there is enough of it to judge the codec by.
+    pub(crate) fn sampling(
+        writer: &'writer mut ObjectWriter,
+        compression: CompressionTypes,
+        min_ratio: f64,
+        sample_bytes: usize,
+    ) -> ObjectFileResult<Self> {
+        ensure_supported(compression)?;
+        Ok(ContentEncoder::Sampling {
+            writer: Some(writer),
+            compression,
+            sample: Vec::with_capacity(sample_bytes),
+            sample_bytes,
+            min_ratio,
+        })
+    }
+
     /// Content bytes fed in so far, before compression.
     pub fn uncompressed_length(&self) -> u64 {
         match self {
@@ -97,6 +180,7 @@ impl<'writer> ContentEncoder<'writer> {
                 uncompressed_length,
                 ..
             } => *uncompressed_length,
+            ContentEncoder::Sampling { sample, .. } => sample.len() as u64,
             #[cfg(feature = "zstd")]
             ContentEncoder::Zstd {
                 uncompressed_length,
@@ -106,6 +190,10 @@ impl<'writer> ContentEncoder<'writer> {
             ContentEncoder::Gzip {
                 uncompressed_length,
                 ..
+            }
+            | ContentEncoder::ParallelGzip {
+                uncompressed_length,
+                ..
             } => *uncompressed_length,
         }
     }
@@ -117,6 +205,8 @@ impl<'writer> ContentEncoder<'writer> {
                 uncompressed_length,
                 ..
             } => *uncompressed_length += written,
+            // The sample buffer is its own record.
+            ContentEncoder::Sampling { .. } => {}
             #[cfg(feature = "zstd")]
             ContentEncoder::Zstd {
                 uncompressed_length,
@@ -126,17 +216,51 @@ impl<'writer> ContentEncoder<'writer> {
             ContentEncoder::Gzip {
                 uncompressed_length,
                 ..
+            }
+            | ContentEncoder::ParallelGzip {
+                uncompressed_length,
+                ..
             } => *uncompressed_length += written,
         }
     }
 
+    /// Judges the codec by the sample collected so far and switches to the encoder it decides on,
+    /// replaying the sample through it. Does nothing once decided.
+    fn decide(&mut self) -> ObjectFileResult<()> {
+        let ContentEncoder::Sampling {
+            writer,
+            compression,
+            sample,
+            min_ratio,
+            ..
+        } = self
+        else {
+            return Ok(());
+        };
+        let writer = writer
+            .take()
+            .expect("a sampling encoder always holds its writer");
+        let sample = std::mem::take(sample);
+
+        let compression = judge_sample(*compression, &sample, *min_ratio)?;
+        writer.commit_compression(compression);
+
+        let mut decided = ContentEncoder::new(writer, compression)?;
+        decided.write_all(&sample)?;
+        *self = decided;
+        Ok(())
+    }
+
     /// Flushes the codec and records the uncompressed length in the object's metadata.
     ///
     /// Returns the uncompressed content length.
-    pub fn finish(self) -> ObjectFileResult<u64> {
+    pub fn finish(mut self) -> ObjectFileResult<u64> {
+        // Content shorter than the sample never filled it, so the decision happens now.
+        self.decide()?;
         let uncompressed_length = self.uncompressed_length();
         let writer = match self {
             ContentEncoder::Stored { writer, .. } => writer,
+            ContentEncoder::Sampling { .. } => unreachable!("decided above"),
             #[cfg(feature = "zstd")]
             ContentEncoder::Zstd { encoder, .. } => {
                 encoder.finish().map_err(ObjectFileError::IO)?
@@ -145,6 +269,20 @@ impl<'writer> ContentEncoder<'writer> {
             ContentEncoder::Gzip { encoder, .. } => {
                 encoder.finish().map_err(ObjectFileError::IO)?
             }
+            #[cfg(feature = "gzip")]
+            ContentEncoder::ParallelGzip {
+                writer,
+                level,
+                mut blocks,
+                ..
+            } => {
+                // Empty content still needs one member, or there is no gzip stream to read back.
+                if blocks.is_empty() {
+                    blocks.push(Vec::new());
+                }
+                write_gzip_members(writer, level, &blocks)?;
+                writer
+            }
         };
         writer.flush().map_err(ObjectFileError::IO)?;
         // Only worth recording when the stored length differs from the real one.
@@ -159,12 +297,60 @@ impl<'writer> ContentEncoder<'writer> {
 
 impl Write for ContentEncoder<'_> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let ContentEncoder::Sampling {
+            sample,
+            sample_bytes,
+            ..
+        } = self
+        {
+            // Take no more than fills the sample; `write_all` comes back with the rest, which then
+            // goes to whichever encoder the sample decided on.
+            let taken = sample_bytes.saturating_sub(sample.len()).min(buf.len());
+            sample.extend_from_slice(&buf[..taken]);
+            if sample.len() >= *sample_bytes {
+                self.decide().map_err(std::io::Error::other)?;
+            }
+            return Ok(taken);
+        }
         let written = match self {
             ContentEncoder::Stored { writer, .. } => writer.write(buf)?,
+            ContentEncoder::Sampling { .. } => unreachable!("handled above"),
             #[cfg(feature = "zstd")]
-            ContentEncoder::Zstd { encoder, .. } => encoder.write(buf)?,
+            ContentEncoder::Zstd { encoder, .. } => {
+                let written = encoder.write(buf)?;
+                encoder.get_mut().sniff(&buf[..written]);
+                written
+            }
             #[cfg(feature = "gzip")]
-            ContentEncoder::Gzip { encoder, .. } => encoder.write(buf)?,
+            ContentEncoder::Gzip { encoder, .. } => {
+                let written = encoder.write(buf)?;
+                encoder.get_mut().sniff(&buf[..written]);
+                written
+            }
+            #[cfg(feature = "gzip")]
+            ContentEncoder::ParallelGzip {
+                writer,
+                level,
+                workers,
+                blocks,
+                ..
+            } => {
+                if blocks
+                    .last()
+                    .is_none_or(|block| block.len() == PARALLEL_GZIP_BLOCK)
+                {
+                    blocks.push(Vec::with_capacity(PARALLEL_GZIP_BLOCK));
+                }
+                let block = blocks.last_mut().expect("pushed above");
+                let taken = (PARALLEL_GZIP_BLOCK - block.len()).min(buf.len());
+                block.extend_from_slice(&buf[..taken]);
+                writer.sniff(&buf[..taken]);
+                if block.len() == PARALLEL_GZIP_BLOCK && blocks.len() == *workers {
+                    write_gzip_members(writer, *level, blocks)?;
+                    blocks.clear();
+                }
+                taken
+            }
         };
         self.record(written);
         Ok(written)
@@ -173,10 +359,88 @@ impl Write for ContentEncoder<'_> {
     fn flush(&mut self) -> std::io::Result<()> {
         match self {
             ContentEncoder::Stored { writer, .. } => writer.flush(),
+            // Nothing has reached the writer yet, and flushing must not force an early decision.
+            ContentEncoder::Sampling { .. } => Ok(()),
             #[cfg(feature = "zstd")]
             ContentEncoder::Zstd { encoder, .. } => encoder.flush(),
             #[cfg(feature = "gzip")]
             ContentEncoder::Gzip { encoder, .. } => encoder.flush(),
+            // A partial block stays buffered: cutting it short would only shrink the next member.
+            #[cfg(feature = "gzip")]
+            ContentEncoder::ParallelGzip { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+/// Compresses each block into a gzip member on a thread of its own, then writes the members out in
+/// order.
+#[cfg(feature = "gzip")]
+fn write_gzip_members(
+    writer: &mut ObjectWriter,
+    level: flate2::Compression,
+    blocks: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let members = std::thread::scope(|scope| {
+        let workers: Vec<_> = blocks
+            .iter()
+            .map(|block| {
+                scope.spawn(move || {
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(Vec::with_capacity(block.len() / 2), level);
+                    encoder.write_all(block)?;
+                    encoder.finish()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err(std::io::Error::other("a gzip worker panicked")))
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+    })?;
+    for member in members {
+        writer.write_all(&member)?;
+    }
+    Ok(())
+}
+
+/// The codec a [CompressionPolicy::Auto] sample decides on: `compression` when the sample shrinks by
+/// at least `min_ratio`, and no compression otherwise. Shared with the async encoder, so the two
+/// layers reach the same verdict on the same content.
+pub(crate) fn judge_sample(
+    compression: CompressionTypes,
+    sample: &[u8],
+    min_ratio: f64,
+) -> ObjectFileResult<CompressionTypes> {
+    // An empty object gives nothing to measure, and nothing to gain by compressing it.
+    let keep = !sample.is_empty()
+        && sample.len() as f64 / compressed_size(compression, sample)?.max(1) as f64 >= min_ratio;
+    Ok(if keep {
+        compression
+    } else {
+        CompressionTypes::None(NoCompression)
+    })
+}
+
+/// The size `sample` compresses to under `compression`, for judging whether the codec is worth it.
+fn compressed_size(compression: CompressionTypes, sample: &[u8]) -> ObjectFileResult<usize> {
+    match compression {
+        CompressionTypes::None(_) => Ok(sample.len()),
+        #[cfg(feature = "zstd")]
+        CompressionTypes::ZSTD(level) => Ok(zstd::bulk::compress(sample, level.0)
+            .map_err(ObjectFileError::IO)?
+            .len()),
+        #[cfg(feature = "gzip")]
+        CompressionTypes::Gzip(level) => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level.0));
+            encoder.write_all(sample)?;
+            Ok(encoder.finish()?.len())
         }
+        #[allow(unreachable_patterns)]
+        other => Err(ObjectFileError::UnsupportedCompression(other)),
     }
 }
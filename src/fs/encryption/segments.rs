@@ -0,0 +1,475 @@
+//! Keys, and sealing and opening content segments with them.
+
+use std::{collections::HashMap, fmt, io::Write};
+
+use aes_gcm::{
+    Aes256Gcm, Tag,
+    aead::{AeadInPlace, KeyInit},
+};
+use chacha20poly1305::ChaCha20Poly1305;
+use zeroize::Zeroize;
+
+use super::{
+    DEFAULT_SEGMENT_SIZE, ENCRYPTION, ENCRYPTION_KEY_ID, ENCRYPTION_NONCE, ENCRYPTION_SEGMENT_SIZE,
+    ENCRYPTION_WRAPPED_KEY, EncryptionAlgorithm, NONCE_PREFIX_SIZE, SEGMENT_TAG_SIZE,
+    WRAPPED_KEY_SIZE,
+};
+use crate::{
+    MetadataMap, ValueType,
+    fs::{ContentAuthenticationError, ObjectFileError, ObjectFileResult},
+};
+
+/// A 256 bit key, master or data. Wiped from memory when dropped, and never printed.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+    /// A fresh random key, for callers that manage their own key storage.
+    pub fn generate() -> ObjectFileResult<Self> {
+        let mut key = [0u8; 32];
+        getrandom::fill(&mut key).map_err(|err| ObjectFileError::IO(err.into()))?;
+        Ok(Self(key))
+    }
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+}
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+impl Drop for EncryptionKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Resolves the key id an object names to the master key its data key is wrapped under.
+///
+/// Implemented by the caller over whatever holds their keys — a KMS client, a keyring, a config
+/// file. Only the id is ever written to disk.
+pub trait KeyProvider {
+    /// The master key for `key_id`, or `None` when the provider does not have it.
+    fn key(&self, key_id: &str) -> Option<EncryptionKey>;
+}
+
+impl KeyProvider for HashMap<String, EncryptionKey> {
+    fn key(&self, key_id: &str) -> Option<EncryptionKey> {
+        self.get(key_id).cloned()
+    }
+}
+
+/// How a new object's content is encrypted. Set with
+/// [crate::fs::CreateOptions::with_encryption].
+#[derive(Debug, Clone)]
+pub struct ContentEncryption {
+    pub algorithm: EncryptionAlgorithm,
+    pub key_id: String,
+    /// The master key. The content itself is sealed under a data key drawn for the object, which
+    /// is stored wrapped under this one.
+    pub key: EncryptionKey,
+    /// Plaintext bytes per segment. A ranged read decrypts whole segments, so smaller segments
+    /// waste less on short ranges at the cost of 16 bytes of tag each.
+    pub segment_size: u32,
+}
+
+impl ContentEncryption {
+    pub fn new(
+        algorithm: EncryptionAlgorithm,
+        key_id: impl Into<String>,
+        key: EncryptionKey,
+    ) -> Self {
+        Self {
+            algorithm,
+            key_id: key_id.into(),
+            key,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+        }
+    }
+    pub fn with_segment_size(mut self, segment_size: u32) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+}
+
+enum Aead {
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(Box<ChaCha20Poly1305>),
+}
+
+impl Aead {
+    fn new(algorithm: EncryptionAlgorithm, key: &EncryptionKey) -> Self {
+        match algorithm {
+            EncryptionAlgorithm::Aes256Gcm => {
+                Aead::Aes256Gcm(Box::new(Aes256Gcm::new(key.as_bytes().into())))
+            }
+            EncryptionAlgorithm::ChaCha20Poly1305 => {
+                Aead::ChaCha20Poly1305(Box::new(ChaCha20Poly1305::new(key.as_bytes().into())))
+            }
+        }
+    }
+    fn seal(&self, nonce: [u8; 12], buffer: &mut [u8]) -> Result<Tag, aes_gcm::Error> {
+        match self {
+            Aead::Aes256Gcm(aead) => aead.encrypt_in_place_detached(&nonce.into(), &[], buffer),
+            Aead::ChaCha20Poly1305(aead) => {
+                aead.encrypt_in_place_detached(&nonce.into(), &[], buffer)
+            }
+        }
+    }
+    fn open(
+        &self,
+        nonce: [u8; 12],
+        buffer: &mut [u8],
+        tag: [u8; SEGMENT_TAG_SIZE],
+    ) -> Result<(), aes_gcm::Error> {
+        match self {
+            Aead::Aes256Gcm(aead) => {
+                aead.decrypt_in_place_detached(&nonce.into(), &[], buffer, &tag.into())
+            }
+            Aead::ChaCha20Poly1305(aead) => {
+                aead.decrypt_in_place_detached(&nonce.into(), &[], buffer, &tag.into())
+            }
+        }
+    }
+}
+
+/// Seals `data_key` under `master_key`, with a random nonce stored in front of it.
+fn wrap_key(
+    algorithm: EncryptionAlgorithm,
+    master_key: &EncryptionKey,
+    data_key: &EncryptionKey,
+) -> ObjectFileResult<[u8; WRAPPED_KEY_SIZE]> {
+    let mut wrapped = [0u8; WRAPPED_KEY_SIZE];
+    let (nonce, sealed) = wrapped.split_at_mut(12);
+    getrandom::fill(nonce).map_err(|err| ObjectFileError::IO(err.into()))?;
+    let nonce: [u8; 12] = (&*nonce).try_into().expect("the nonce is 12 bytes");
+    let (key, tag) = sealed.split_at_mut(32);
+    key.copy_from_slice(data_key.as_bytes());
+    let sealed_tag = Aead::new(algorithm, master_key)
+        .seal(nonce, key)
+        .map_err(|_| std::io::Error::other("the data key could not be wrapped"))?;
+    tag.copy_from_slice(&sealed_tag);
+    Ok(wrapped)
+}
+
+/// The data key inside `wrapped`, or `None` when `master_key` does not open it.
+fn unwrap_key(
+    algorithm: EncryptionAlgorithm,
+    master_key: &EncryptionKey,
+    wrapped: &[u8; WRAPPED_KEY_SIZE],
+) -> Option<EncryptionKey> {
+    let nonce: [u8; 12] = wrapped[..12].try_into().expect("the nonce is 12 bytes");
+    let tag: [u8; SEGMENT_TAG_SIZE] = wrapped[44..].try_into().expect("the tag is 16 bytes");
+    let mut key = EncryptionKey::new(wrapped[12..44].try_into().expect("the key is 32 bytes"));
+    Aead::new(algorithm, master_key)
+        .open(nonce, &mut key.0, tag)
+        .ok()?;
+    Some(key)
+}
+
+/// An object's data key and nonce prefix, ready to seal or open its segments.
+pub(crate) struct SegmentCipher {
+    /// Built from the data key.
+    aead: Aead,
+    algorithm: EncryptionAlgorithm,
+    key_id: String,
+    /// Kept so new content can be sealed under the same master key, by a recompression.
+    master_key: EncryptionKey,
+    /// Kept so it can be wrapped again, by a master key rotation.
+    data_key: EncryptionKey,
+    wrapped_key: [u8; WRAPPED_KEY_SIZE],
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    segment_size: usize,
+}
+
+impl fmt::Debug for SegmentCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SegmentCipher")
+            .field("algorithm", &self.algorithm)
+            .field("key_id", &self.key_id)
+            .field("segment_size", &self.segment_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SegmentCipher {
+    /// A cipher for a new object, with a freshly drawn data key and nonce prefix.
+    pub(crate) fn create(encryption: &ContentEncryption) -> ObjectFileResult<Self> {
+        if encryption.segment_size == 0 {
+            return Err(ObjectFileError::InvalidEncryptionMetadata(
+                "the segment size must not be zero",
+            ));
+        }
+        let data_key = EncryptionKey::generate()?;
+        let wrapped_key = wrap_key(encryption.algorithm, &encryption.key, &data_key)?;
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        getrandom::fill(&mut nonce_prefix).map_err(|err| ObjectFileError::IO(err.into()))?;
+        Ok(Self::with_parts(
+            encryption.algorithm,
+            encryption.key_id.clone(),
+            encryption.key.clone(),
+            data_key,
+            wrapped_key,
+            nonce_prefix,
+            encryption.segment_size as usize,
+        ))
+    }
+
+    /// The cipher an existing object was sealed with, described by its metadata, with the data key
+    /// unwrapped under the master key `keys` has for it.
+    pub(crate) fn from_metadata(
+        metadata: &MetadataMap,
+        keys: &(impl KeyProvider + ?Sized),
+    ) -> ObjectFileResult<Self> {
+        let algorithm = metadata
+            .get_header(&ENCRYPTION)
+            .and_then(ValueType::as_str)
+            .and_then(EncryptionAlgorithm::from_name)
+            .ok_or(ObjectFileError::InvalidEncryptionMetadata(
+                "unknown or missing algorithm",
+            ))?;
+        let key_id = metadata
+            .get_header(&ENCRYPTION_KEY_ID)
+            .and_then(ValueType::as_str)
+            .ok_or(ObjectFileError::InvalidEncryptionMetadata("missing key id"))?;
+        let wrapped_key = match metadata.get_header(&ENCRYPTION_WRAPPED_KEY) {
+            Some(ValueType::Bytes(bytes)) => <[u8; WRAPPED_KEY_SIZE]>::try_from(bytes.as_slice())
+                .map_err(|_| {
+                ObjectFileError::InvalidEncryptionMetadata("malformed wrapped key")
+            })?,
+            _ => {
+                return Err(ObjectFileError::InvalidEncryptionMetadata(
+                    "missing wrapped key",
+                ));
+            }
+        };
+        let nonce_prefix = match metadata.get_header(&ENCRYPTION_NONCE) {
+            Some(ValueType::Bytes(bytes)) => <[u8; NONCE_PREFIX_SIZE]>::try_from(bytes.as_slice())
+                .map_err(|_| ObjectFileError::InvalidEncryptionMetadata("malformed nonce"))?,
+            _ => return Err(ObjectFileError::InvalidEncryptionMetadata("missing nonce")),
+        };
+        let segment_size = match metadata.get_header(&ENCRYPTION_SEGMENT_SIZE) {
+            Some(ValueType::U32(size)) if *size > 0 => *size as usize,
+            _ => {
+                return Err(ObjectFileError::InvalidEncryptionMetadata(
+                    "missing segment size",
+                ));
+            }
+        };
+        let master_key = keys
+            .key(key_id)
+            .ok_or_else(|| ObjectFileError::UnknownEncryptionKey(key_id.to_owned()))?;
+        let data_key = unwrap_key(algorithm, &master_key, &wrapped_key)
+            .ok_or_else(|| ObjectFileError::KeyUnwrapFailed(key_id.to_owned()))?;
+        Ok(Self::with_parts(
+            algorithm,
+            key_id.to_owned(),
+            master_key,
+            data_key,
+            wrapped_key,
+            nonce_prefix,
+            segment_size,
+        ))
+    }
+
+    fn with_parts(
+        algorithm: EncryptionAlgorithm,
+        key_id: String,
+        master_key: EncryptionKey,
+        data_key: EncryptionKey,
+        wrapped_key: [u8; WRAPPED_KEY_SIZE],
+        nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+        segment_size: usize,
+    ) -> Self {
+        Self {
+            aead: Aead::new(algorithm, &data_key),
+            algorithm,
+            key_id,
+            master_key,
+            data_key,
+            wrapped_key,
+            nonce_prefix,
+            segment_size,
+        }
+    }
+
+    /// The same cipher with its data key wrapped under another master key. The content it seals
+    /// and opens is unchanged.
+    pub(crate) fn rewrap(
+        &self,
+        key_id: String,
+        master_key: EncryptionKey,
+    ) -> ObjectFileResult<Self> {
+        let wrapped_key = wrap_key(self.algorithm, &master_key, &self.data_key)?;
+        Ok(Self::with_parts(
+            self.algorithm,
+            key_id,
+            master_key,
+            self.data_key.clone(),
+            wrapped_key,
+            self.nonce_prefix,
+            self.segment_size,
+        ))
+    }
+
+    /// Records the algorithm, key id, wrapped data key, nonce prefix and segment size in the
+    /// object's metadata.
+    pub(crate) fn describe(&self, metadata: &mut MetadataMap) {
+        metadata.insert_header(ENCRYPTION, self.algorithm.name().to_owned().into());
+        metadata.insert_header(ENCRYPTION_KEY_ID, self.key_id.clone().into());
+        metadata.insert_header(ENCRYPTION_WRAPPED_KEY, self.wrapped_key.to_vec().into());
+        metadata.insert_header(ENCRYPTION_NONCE, self.nonce_prefix.to_vec().into());
+        metadata.insert_header(ENCRYPTION_SEGMENT_SIZE, (self.segment_size as u32).into());
+    }
+
+    /// The same algorithm, master key and segment size, for sealing new content. A cipher built
+    /// from it draws its own data key and nonce prefix.
+    pub(crate) fn encryption(&self) -> ContentEncryption {
+        ContentEncryption {
+            algorithm: self.algorithm,
+            key_id: self.key_id.clone(),
+            key: self.master_key.clone(),
+            segment_size: self.segment_size as u32,
+        }
+    }
+
+    /// Plaintext bytes per segment.
+    pub(crate) fn segment_size(&self) -> usize {
+        self.segment_size
+    }
+    /// Stored bytes per full segment, tag included.
+    pub(crate) fn stored_segment_size(&self) -> u64 {
+        (self.segment_size + SEGMENT_TAG_SIZE) as u64
+    }
+
+    /// Number of segments in `stored_length` bytes of content. Never zero for a well-formed object:
+    /// empty content is one empty segment.
+    pub(crate) fn segment_count(&self, stored_length: u64) -> u64 {
+        stored_length.div_ceil(self.stored_segment_size())
+    }
+
+    /// The plaintext length behind `stored_length` bytes of content.
+    pub(crate) fn plaintext_length(&self, stored_length: u64) -> ObjectFileResult<u64> {
+        let tags = self.segment_count(stored_length) * SEGMENT_TAG_SIZE as u64;
+        stored_length
+            .checked_sub(tags)
+            .filter(|_| stored_length > 0)
+            .ok_or(ObjectFileError::InvalidEncryptionMetadata(
+                "the content is too short to hold its segment tags",
+            ))
+    }
+
+    fn nonce(&self, segment: u64, last: bool) -> std::io::Result<[u8; 12]> {
+        let index = u32::try_from(segment)
+            .map_err(|_| std::io::Error::other("encrypted content is limited to 2^32 segments"))?;
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_SIZE..11].copy_from_slice(&index.to_be_bytes());
+        nonce[11] = last as u8;
+        Ok(nonce)
+    }
+
+    /// Encrypts `segment` in place and appends its tag.
+    pub(crate) fn seal(
+        &self,
+        index: u64,
+        last: bool,
+        segment: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        let nonce = self.nonce(index, last)?;
+        let tag = self
+            .aead
+            .seal(nonce, segment)
+            .map_err(|_| std::io::Error::other("content segment could not be encrypted"))?;
+        segment.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    /// Checks the tag on a stored segment and decrypts it in place, leaving only the plaintext.
+    pub(crate) fn open(
+        &self,
+        index: u64,
+        last: bool,
+        segment: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        let failed = || std::io::Error::other(ContentAuthenticationError { segment: index });
+        let tag_start = segment
+            .len()
+            .checked_sub(SEGMENT_TAG_SIZE)
+            .ok_or_else(failed)?;
+        let tag: [u8; SEGMENT_TAG_SIZE] = segment[tag_start..]
+            .try_into()
+            .expect("the slice is exactly one tag long");
+        segment.truncate(tag_start);
+        let nonce = self.nonce(index, last)?;
+        self.aead.open(nonce, segment, tag).map_err(|_| failed())
+    }
+}
+
+/// Seals plaintext into segments as it is written, for [crate::fs::ObjectWriter].
+///
+/// A full segment is held back until more plaintext arrives, because only then is it known not to
+/// be the last one — and the last one is sealed differently.
+pub(crate) struct SegmentSealer {
+    cipher: SegmentCipher,
+    buffer: Vec<u8>,
+    next_segment: u64,
+}
+
+impl SegmentSealer {
+    pub(crate) fn new(cipher: SegmentCipher) -> Self {
+        let buffer = Vec::with_capacity(cipher.segment_size() + SEGMENT_TAG_SIZE);
+        Self {
+            cipher,
+            buffer,
+            next_segment: 0,
+        }
+    }
+
+    pub(crate) fn into_cipher(self) -> SegmentCipher {
+        self.cipher
+    }
+
+    /// Takes as much of `buf` as fits in the current segment. Returns how much was taken and how
+    /// many stored bytes reached `sink`.
+    pub(crate) fn write(
+        &mut self,
+        sink: &mut impl Write,
+        buf: &[u8],
+    ) -> std::io::Result<(usize, u64)> {
+        if buf.is_empty() {
+            return Ok((0, 0));
+        }
+        let mut stored = 0;
+        if self.buffer.len() == self.cipher.segment_size() {
+            stored = self.seal_buffer(sink, false)?;
+        }
+        let taken = (self.cipher.segment_size() - self.buffer.len()).min(buf.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        Ok((taken, stored))
+    }
+
+    /// Seals whatever is buffered as the last segment. Returns the stored bytes written.
+    pub(crate) fn finish(&mut self, sink: &mut impl Write) -> std::io::Result<u64> {
+        self.seal_buffer(sink, true)
+    }
+
+    fn seal_buffer(&mut self, sink: &mut impl Write, last: bool) -> std::io::Result<u64> {
+        self.cipher
+            .seal(self.next_segment, last, &mut self.buffer)?;
+        sink.write_all(&self.buffer)?;
+        let stored = self.buffer.len() as u64;
+        self.buffer.clear();
+        self.next_segment += 1;
+        Ok(stored)
+    }
+}
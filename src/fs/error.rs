@@ -8,8 +8,10 @@ pub type ObjectFileResult<T> = Result<T, ObjectFileError>;
 pub enum ObjectFileError {
     #[error(transparent)]
     Encoding(#[from] EncodingError),
+    /// Not `#[from]`: the conversion unwraps a [ContentAuthenticationError] that had to travel
+    /// through an [std::io::Read] impl, so it surfaces as [ObjectFileError::ContentAuthentication].
     #[error(transparent)]
-    IO(#[from] std::io::Error),
+    IO(std::io::Error),
     /// The metadata and tag sections no longer fit in the space reserved for them, and the caller
     /// asked for an in-place update.
     #[error(
@@ -36,6 +38,100 @@ pub enum ObjectFileError {
     /// Ranged reads seek into the stored bytes, which is meaningless once a codec is in the way.
     #[error("ranged reads are not supported on compressed objects")]
     RangedReadOnCompressed,
+    /// A file range hands out the stored bytes, which for an encrypted object are ciphertext.
+    #[error("the object's content is encrypted, so its stored bytes cannot be sent as content")]
+    EncryptedFileRange,
+    /// Appending adds bytes to the content as stored, which only extends the content when it is
+    /// stored as is.
+    #[error("appending is only supported for content that is neither compressed nor encrypted")]
+    AppendToEncodedContent,
+    /// A versioned object shares its file with the version kept of it, so changing it in place
+    /// would change history too. The atomic updates publish a new version instead.
+    #[error("a versioned object, or a kept version, cannot be updated in place")]
+    InPlaceUpdateOfVersionedObject,
+    /// The content is encrypted and no key has been supplied to read it — or this build, or this
+    /// layer, cannot decrypt at all.
+    #[error("the object's content is encrypted and cannot be read without its key")]
+    ContentLocked,
+    /// The caller's key provider has no key under the id the object names.
+    #[error("no key is available for encryption key id {0:?}")]
+    UnknownEncryptionKey(String),
+    /// The master key the provider returned does not unwrap the object's data key: it is the wrong
+    /// key for that id, or the wrapped key was altered.
+    #[error("the key for encryption key id {0:?} does not unwrap the object's data key")]
+    KeyUnwrapFailed(String),
+    /// A key operation on an object that is not encrypted.
+    #[error("the object's content is not encrypted")]
+    NotEncrypted,
+    #[error("the object's encryption metadata is missing or malformed: {0}")]
+    InvalidEncryptionMetadata(&'static str),
+    /// Encrypted objects are only written through the blocking [crate::fs::ObjectWriter].
+    #[error("the async writer cannot encrypt content")]
+    AsyncEncryptionUnsupported,
+    #[error(transparent)]
+    ContentAuthentication(#[from] ContentAuthenticationError),
+    /// A conditional write found the object in another state than the one it was conditioned on.
+    ///
+    /// `expected` is the generation the write required, or `None` when it required there to be no
+    /// object. `found` is the generation there was, or `None` when there was no object to read one
+    /// from.
+    #[error(
+        "precondition failed: expected {}, found {}",
+        describe_generation(expected),
+        describe_generation(found)
+    )]
+    PreconditionFailed {
+        expected: Option<u64>,
+        found: Option<u64>,
+    },
+    /// A conditional finish from a handle holding a shared lock. The check has to hold the object
+    /// exclusively until the new one is published, which the handle's own lock rules out, and two
+    /// shared holders checking at once would both pass. Lock the handle exclusively first.
+    #[error(
+        "a conditional write needs an exclusive lock, and the object is held under a shared one"
+    )]
+    ConditionalWriteUnderSharedLock,
+    /// A key an [crate::fs::store::ObjectStore] cannot map to a path.
+    #[error("invalid object key {key:?}: {reason}")]
+    InvalidObjectKey { key: String, reason: &'static str },
+    /// The multipart upload was completed, aborted, or never begun.
+    #[error("no multipart upload with id {0:?}")]
+    NoSuchUpload(String),
+    /// A multipart upload's part cannot be stored or assembled as named.
+    #[error("invalid part {number}: {reason}")]
+    InvalidPart { number: u32, reason: &'static str },
+    /// The version was deleted, is a delete marker, or never existed.
+    #[error("no version {0:?} of the object")]
+    NoSuchVersion(String),
+}
+
+fn describe_generation(generation: &Option<u64>) -> String {
+    match generation {
+        Some(generation) => format!("generation {generation}"),
+        None => "no object".to_owned(),
+    }
+}
+
+/// A segment of encrypted content failed to authenticate: the object was altered, truncated, or is
+/// being read with the wrong key.
+///
+/// Distinct from every other failure on purpose — it is the one error that means the bytes on disk
+/// cannot be trusted.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "encrypted content segment {segment} failed authentication: the object was modified or the key is wrong"
+)]
+pub struct ContentAuthenticationError {
+    pub segment: u64,
+}
+
+impl From<std::io::Error> for ObjectFileError {
+    fn from(err: std::io::Error) -> Self {
+        match err.downcast::<ContentAuthenticationError>() {
+            Ok(authentication) => ObjectFileError::ContentAuthentication(authentication),
+            Err(err) => ObjectFileError::IO(err),
+        }
+    }
 }
 
 impl ObjectFileError {
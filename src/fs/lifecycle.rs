@@ -0,0 +1,304 @@
+//! Standard lifecycle metadata, and a sweep that deletes the objects it says have expired.
+//!
+//! An object expires at the [RawDateTime] under [EXPIRES_AT] in its metadata. [STORAGE_CLASS]
+//! labels the tier it belongs to — `STANDARD`, `ARCHIVE` or whatever a deployment uses — which
+//! nothing here interprets beyond letting an [ExpirationPolicy] pick objects by it.
+//!
+//! ```no_run
+//! use tux_io_encoding::{RawDateTime, fs::lifecycle::{self, ExpirationPolicy}};
+//!
+//! let report = lifecycle::sweep("/var/lib/objects", RawDateTime::now(), &ExpirationPolicy::new())?;
+//! println!("expired {} objects", report.expired.len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use http::HeaderName;
+
+use crate::{
+    MetadataMap, RawDateTime, ValueType,
+    fs::{LockMode, ObjectFileResult, TuxObject, object_files, versions},
+};
+
+/// Metadata key holding the [RawDateTime] an object expires at.
+pub const EXPIRES_AT: HeaderName = HeaderName::from_static("x-tuxio-expires-at");
+/// Metadata key holding the storage class an object belongs to, as a string.
+pub const STORAGE_CLASS: HeaderName = HeaderName::from_static("x-tuxio-storage-class");
+
+/// When the object with this metadata expires, if it says.
+pub fn expires_at(metadata: &MetadataMap) -> Option<RawDateTime> {
+    match metadata.get_header(&EXPIRES_AT)? {
+        ValueType::RawDateTime(expires_at) => Some(*expires_at),
+        _ => None,
+    }
+}
+
+/// The storage class the object with this metadata belongs to, if it says.
+pub fn storage_class(metadata: &MetadataMap) -> Option<&str> {
+    metadata.get_header(&STORAGE_CLASS)?.as_str()
+}
+
+/// Which objects a [sweep] expires, and whether it deletes them.
+#[derive(Debug, Clone, Default)]
+pub struct ExpirationPolicy {
+    /// Report what would be deleted, without deleting anything.
+    pub dry_run: bool,
+    /// Only expire objects in one of these storage classes. Empty expires objects in any class, or
+    /// none.
+    pub storage_classes: Vec<String>,
+    /// Expire objects without an [EXPIRES_AT] this long after their file was last modified, as an
+    /// S3 lifecycle rule's `Days` does. Without it they never expire.
+    pub default_lifetime: Option<Duration>,
+}
+
+impl ExpirationPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+    pub fn with_storage_class(mut self, storage_class: impl Into<String>) -> Self {
+        self.storage_classes.push(storage_class.into());
+        self
+    }
+    pub fn with_default_lifetime(mut self, default_lifetime: Duration) -> Self {
+        self.default_lifetime = Some(default_lifetime);
+        self
+    }
+}
+
+/// What a [sweep] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpirationReport {
+    /// Objects deleted — or, on a dry run, that would have been.
+    pub expired: Vec<PathBuf>,
+    /// Total size of the expired objects' files.
+    pub expired_bytes: u64,
+    /// Objects left alone: not expired yet, without an expiry, or outside the policy.
+    pub retained: usize,
+    /// Files that could not be read as objects, left alone.
+    pub unreadable: Vec<PathBuf>,
+}
+
+/// Deletes the objects under `dir`, recursively, that `policy` finds expired at `now`.
+///
+/// Every file [object_files] finds is taken for an object, which leaves out writers' temp files
+/// and anything in a directory whose name starts with a dot — where the versions and multipart
+/// uploads of a [crate::fs::store::ObjectStore] are kept. A versioned object is deleted with a delete marker, as
+/// S3 expires one, so its versions stay retrievable.
+///
+/// Each object is checked again under an exclusive lock before it is deleted, so an update from a
+/// writer that locks — pushing the expiry back, say — is never lost to a sweep that read the
+/// object before it.
+pub fn sweep(
+    dir: impl AsRef<Path>,
+    now: RawDateTime,
+    policy: &ExpirationPolicy,
+) -> ObjectFileResult<ExpirationReport> {
+    let mut report = ExpirationReport::default();
+    for path in object_files(dir)? {
+        match expire(&path, now, policy) {
+            Ok(Some(size)) => {
+                report.expired.push(path);
+                report.expired_bytes += size;
+            }
+            Ok(None) => report.retained += 1,
+            // Deleted or replaced since the directory was read.
+            Err(err) if err.is_not_found() => {}
+            Err(_) => report.unreadable.push(path),
+        }
+    }
+    report.expired.sort();
+    report.unreadable.sort();
+    Ok(report)
+}
+
+/// Deletes the object at `path` if it has expired, returning its size if so.
+fn expire(
+    path: &Path,
+    now: RawDateTime,
+    policy: &ExpirationPolicy,
+) -> ObjectFileResult<Option<u64>> {
+    let mut object = TuxObject::open(path)?;
+    if !is_expired(&object, now, policy)? {
+        return Ok(None);
+    }
+    let size = object.file_size()?;
+    if policy.dry_run {
+        return Ok(Some(size));
+    }
+    // Reloads the object, moving to whatever replaced it since it was opened.
+    object.lock(LockMode::Exclusive)?;
+    if !is_expired(&object, now, policy)? {
+        return Ok(None);
+    }
+    if versions::is_versioned(path) {
+        versions::place_delete_marker(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(Some(object.file_size()?))
+}
+
+fn is_expired(
+    object: &TuxObject,
+    now: RawDateTime,
+    policy: &ExpirationPolicy,
+) -> ObjectFileResult<bool> {
+    let metadata = object.metadata();
+    if !policy.storage_classes.is_empty()
+        && !storage_class(metadata).is_some_and(|class| {
+            policy
+                .storage_classes
+                .iter()
+                .any(|allowed| allowed == class)
+        })
+    {
+        return Ok(false);
+    }
+    let expires_at = match (expires_at(metadata), policy.default_lifetime) {
+        (Some(expires_at), _) => expires_at,
+        (None, Some(lifetime)) => {
+            let modified = std::fs::metadata(object.path())?.modified()?;
+            // A lifetime too long to add to the time never runs out.
+            match modified.checked_add(lifetime) {
+                Some(expires_at) => RawDateTime::from(expires_at),
+                None => return Ok(false),
+            }
+        }
+        (None, None) => return Ok(false),
+    };
+    Ok(expires_at.unix_timestamp() <= now.unix_timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::fs::CreateOptions;
+    use crate::fs::testing::TempDir;
+
+    const NOW: i64 = 1_800_000_000;
+
+    fn write(path: &Path, expires_at: Option<i64>, storage_class: Option<&str>) {
+        let mut metadata = MetadataMap::new();
+        if let Some(expires_at) = expires_at {
+            metadata.insert_header(
+                EXPIRES_AT,
+                RawDateTime::from_unix_timestamp(expires_at, 0).into(),
+            );
+        }
+        if let Some(storage_class) = storage_class {
+            metadata.insert_header(STORAGE_CLASS, storage_class.to_owned().into());
+        }
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut writer =
+            TuxObject::create(path, CreateOptions::new().with_metadata(metadata)).unwrap();
+        writer.write_all(b"content").unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn expired_objects_are_deleted() {
+        let dir = TempDir::new("expire");
+        let expired = dir.0.join("a/expired.tuxio");
+        let later = dir.0.join("a/later.tuxio");
+        let forever = dir.0.join("forever.tuxio");
+        write(&expired, Some(NOW - 1), None);
+        write(&later, Some(NOW + 60), None);
+        write(&forever, None, None);
+        std::fs::write(dir.0.join("not-an-object"), b"junk").unwrap();
+        let now = RawDateTime::from_unix_timestamp(NOW, 0);
+
+        let dry_run = sweep(&dir.0, now, &ExpirationPolicy::new().with_dry_run(true)).unwrap();
+        assert_eq!(dry_run.expired, std::slice::from_ref(&expired));
+        assert!(expired.exists());
+
+        let report = sweep(&dir.0, now, &ExpirationPolicy::new()).unwrap();
+        assert_eq!(
+            report,
+            ExpirationReport {
+                expired: vec![expired.clone()],
+                expired_bytes: dry_run.expired_bytes,
+                retained: 2,
+                unreadable: vec![dir.0.join("not-an-object")],
+            }
+        );
+        assert!(report.expired_bytes > 0);
+        assert!(!expired.exists() && later.exists() && forever.exists());
+
+        // Without an expiry of its own, an object lives as long as the default lifetime.
+        let policy = ExpirationPolicy::new().with_default_lifetime(Duration::from_secs(60));
+        let report = sweep(&dir.0, RawDateTime::now(), &policy).unwrap();
+        assert!(report.expired.is_empty());
+        let report = sweep(
+            &dir.0,
+            RawDateTime::from(std::time::SystemTime::now() + Duration::from_secs(120)),
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(report.expired, [forever]);
+    }
+
+    #[test]
+    fn huge_lifetimes_never_run_out() {
+        let dir = TempDir::new("forever");
+        write(&dir.0.join("object.tuxio"), None, None);
+        for lifetime in [
+            Duration::MAX,
+            Duration::from_secs(u64::MAX),
+            Duration::from_secs(1 << 62),
+        ] {
+            let policy = ExpirationPolicy::new().with_default_lifetime(lifetime);
+            let report = sweep(&dir.0, RawDateTime::now(), &policy).unwrap();
+            assert!(report.expired.is_empty());
+            assert_eq!(report.retained, 1);
+        }
+    }
+
+    #[test]
+    fn policies_pick_objects_by_storage_class() {
+        let dir = TempDir::new("class");
+        let archived = dir.0.join("archived.tuxio");
+        let standard = dir.0.join("standard.tuxio");
+        write(&archived, Some(NOW - 1), Some("ARCHIVE"));
+        write(&standard, Some(NOW - 1), Some("STANDARD"));
+        assert_eq!(
+            storage_class(TuxObject::open(&archived).unwrap().metadata()),
+            Some("ARCHIVE")
+        );
+
+        let policy = ExpirationPolicy::new().with_storage_class("ARCHIVE");
+        let report = sweep(&dir.0, RawDateTime::from_unix_timestamp(NOW, 0), &policy).unwrap();
+        assert_eq!(report.expired, [archived]);
+        assert_eq!(report.retained, 1);
+    }
+
+    #[test]
+    fn versioned_objects_expire_with_a_delete_marker() {
+        let dir = TempDir::new("versioned");
+        let path = dir.0.join("object.tuxio");
+        write(&path, Some(NOW - 1), None);
+        std::fs::create_dir_all(versions::versions_dir(&path)).unwrap();
+
+        let report = sweep(
+            &dir.0,
+            RawDateTime::from_unix_timestamp(NOW, 0),
+            &ExpirationPolicy::new(),
+        )
+        .unwrap();
+        assert_eq!(report.expired, std::slice::from_ref(&path));
+        assert!(!path.exists());
+        let history = versions::list_versions(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history[0].is_delete_marker && history[0].is_latest);
+        assert_eq!(history[1].version_id, versions::NULL_VERSION_ID);
+    }
+}
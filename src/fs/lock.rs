@@ -0,0 +1,99 @@
+//! Advisory locks on object files.
+//!
+//! The locks are `flock` style — taken on the open file, released when it closes — and only
+//! exclude other lockers: a process that never locks can still read and write. Because the atomic
+//! updates publish a new file over the old one, a lock taken while someone else held it can end up
+//! on a file that is no longer at the path; [TuxObject::lock](crate::fs::TuxObject::lock) and its
+//! async twin notice and reopen, and an update made while locked locks the new file before
+//! publishing it, so the lock is never dropped in between.
+
+use std::{
+    fs::{File, Metadata, TryLockError},
+    time::{Duration, Instant},
+};
+
+/// How an object is locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Any number of shared holders at once, but no exclusive one.
+    Shared,
+    /// One holder, and no shared ones.
+    Exclusive,
+}
+
+/// How long to wait for a lock held by someone else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockWait {
+    Forever,
+    /// Not at all.
+    Never,
+    Until(Instant),
+}
+
+impl LockWait {
+    pub(crate) fn timeout(timeout: Duration) -> Self {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => LockWait::Until(deadline),
+            None => LockWait::Forever,
+        }
+    }
+}
+
+/// Longest pause between attempts while polling for a lock.
+pub(crate) const MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Locks `file`, returning whether the lock was taken before `wait` ran out.
+///
+/// Replaces any lock the file already holds, so this also upgrades and downgrades.
+pub(crate) fn lock_file(file: &File, mode: LockMode, wait: LockWait) -> std::io::Result<bool> {
+    let deadline = match wait {
+        LockWait::Forever => {
+            match mode {
+                LockMode::Shared => file.lock_shared()?,
+                LockMode::Exclusive => file.lock()?,
+            }
+            return Ok(true);
+        }
+        LockWait::Never => None,
+        LockWait::Until(deadline) => Some(deadline),
+    };
+    // There is no waiting lock call with a timeout, so poll, backing off up to a limit.
+    let mut interval = Duration::from_millis(1);
+    loop {
+        let attempt = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match attempt {
+            Ok(()) => return Ok(true),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+        let Some(remaining) = deadline.and_then(|deadline| {
+            deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+        }) else {
+            return Ok(false);
+        };
+        std::thread::sleep(interval.min(remaining));
+        interval = (interval * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
+/// Whether two metadata describe the same file, rather than two files that happen to be alike.
+///
+/// Only answerable on Unix; elsewhere the files are assumed to be the same, so a replaced object
+/// goes unnoticed there.
+pub(crate) fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        a.dev() == b.dev() && a.ino() == b.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        true
+    }
+}
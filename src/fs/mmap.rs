@@ -0,0 +1,505 @@
+//! Read-only access to an object through a memory map.
+//!
+//! [MappedObject] hands out the content as a slice of the mapping and reads the metadata and tags
+//! in place: a value is a [ValueView] over its encoded bytes, decoded only when asked. Serving a
+//! small object is then a lookup and a slice, with no `read` call and no copy into a buffer.
+//!
+//! ```no_run
+//! use tux_io_encoding::fs::TuxObject;
+//!
+//! let object = TuxObject::open_mmap("object.tuxio")?;
+//! let content_type = object
+//!     .metadata()
+//!     .get(&http::header::CONTENT_TYPE)
+//!     .and_then(|value| value.as_str());
+//! let body: &[u8] = object.content()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{
+    fs::File,
+    io::Cursor,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use http::HeaderName;
+use memmap2::Mmap;
+
+use crate::{
+    CompressionTypes, ConstTypedObjectType, EncodingError, MetadataMap, ObjectHeader,
+    ReadableObjectType, Tags, ValueType,
+    fs::{HEADER_SIZE, ObjectFileError, ObjectFileResult, SectionLayout, TuxObject},
+};
+
+impl TuxObject {
+    /// Maps an object for reading. See [MappedObject].
+    pub fn open_mmap(path: impl Into<PathBuf>) -> ObjectFileResult<MappedObject> {
+        MappedObject::open(path)
+    }
+}
+
+/// An object mapped into memory, read-only.
+///
+/// Objects are replaced by renaming a new file over them, which leaves the mapped file as it was,
+/// so a mapping keeps showing the object as it was when opened. The exception is
+/// [TuxObject::set_sections_in_place], which writes the prefix of the file itself: a mapping open
+/// meanwhile sees the metadata and tags change under it, and may see them half written, in which
+/// case their values read as `None` or fail to decode. [TuxObject::append] writes the header too,
+/// but the mapping keeps the content length it read when it was opened.
+pub struct MappedObject {
+    map: Mmap,
+    path: PathBuf,
+    header: ObjectHeader,
+    /// Where each metadata entry's key and value lie in the mapping, found once at opening.
+    metadata: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl std::fmt::Debug for MappedObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedObject")
+            .field("path", &self.path)
+            .field("header", &self.header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MappedObject {
+    /// Maps the object at `path`, checking that its sections lie within the file.
+    pub fn open(path: impl Into<PathBuf>) -> ObjectFileResult<Self> {
+        let path = path.into();
+        let file = File::open(&path)?;
+        // SAFETY: the mapping is read-only and the library never truncates an object. The only
+        // writes it makes to a published file in place are to the prefix, by
+        // `set_sections_in_place`, and past the content followed by the header, by `append`; the
+        // prefix is read through bounds-checked slices, which see at worst garbage, and bytes past
+        // the content the header read at opening covers are never looked at. Another process
+        // truncating or writing a mapped object is outside what this can guard against, as with
+        // any mapping.
+        let map = unsafe { Mmap::map(&file)? };
+        let header = ObjectHeader::read_from_bytes(map.get(..HEADER_SIZE).unwrap_or(&map))?;
+
+        let content_end = (header.content_start as u64).checked_add(header.content_length);
+        if header.tags_start as u32 > header.content_start
+            || content_end.is_none_or(|end| end > map.len() as u64)
+        {
+            return Err(EncodingError::UnexpectedEof.into());
+        }
+        let mut object = Self {
+            map,
+            path,
+            header,
+            metadata: Vec::new(),
+        };
+        let metadata_section = object.metadata_range();
+        object.metadata = EntryIter::new(&object.map, metadata_section)
+            .map(|entry| {
+                let (key, value) = entry?;
+                // Header names are ASCII, so checking them once here lets every lookup compare
+                // bytes.
+                HeaderName::from_bytes(&object.map[key.clone()]).map_err(EncodingError::other)?;
+                Ok((key, value))
+            })
+            .collect::<Result<_, EncodingError>>()?;
+        Ok(object)
+    }
+
+    pub fn header(&self) -> &ObjectHeader {
+        &self.header
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// See [ObjectHeader::generation].
+    pub fn generation(&self) -> u64 {
+        self.header.generation
+    }
+    /// Length of the stored content, which for a compressed object is the compressed length.
+    pub fn content_length(&self) -> u64 {
+        self.header.content_length
+    }
+    pub fn compression(&self) -> CompressionTypes {
+        self.header.compression_type
+    }
+    pub fn is_compressed(&self) -> bool {
+        !matches!(self.header.compression_type, CompressionTypes::None(_))
+    }
+    pub fn is_encrypted(&self) -> bool {
+        self.header.is_encrypted()
+    }
+    pub fn layout(&self) -> SectionLayout {
+        SectionLayout {
+            tags_start: self.header.tags_start,
+            content_start: self.header.content_start,
+        }
+    }
+
+    /// The metadata, read in place.
+    pub fn metadata(&self) -> MetadataView<'_> {
+        MetadataView {
+            map: &self.map,
+            entries: &self.metadata,
+        }
+    }
+
+    /// The tags, decoded one at a time as the iterator is advanced.
+    pub fn tags(&self) -> TagIter<'_> {
+        TagIter(EntryIter::new(&self.map, self.tags_range()))
+    }
+
+    /// Number of tags, without looking at any of them.
+    pub fn tag_count(&self) -> ObjectFileResult<u16> {
+        Ok(EntryIter::new(&self.map, self.tags_range()).count)
+    }
+
+    /// Looks up a single tag, stepping over the others without decoding their values.
+    pub fn find_tag(&self, key: &str) -> ObjectFileResult<Option<ValueView<'_>>> {
+        for entry in self.tags() {
+            let (tag, value) = entry?;
+            if tag == key {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decodes every tag into an owned map.
+    pub fn read_tags(&self) -> ObjectFileResult<Tags> {
+        self.tags()
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((key.to_owned(), value.to_value()?))
+            })
+            .collect()
+    }
+
+    /// The content exactly as stored: compressed, or encrypted, if the object is.
+    pub fn stored_content(&self) -> &[u8] {
+        let start = self.header.content_start as usize;
+        &self.map[start..start + self.header.content_length as usize]
+    }
+
+    /// The content, in place.
+    ///
+    /// Only an object stored as it was written can be viewed this way: compressed content fails
+    /// as a ranged read does, and encrypted content with [ObjectFileError::ContentLocked], since
+    /// the mapping cannot be decrypted where it lies. [MappedObject::stored_content] shows either
+    /// as stored.
+    pub fn content(&self) -> ObjectFileResult<&[u8]> {
+        self.content_range(0, None)
+    }
+
+    /// `length` bytes of the content from `offset`, or to the end when `length` is `None`. The
+    /// bounds are checked as [TuxObject::content_range_reader] checks them.
+    pub fn content_range(&self, offset: u64, length: Option<u64>) -> ObjectFileResult<&[u8]> {
+        if self.is_encrypted() {
+            return Err(ObjectFileError::ContentLocked);
+        }
+        if self.is_compressed() {
+            return Err(ObjectFileError::RangedReadOnCompressed);
+        }
+        let content = self.stored_content();
+        let content_length = content.len() as u64;
+        let available =
+            content_length
+                .checked_sub(offset)
+                .ok_or(ObjectFileError::RangeOutOfBounds {
+                    offset,
+                    end: offset,
+                    content_length,
+                })?;
+        let length = length.unwrap_or(available);
+        if length > available {
+            return Err(ObjectFileError::RangeOutOfBounds {
+                offset,
+                end: offset.saturating_add(length),
+                content_length,
+            });
+        }
+        Ok(&content[offset as usize..(offset + length) as usize])
+    }
+
+    fn metadata_range(&self) -> Range<usize> {
+        HEADER_SIZE..(self.header.tags_start as usize).max(HEADER_SIZE)
+    }
+
+    fn tags_range(&self) -> Range<usize> {
+        let start = (self.header.tags_start as usize).max(HEADER_SIZE);
+        start..(self.header.content_start as usize).max(start)
+    }
+}
+
+/// The metadata of a [MappedObject], read in place.
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataView<'a> {
+    map: &'a [u8],
+    entries: &'a [(Range<usize>, Range<usize>)],
+}
+
+impl<'a> MetadataView<'a> {
+    pub fn get(&self, name: &HeaderName) -> Option<ValueView<'a>> {
+        self.entries
+            .iter()
+            .find(|(key, _)| &self.map[key.clone()] == name.as_str().as_bytes())
+            .map(|(_, value)| ValueView(&self.map[value.clone()]))
+    }
+    pub fn contains(&self, name: &HeaderName) -> bool {
+        self.get(name).is_some()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Every entry, in the order they are stored, by header name.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, ValueView<'a>)> + use<'a> {
+        let map = self.map;
+        self.entries.iter().map(move |(key, value)| {
+            // Checked to be a header name, so ASCII, when the object was opened.
+            let key = std::str::from_utf8(&map[key.clone()]).unwrap_or_default();
+            (key, ValueView(&map[value.clone()]))
+        })
+    }
+    /// Decodes every entry into an owned map.
+    pub fn to_map(&self) -> ObjectFileResult<MetadataMap> {
+        let mut metadata = MetadataMap::new();
+        for (key, value) in self.iter() {
+            let name = HeaderName::from_bytes(key.as_bytes()).map_err(EncodingError::other)?;
+            metadata.insert_header(name, value.to_value()?);
+        }
+        Ok(metadata)
+    }
+}
+
+/// The tags of a [MappedObject], each decoded as the iterator reaches it.
+///
+/// A malformed entry ends the iteration with its error.
+#[derive(Debug, Clone)]
+pub struct TagIter<'a>(EntryIter<'a>);
+
+impl<'a> Iterator for TagIter<'a> {
+    type Item = ObjectFileResult<(&'a str, ValueView<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let map = self.0.map;
+        let entry = self.0.next()?.and_then(|(key, value)| {
+            let key = std::str::from_utf8(&map[key]).map_err(EncodingError::other)?;
+            Ok((key, ValueView(&map[value])))
+        });
+        Some(entry.map_err(ObjectFileError::from))
+    }
+}
+
+/// One encoded value — its type key, then the value — borrowed from where it is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueView<'a>(&'a [u8]);
+
+impl<'a> ValueView<'a> {
+    /// The type key in front of the value, which says how the rest is encoded.
+    pub fn type_key(&self) -> u8 {
+        self.0[0]
+    }
+    /// The value as encoded, type key included.
+    pub fn encoded(&self) -> &'a [u8] {
+        self.0
+    }
+    /// The string, when the value is one.
+    pub fn as_str(&self) -> Option<&'a str> {
+        if self.type_key() != String::TYPE_KEY {
+            return None;
+        }
+        std::str::from_utf8(self.length_prefixed()?).ok()
+    }
+    /// The bytes, when the value is a byte array.
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        if self.type_key() != <Vec<u8>>::TYPE_KEY {
+            return None;
+        }
+        self.length_prefixed()
+    }
+    /// Decodes the value.
+    pub fn to_value(&self) -> Result<ValueType, EncodingError> {
+        ValueType::read_from_bytes(self.0)
+    }
+
+    /// What follows the `u16` length of a string or byte array, which the value's size was
+    /// measured from when the view was made. `None` when the value is too short to have one, as it
+    /// can be when the prefix was rewritten in place after the view's range was found.
+    fn length_prefixed(&self) -> Option<&'a [u8]> {
+        self.0.get(3..)
+    }
+}
+
+/// Walks the entries of an encoded map — a `u16` count, then each key as a length-prefixed string
+/// and its value — yielding where each key's bytes and each value lie.
+#[derive(Debug, Clone)]
+struct EntryIter<'a> {
+    map: &'a [u8],
+    position: usize,
+    end: usize,
+    count: u16,
+    remaining: u16,
+}
+
+impl<'a> EntryIter<'a> {
+    /// An empty section, as an object with no tags has, holds no entries.
+    fn new(map: &'a [u8], section: Range<usize>) -> Self {
+        let count = map
+            .get(section.start..section.start + 2)
+            .filter(|_| section.len() >= 2)
+            .map_or(0, |count| u16::from_le_bytes([count[0], count[1]]));
+        Self {
+            map,
+            position: section.start + 2,
+            end: section.end,
+            count,
+            remaining: count,
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<Range<usize>, EncodingError> {
+        let start = self.position;
+        let end = start
+            .checked_add(length)
+            .filter(|end| *end <= self.end)
+            .ok_or(EncodingError::UnexpectedEof)?;
+        self.position = end;
+        Ok(start..end)
+    }
+
+    fn next_entry(&mut self) -> Result<(Range<usize>, Range<usize>), EncodingError> {
+        let length = self.take(2)?;
+        let length = u16::from_le_bytes([self.map[length.start], self.map[length.start + 1]]);
+        let key = self.take(length as usize)?;
+        let value_size =
+            ValueType::read_size(&mut Cursor::new(&self.map[self.position..self.end]))?;
+        let value = self.take(value_size)?;
+        Ok((key, value))
+    }
+}
+
+impl Iterator for EntryIter<'_> {
+    type Item = Result<(Range<usize>, Range<usize>), EncodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self.next_entry();
+        // Nothing after a malformed entry can be found.
+        self.remaining = if entry.is_ok() { self.remaining - 1 } else { 0 };
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{CONTENT_TYPE, ETAG};
+
+    use super::*;
+    use crate::fs::testing::{TempDir, write_object};
+    use crate::fs::{CreateOptions, LayoutOptions};
+
+    #[test]
+    fn sections_are_read_in_place() {
+        let dir = TempDir::new("sections");
+        let path = dir.0.join("object.tuxio");
+        let mut metadata = MetadataMap::new();
+        metadata.insert_header(CONTENT_TYPE, "text/plain".to_owned().into());
+        metadata.insert_header(ETAG, 7u32.into());
+        let mut tags = Tags::new();
+        tags.insert("colour".to_owned(), "blue".to_owned().into());
+        tags.insert("raw".to_owned(), vec![1u8, 2, 3].into());
+        write_object(
+            &path,
+            CreateOptions::new()
+                .with_metadata(metadata.clone())
+                .with_tags(tags.clone()),
+            b"Hello, world!",
+        );
+
+        let object = TuxObject::open_mmap(&path).unwrap();
+        assert_eq!(object.content().unwrap(), b"Hello, world!");
+        assert_eq!(object.content_range(7, Some(5)).unwrap(), b"world");
+        assert!(matches!(
+            object.content_range(7, Some(7)),
+            Err(ObjectFileError::RangeOutOfBounds { .. })
+        ));
+
+        let view = object.metadata();
+        assert_eq!(view.len(), 2);
+        assert_eq!(
+            view.get(&CONTENT_TYPE).and_then(|value| value.as_str()),
+            Some("text/plain")
+        );
+        assert_eq!(view.get(&ETAG).unwrap().as_str(), None);
+        assert_eq!(view.get(&ETAG).unwrap().to_value().unwrap(), 7u32.into());
+        assert_eq!(view.to_map().unwrap(), metadata);
+
+        assert_eq!(object.tag_count().unwrap(), 2);
+        assert_eq!(
+            object.find_tag("raw").unwrap().unwrap().as_bytes(),
+            Some(&[1u8, 2, 3][..])
+        );
+        assert_eq!(object.find_tag("missing").unwrap(), None);
+        assert_eq!(object.read_tags().unwrap(), tags);
+    }
+
+    #[test]
+    fn an_empty_object_maps() {
+        let dir = TempDir::new("empty");
+        let path = dir.0.join("object.tuxio");
+        write_object(
+            &path,
+            CreateOptions::new().with_layout(LayoutOptions::packed()),
+            b"",
+        );
+
+        let object = TuxObject::open_mmap(&path).unwrap();
+        assert!(object.metadata().is_empty());
+        assert_eq!(object.tags().count(), 0);
+        assert_eq!(object.content().unwrap(), b"");
+    }
+
+    #[test]
+    fn truncated_objects_are_refused() {
+        let dir = TempDir::new("truncated");
+        let path = dir.0.join("object.tuxio");
+        write_object(&path, CreateOptions::new(), b"Hello, world!");
+        let length = std::fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(length - 1)
+            .unwrap();
+
+        assert!(matches!(
+            TuxObject::open_mmap(&path),
+            Err(ObjectFileError::Encoding(EncodingError::UnexpectedEof))
+        ));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_content_is_only_available_as_stored() {
+        let dir = TempDir::new("compressed");
+        let path = dir.0.join("object.tuxio");
+        let gzip = CompressionTypes::Gzip(crate::compression_types::GzipCompressionType(6));
+        let mut writer =
+            TuxObject::create(&path, CreateOptions::new().with_compression(gzip)).unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        std::io::Write::write_all(&mut encoder, &b"tuxio ".repeat(100)).unwrap();
+        encoder.finish().unwrap();
+        let object = writer.finish().unwrap();
+        let stored = object.content_length();
+
+        let object = TuxObject::open_mmap(&path).unwrap();
+        assert!(matches!(
+            object.content(),
+            Err(ObjectFileError::RangedReadOnCompressed)
+        ));
+        assert_eq!(object.stored_content().len() as u64, stored);
+    }
+}
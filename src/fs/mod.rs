@@ -35,34 +35,67 @@
 //! The padding after each section is what keeps metadata edits cheap: as long as the new sections
 //! fit in the reserved prefix the content never moves. [TuxObject::set_metadata] and friends are
 //! atomic (rewrite plus rename); [TuxObject::set_sections_in_place] trades that safety for speed.
+//!
+//! [store::ObjectStore] addresses a directory of objects by key rather than by path, and
+//! [SharedTuxObject] reads at offsets rather than through the file's cursor, so one open handle can
+//! serve many threads at once. [versions] keeps what an overwrite replaces, for objects that opt in.
 
+#[cfg(feature = "tokio")]
+mod async_codec;
 #[cfg(feature = "tokio")]
 mod async_io;
 mod compression;
+mod encryption;
 mod error;
 mod layout;
+pub mod lifecycle;
+mod lock;
+#[cfg(feature = "mmap")]
+mod mmap;
 mod object;
 mod reader;
+mod shared;
+mod sniff;
+pub mod store;
+mod sweep;
+#[cfg(test)]
+mod testing;
+mod transfer;
+pub mod versions;
+mod walk;
 mod writer;
 
+#[cfg(feature = "tokio")]
+pub use async_codec::*;
 #[cfg(feature = "tokio")]
 pub use async_io::*;
 pub use compression::*;
+pub use encryption::*;
 pub use error::*;
 pub use layout::*;
+pub use lock::LockMode;
+#[cfg(feature = "mmap")]
+pub use mmap::*;
 pub use object::*;
 pub use reader::*;
+pub use shared::{SharedContentReader, SharedTuxObject};
+pub use sniff::*;
+pub use sweep::*;
+pub use transfer::*;
+pub use walk::*;
 pub use writer::*;
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Read, Write};
+    use std::io::{Read, Seek, SeekFrom, Write};
 
     use http::header::{CONTENT_TYPE, ETAG, LAST_MODIFIED};
 
     use super::*;
+    use crate::fs::testing::TempDir;
     use crate::{
-        MetadataMap, RawDate, RawDateTime, RawTime, RawTimeZone, Tags, TuxIOType, ValueType,
+        MetadataMap, ObjectHeader, RawDate, RawDateTime, RawTime, RawTimeZone, Tags, TuxIOType,
+        ValueType,
     };
 
     /// A fixed timestamp, so the tests do not need the `chrono` feature to build one.
@@ -81,32 +114,6 @@ mod tests {
         }
     }
 
-    /// A scratch directory that removes itself, so these tests need no extra dependency.
-    struct TempDir(std::path::PathBuf);
-    impl TempDir {
-        fn new(name: &str) -> Self {
-            let path = std::env::temp_dir().join(format!(
-                "tux-io-encoding-fs-{}-{}-{}",
-                name,
-                std::process::id(),
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos()
-            ));
-            std::fs::create_dir_all(&path).unwrap();
-            TempDir(path)
-        }
-        fn join(&self, name: &str) -> std::path::PathBuf {
-            self.0.join(name)
-        }
-    }
-    impl Drop for TempDir {
-        fn drop(&mut self) {
-            let _ = std::fs::remove_dir_all(&self.0);
-        }
-    }
-
     fn sample_metadata() -> MetadataMap {
         let mut metadata = MetadataMap::new();
         metadata.insert(CONTENT_TYPE.into(), "text/plain".to_owned().into());
@@ -371,6 +378,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn copies_replace_sections_and_keep_the_content() {
+        let dir = TempDir::new("copy-to");
+        let path = dir.join("object.tuxio");
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i * 7) as u8).collect();
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_metadata(sample_metadata())
+                .with_tags(sample_tags()),
+        )
+        .unwrap();
+        writer.write_all(&content).unwrap();
+        writer.finish().unwrap();
+
+        let mut source = TuxObject::open(&path).unwrap();
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            CONTENT_TYPE.into(),
+            "application/octet-stream".to_owned().into(),
+        );
+
+        // The new sections fit, so the content stays where it was.
+        let mut copy = source
+            .copy_to(dir.join("copy.tuxio"), Some(metadata.clone()), None)
+            .unwrap();
+        assert_eq!(copy.layout(), source.layout());
+        assert_eq!(copy.metadata(), &metadata);
+        assert_eq!(copy.read_tags().unwrap(), sample_tags());
+        assert_eq!(copy.read_content_to_vec().unwrap(), content);
+        assert_eq!(copy.file_size().unwrap(), source.file_size().unwrap());
+
+        // These do not, so the content moves back, copied through.
+        metadata.insert(
+            http::header::CONTENT_DISPOSITION.into(),
+            "attachment; filename=\"a-fairly-long-file-name.txt\""
+                .repeat(20)
+                .into(),
+        );
+        let mut moved = source
+            .copy_to(
+                dir.join("moved.tuxio"),
+                Some(metadata.clone()),
+                Some(Tags::new()),
+            )
+            .unwrap();
+        assert!(moved.layout().content_start > source.layout().content_start);
+        assert_eq!(moved.metadata(), &metadata);
+        assert_eq!(moved.read_tags().unwrap(), Tags::new());
+        assert_eq!(moved.read_content_to_vec().unwrap(), content);
+
+        // Replacing an object advances its generation, and the source is left alone.
+        let replaced = source.copy_to(dir.join("copy.tuxio"), None, None).unwrap();
+        assert_eq!(replaced.generation(), 2);
+        assert_eq!(replaced.metadata(), &sample_metadata());
+        assert_eq!(
+            TuxObject::open(&path).unwrap().metadata(),
+            &sample_metadata()
+        );
+    }
+
     #[test]
     fn tags_can_shrink() {
         // Shrinking is what the older store implementation rejected outright.
@@ -631,6 +699,1295 @@ mod tests {
         assert_eq!(object.read_content_to_vec().unwrap(), content);
     }
 
+    /// Enough content for several batches of parallel gzip blocks, ending on a partial block.
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn parallel_gzip_round_trips() {
+        let dir = TempDir::new("parallel-gzip");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(PARALLEL_GZIP_BLOCK);
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(crate::CompressionTypes::Gzip(
+                    crate::compression_types::GzipCompressionType(6),
+                ))
+                .with_compression_workers(4),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        assert!(matches!(encoder, ContentEncoder::ParallelGzip { .. }));
+        // Odd-sized writes, so blocks fill across write boundaries.
+        for chunk in content.chunks(100_003) {
+            encoder.write_all(chunk).unwrap();
+        }
+        assert_eq!(encoder.finish().unwrap(), content.len() as u64);
+        let object = writer.finish().unwrap();
+        assert!(object.content_length() < content.len() as u64);
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn parallel_gzip_handles_empty_content() {
+        let dir = TempDir::new("parallel-gzip-empty");
+        let path = dir.join("object.tuxio");
+
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(crate::CompressionTypes::Gzip(
+                    crate::compression_types::GzipCompressionType(6),
+                ))
+                .with_compression_workers(2),
+        )
+        .unwrap();
+        writer.content_encoder().unwrap().finish().unwrap();
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert!(object.read_content_to_vec().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn multithreaded_zstd_round_trips() {
+        let dir = TempDir::new("zstdmt");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(1024 * 1024);
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(crate::CompressionTypes::ZSTD(
+                    crate::compression_types::ZStdCompressionType(3),
+                ))
+                .with_compression_workers(4),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        let object = writer.finish().unwrap();
+        assert!(object.content_length() < content.len() as u64);
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    /// Bytes that no codec can shrink, standing in for a JPEG or a zip archive.
+    #[cfg(any(feature = "zstd", feature = "gzip"))]
+    fn incompressible(length: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[cfg(any(feature = "zstd", feature = "gzip"))]
+    fn auto_policy() -> CompressionPolicy {
+        CompressionPolicy::Auto {
+            min_ratio: 1.1,
+            sample_bytes: 16 * 1024,
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn auto_policy_keeps_the_codec_for_compressible_content() {
+        let dir = TempDir::new("auto-keep");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(16 * 1024);
+        let compression =
+            crate::CompressionTypes::ZSTD(crate::compression_types::ZStdCompressionType(3));
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(compression)
+                .with_compression_policy(auto_policy()),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        assert_eq!(encoder.finish().unwrap(), content.len() as u64);
+        assert_eq!(writer.compression(), compression);
+        let object = writer.finish().unwrap();
+        assert!(object.content_length() < content.len() as u64);
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.compression(), compression);
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn auto_policy_stores_incompressible_content_uncompressed() {
+        let dir = TempDir::new("auto-fallback");
+        let path = dir.join("object.tuxio");
+
+        let content = incompressible(64 * 1024);
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(crate::CompressionTypes::ZSTD(
+                    crate::compression_types::ZStdCompressionType(3),
+                ))
+                .with_compression_policy(auto_policy()),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        // Several small writes, so the sample fills part way through one of them.
+        for chunk in content.chunks(5000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        encoder.finish().unwrap();
+        assert!(matches!(
+            writer.compression(),
+            crate::CompressionTypes::None(_)
+        ));
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert!(!object.is_compressed());
+        assert_eq!(object.content_length(), content.len() as u64);
+        // Stored as is, so there is no separate uncompressed length to record.
+        assert!(object.metadata().get_header(&UNCOMPRESSED_LENGTH).is_none());
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+        // And, being uncompressed, it supports ranged reads again.
+        assert!(object.content_range_reader(0, Some(8)).is_ok());
+    }
+
+    /// Content shorter than the sample never fills it, so the decision waits for `finish`.
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn auto_policy_decides_short_content_at_finish() {
+        let dir = TempDir::new("auto-short");
+
+        let compression =
+            crate::CompressionTypes::Gzip(crate::compression_types::GzipCompressionType(6));
+        let write = |name: &str, content: &[u8]| {
+            let path = dir.join(name);
+            let mut writer = TuxObject::create(
+                &path,
+                CreateOptions::new()
+                    .with_compression(compression)
+                    .with_compression_policy(auto_policy()),
+            )
+            .unwrap();
+            let mut encoder = writer.content_encoder().unwrap();
+            encoder.write_all(content).unwrap();
+            encoder.finish().unwrap();
+            writer.finish().unwrap();
+            TuxObject::open(&path).unwrap()
+        };
+
+        let mut compressible = write("compressible.tuxio", &b"ab".repeat(1024));
+        assert_eq!(compressible.compression(), compression);
+        assert_eq!(
+            compressible.read_content_to_vec().unwrap(),
+            b"ab".repeat(1024)
+        );
+
+        let mut random = write("random.tuxio", &incompressible(1024));
+        assert!(!random.is_compressed());
+        assert_eq!(random.read_content_to_vec().unwrap(), incompressible(1024));
+
+        let mut empty = write("empty.tuxio", b"");
+        assert!(!empty.is_compressed());
+        assert!(empty.read_content_to_vec().unwrap().is_empty());
+    }
+
+    /// A metadata rewrite carries the content across in its stored form, which for a compressed
+    /// object is compressed bytes — they must not be refused as raw writes, nor compressed again.
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_objects_survive_a_metadata_rewrite() {
+        let dir = TempDir::new("compressed-rewrite");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(4096);
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(crate::CompressionTypes::ZSTD(
+                    crate::compression_types::ZStdCompressionType(3),
+                ))
+                .with_layout(LayoutOptions::packed()),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        // Metadata that outgrows the packed prefix forces `finish` down its rewriting path too.
+        writer
+            .metadata_mut()
+            .insert(CONTENT_TYPE.into(), "text/plain".to_owned().into());
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+        object
+            .modify_metadata(|metadata| {
+                metadata.insert(ETAG.into(), vec![3u8; 16].into());
+            })
+            .unwrap();
+        assert!(object.is_compressed());
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    #[test]
+    fn replaced_content_keeps_the_sections() {
+        let dir = TempDir::new("replace-content");
+        let path = dir.join("object.tuxio");
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_metadata(sample_metadata())
+                .with_tags(sample_tags()),
+        )
+        .unwrap();
+        writer.write_all(b"old content").unwrap();
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let layout = object.layout();
+        object.replace_content(&b"new, longer content"[..]).unwrap();
+        assert_eq!(object.generation(), 2);
+        assert_eq!(object.layout(), layout);
+        assert_eq!(
+            object.read_content_to_vec().unwrap(),
+            b"new, longer content"
+        );
+
+        object
+            .replace_content_with(&b"refreshed"[..], |writer| {
+                let etag = format!("\"{}\"", writer.content_length());
+                writer.metadata_mut().insert_header(ETAG, etag.into());
+            })
+            .unwrap();
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), b"refreshed");
+        assert_eq!(
+            object.metadata().get_header(&ETAG),
+            Some(&"\"9\"".to_owned().into())
+        );
+        assert_eq!(
+            object.metadata().get_header(&CONTENT_TYPE),
+            sample_metadata().get_header(&CONTENT_TYPE)
+        );
+        assert_eq!(object.read_tags().unwrap(), sample_tags());
+        assert!(matches!(
+            object.replace_content(&b"read-only"[..]),
+            Err(ObjectFileError::ReadOnly(_))
+        ));
+    }
+
+    #[test]
+    fn appends_extend_the_content_in_place() {
+        let dir = TempDir::new("append");
+        let path = dir.join("object.tuxio");
+        let mut writer =
+            TuxObject::create(&path, CreateOptions::new().with_metadata(sample_metadata()))
+                .unwrap();
+        writer.write_all(b"line 1\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let layout = object.layout();
+        assert_eq!(object.append(b"line 2\n").unwrap(), 14);
+        // What a crash between the two writes of an append leaves behind: bytes past the content
+        // that the header never came to cover. The next append writes over them.
+        {
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(b"torn").unwrap();
+        }
+        assert_eq!(object.append(b"line 3\n").unwrap(), 21);
+        assert_eq!(object.generation(), 3);
+        assert_eq!(object.layout(), layout);
+        assert_eq!(object.lock_mode(), None);
+
+        let mut reopened = TuxObject::open(&path).unwrap();
+        assert_eq!(
+            reopened.read_content_to_vec().unwrap(),
+            b"line 1\nline 2\nline 3\n"
+        );
+        assert_eq!(reopened.metadata(), &sample_metadata());
+        assert!(matches!(
+            reopened.append(b"read-only"),
+            Err(ObjectFileError::ReadOnly(_))
+        ));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_content_cannot_be_appended_to() {
+        let dir = TempDir::new("append-compressed");
+        let path = dir.join("object.tuxio");
+        let zstd = crate::CompressionTypes::ZSTD(crate::compression_types::ZStdCompressionType(3));
+        let mut writer =
+            TuxObject::create(&path, CreateOptions::new().with_compression(zstd)).unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(b"compressed").unwrap();
+        encoder.finish().unwrap();
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        assert!(matches!(
+            object.append(b"more"),
+            Err(ObjectFileError::AppendToEncodedContent)
+        ));
+        assert_eq!(object.read_content_to_vec().unwrap(), b"compressed");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn content_writers_keep_the_compression() {
+        let dir = TempDir::new("content-writer");
+        let path = dir.join("object.tuxio");
+        let zstd = crate::CompressionTypes::ZSTD(crate::compression_types::ZStdCompressionType(3));
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_metadata(sample_metadata())
+                .with_compression(zstd),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&b"old ".repeat(1000)).unwrap();
+        encoder.finish().unwrap();
+        writer.finish().unwrap();
+
+        let content = b"new ".repeat(3000);
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let mut writer = object.content_writer().unwrap();
+        assert_eq!(writer.compression(), zstd);
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        let mut replaced = writer.finish().unwrap();
+
+        assert_eq!(replaced.compression(), zstd);
+        assert_eq!(replaced.read_content_to_vec().unwrap(), content);
+        assert_eq!(
+            replaced.metadata().get_header(&UNCOMPRESSED_LENGTH),
+            Some(&ValueType::U64(content.len() as u64))
+        );
+        // The handle the writer came from still reads what it opened.
+        assert_eq!(object.read_content_to_vec().unwrap(), b"old ".repeat(1000));
+    }
+
+    #[cfg(all(feature = "zstd", feature = "gzip"))]
+    #[test]
+    fn recompress_transcodes_and_preserves_the_sections() {
+        let dir = TempDir::new("recompress");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(4096);
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_metadata(sample_metadata())
+                .with_tags(sample_tags())
+                .with_compression(crate::CompressionTypes::Gzip(
+                    crate::compression_types::GzipCompressionType(1),
+                )),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let zstd = crate::CompressionTypes::ZSTD(crate::compression_types::ZStdCompressionType(19));
+        object.recompress(zstd).unwrap();
+        assert_eq!(object.compression(), zstd);
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.compression(), zstd);
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+        assert_eq!(
+            object.metadata().get_header(&UNCOMPRESSED_LENGTH),
+            Some(&ValueType::U64(content.len() as u64))
+        );
+        assert_eq!(
+            object.metadata().get_header(&CONTENT_TYPE),
+            sample_metadata().get_header(&CONTENT_TYPE)
+        );
+        assert_eq!(object.read_tags().unwrap(), sample_tags());
+    }
+
+    /// Recompressing to no codec stores the content as is, and the uncompressed length goes with
+    /// the codec: the stored length is the real one again.
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn recompress_can_store_content_uncompressed() {
+        let dir = TempDir::new("decompress");
+        let path = dir.join("object.tuxio");
+
+        let content = b"tuxio ".repeat(1024);
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new().with_compression(crate::CompressionTypes::ZSTD(
+                crate::compression_types::ZStdCompressionType(3),
+            )),
+        )
+        .unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        object
+            .recompress(crate::CompressionTypes::default())
+            .unwrap();
+        assert!(!object.is_compressed());
+        assert_eq!(object.content_length(), content.len() as u64);
+        assert!(object.metadata().get_header(&UNCOMPRESSED_LENGTH).is_none());
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    fn write_plain(path: &std::path::Path, content: &[u8]) {
+        let mut writer = TuxObject::create(path, CreateOptions::new()).unwrap();
+        writer.write_all(content).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn content_readers_seek_within_their_range() {
+        let dir = TempDir::new("seek");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"0123456789");
+
+        let mut object = TuxObject::open(&path).unwrap();
+        let mut reader = object.stored_content_reader().unwrap();
+        let mut buf = [0u8; 3];
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 7);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"789");
+        assert_eq!(reader.seek(SeekFrom::Current(-8)).unwrap(), 2);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"234");
+        assert_eq!(reader.stream_position().unwrap(), 5);
+        // Past the end stops at the end rather than reading what follows the content.
+        assert_eq!(reader.seek(SeekFrom::Start(100)).unwrap(), 10);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-11)).is_err());
+
+        // A range keeps content offsets, and stays inside the range.
+        let mut ranged = object.content_range_reader(3, Some(4)).unwrap();
+        assert_eq!(ranged.seek(SeekFrom::Start(0)).unwrap(), 3);
+        assert_eq!(ranged.seek(SeekFrom::Start(5)).unwrap(), 5);
+        assert_eq!(ranged.remaining(), 2);
+        let mut rest = Vec::new();
+        ranged.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"56");
+        assert_eq!(ranged.seek(SeekFrom::End(-1)).unwrap(), 6);
+    }
+
+    /// Locks belong to the open file, so two handles in one process contend like two processes.
+    #[test]
+    fn locks_exclude_other_handles() {
+        let dir = TempDir::new("lock");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"locked");
+
+        let mut first = TuxObject::open(&path).unwrap();
+        let mut second = TuxObject::open(&path).unwrap();
+        first.lock(LockMode::Shared).unwrap();
+        assert!(second.try_lock(LockMode::Shared).unwrap());
+        assert!(!first.try_lock(LockMode::Exclusive).unwrap());
+        assert_eq!(first.lock_mode(), Some(LockMode::Shared));
+
+        second.release_lock().unwrap();
+        assert_eq!(second.lock_mode(), None);
+        assert!(first.try_lock(LockMode::Exclusive).unwrap());
+        let started = std::time::Instant::now();
+        assert!(
+            !second
+                .lock_timeout(LockMode::Shared, std::time::Duration::from_millis(30))
+                .unwrap()
+        );
+        assert!(started.elapsed() >= std::time::Duration::from_millis(30));
+
+        drop(first);
+        assert!(second.try_lock(LockMode::Exclusive).unwrap());
+    }
+
+    /// An atomic update publishes a new file, which has to come out already locked.
+    #[test]
+    fn locks_are_kept_across_rewrites() {
+        let dir = TempDir::new("lock-rewrite");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"locked");
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        object.lock(LockMode::Exclusive).unwrap();
+        object.set_metadata(sample_metadata()).unwrap();
+        assert_eq!(object.lock_mode(), Some(LockMode::Exclusive));
+
+        let mut other = TuxObject::open(&path).unwrap();
+        assert!(!other.try_lock(LockMode::Shared).unwrap());
+        object.release_lock().unwrap();
+        assert!(other.try_lock(LockMode::Shared).unwrap());
+    }
+
+    /// A handle opened before an update locks the file the update replaced, and has to move on to
+    /// the new one.
+    #[test]
+    fn waiting_lockers_follow_a_replaced_object() {
+        let dir = TempDir::new("lock-replaced");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"locked");
+
+        let mut holder = TuxObject::open_writable(&path).unwrap();
+        holder.lock(LockMode::Exclusive).unwrap();
+        let mut waiter = TuxObject::open(&path).unwrap();
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(move || {
+                waiter.lock(LockMode::Shared).unwrap();
+                waiter
+            });
+            holder.set_metadata(sample_metadata()).unwrap();
+            drop(holder);
+
+            let mut waiter = waiting.join().unwrap();
+            assert_eq!(waiter.metadata(), &sample_metadata());
+            assert_eq!(waiter.read_content_to_vec().unwrap(), b"locked");
+        });
+    }
+
+    #[test]
+    fn conditional_updates_check_the_current_metadata() {
+        let dir = TempDir::new("set-metadata-if");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"content");
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let mut stale = TuxObject::open_writable(&path).unwrap();
+        let etag = |value: &str| {
+            let mut metadata = MetadataMap::new();
+            metadata.insert_header(ETAG, value.to_owned().into());
+            metadata
+        };
+        let matches = |expected: &'static str| {
+            move |_: &ObjectHeader, metadata: &MetadataMap| {
+                metadata.get_header(&ETAG).and_then(ValueType::as_str) == Some(expected)
+            }
+        };
+
+        assert!(
+            !object
+                .set_metadata_if(etag("\"2\""), matches("\"1\""))
+                .unwrap()
+        );
+        object.set_metadata(etag("\"1\"")).unwrap();
+        assert!(
+            object
+                .set_metadata_if(etag("\"2\""), matches("\"1\""))
+                .unwrap()
+        );
+        assert_eq!(object.lock_mode(), None);
+
+        // Another handle's view is refreshed under the lock, so it cannot act on a stale read.
+        assert!(
+            !stale
+                .set_metadata_if(etag("\"3\""), matches("\"1\""))
+                .unwrap()
+        );
+        assert_eq!(
+            stale
+                .metadata()
+                .get_header(&ETAG)
+                .and_then(ValueType::as_str),
+            Some("\"2\"")
+        );
+
+        stale.lock(LockMode::Shared).unwrap();
+        assert!(
+            stale
+                .set_metadata_if(etag("\"3\""), matches("\"2\""))
+                .unwrap()
+        );
+        assert_eq!(stale.lock_mode(), Some(LockMode::Shared));
+    }
+
+    #[test]
+    fn every_write_advances_the_generation() {
+        let dir = TempDir::new("generation");
+        let path = dir.join("object.tuxio");
+        let mut writer = TuxObject::create(&path, CreateOptions::new()).unwrap();
+        writer.write_all(b"first").unwrap();
+        let mut object = writer.finish().unwrap();
+        assert_eq!(object.generation(), 1);
+
+        object.set_metadata(sample_metadata()).unwrap();
+        assert_eq!(object.generation(), 2);
+        object
+            .set_sections_in_place(MetadataMap::new(), sample_tags())
+            .unwrap();
+        assert_eq!(object.generation(), 3);
+        assert_eq!(TuxObject::open(&path).unwrap().generation(), 3);
+
+        // A whole new object carries on from the one it replaces.
+        write_plain(&path, b"second");
+        assert_eq!(TuxObject::open(&path).unwrap().generation(), 4);
+    }
+
+    #[test]
+    fn conditional_finishes_check_the_object_they_replace() {
+        let dir = TempDir::new("conditional-finish");
+        let path = dir.join("object.tuxio");
+        let writer = |content: &[u8]| {
+            let mut writer = TuxObject::create(&path, CreateOptions::new()).unwrap();
+            writer.write_all(content).unwrap();
+            writer
+        };
+
+        assert!(matches!(
+            writer(b"missing").finish_if_generation(1),
+            Err(ObjectFileError::PreconditionFailed {
+                expected: Some(1),
+                found: None
+            })
+        ));
+        assert_eq!(writer(b"first").finish_if_absent().unwrap().generation(), 1);
+        assert!(matches!(
+            writer(b"second").finish_if_absent(),
+            Err(ObjectFileError::PreconditionFailed {
+                expected: None,
+                found: Some(1)
+            })
+        ));
+        assert!(matches!(
+            writer(b"second").finish_if_generation(2),
+            Err(ObjectFileError::PreconditionFailed {
+                expected: Some(2),
+                found: Some(1)
+            })
+        ));
+        assert_eq!(
+            writer(b"second")
+                .finish_if_generation(1)
+                .unwrap()
+                .generation(),
+            2
+        );
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), b"second");
+        // The failed writes cleaned up after themselves.
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+
+    /// A writer from a locked handle checks the generation through that handle's lock, rather
+    /// than waiting forever on it from a second open of the file.
+    #[test]
+    fn conditional_finishes_from_a_locked_handle_do_not_wait_on_its_lock() {
+        let dir = TempDir::new("conditional-locked");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"first");
+
+        let finished = std::sync::mpsc::channel();
+        let thread_path = path.clone();
+        std::thread::spawn(move || {
+            let finish = |expected: u64| {
+                let mut object = TuxObject::open_writable(&thread_path).unwrap();
+                object.lock(LockMode::Exclusive).unwrap();
+                let mut writer = object.content_writer().unwrap();
+                writer.write_all(b"second").unwrap();
+                writer
+                    .finish_if_generation(expected)
+                    .map(|object| object.generation())
+            };
+            let stale = finish(2);
+            let fresh = finish(1);
+            finished.0.send((stale, fresh)).unwrap();
+        });
+        let (stale, fresh) = finished
+            .1
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("the conditional finish waited on its own lock");
+        assert!(matches!(
+            stale,
+            Err(ObjectFileError::PreconditionFailed {
+                expected: Some(2),
+                found: Some(1)
+            })
+        ));
+        assert_eq!(fresh.unwrap(), 2);
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), b"second");
+    }
+
+    #[test]
+    fn conditional_finishes_under_shared_locks_cannot_both_win() {
+        let dir = TempDir::new("conditional-shared");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"first");
+
+        let mut readers = [(); 2].map(|_| {
+            let mut object = TuxObject::open_writable(&path).unwrap();
+            object.lock(LockMode::Shared).unwrap();
+            object
+        });
+        for (object, content) in readers.iter_mut().zip([b"second", b"third!"]) {
+            let mut writer = object.content_writer().unwrap();
+            writer.write_all(content).unwrap();
+            assert!(matches!(
+                writer.finish_if_generation(1),
+                Err(ObjectFileError::ConditionalWriteUnderSharedLock)
+            ));
+        }
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(object.generation(), 1);
+        assert_eq!(object.read_content_to_vec().unwrap(), b"first");
+    }
+
+    #[test]
+    fn section_updates_can_be_conditioned_on_the_generation() {
+        let dir = TempDir::new("conditional-sections");
+        let path = dir.join("object.tuxio");
+        write_plain(&path, b"content");
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let mut stale = TuxObject::open_writable(&path).unwrap();
+        object
+            .set_sections_if_generation(1, sample_metadata(), sample_tags())
+            .unwrap();
+        assert_eq!(object.generation(), 2);
+
+        assert!(matches!(
+            stale.set_sections_if_generation(1, MetadataMap::new(), Tags::new()),
+            Err(ObjectFileError::PreconditionFailed {
+                expected: Some(1),
+                found: Some(2)
+            })
+        ));
+        // Left on the object as it now is, ready to retry.
+        assert_eq!(stale.metadata(), &sample_metadata());
+        assert_eq!(stale.lock_mode(), None);
+    }
+
+    #[test]
+    fn shared_objects_serve_concurrent_range_readers() {
+        let dir = TempDir::new("shared");
+        let path = dir.join("object.tuxio");
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i * 31) as u8).collect();
+        write_plain(&path, &content);
+
+        let object = std::sync::Arc::new(SharedTuxObject::open(&path).unwrap());
+        let readers: Vec<_> = (0..8u64)
+            .map(|i| {
+                let object = object.clone();
+                std::thread::spawn(move || {
+                    let mut reader = object
+                        .into_content_range_reader(i * 1000, Some(1500))
+                        .unwrap();
+                    let mut range = Vec::new();
+                    // Small reads, so the readers' positions interleave.
+                    let mut buf = [0u8; 7];
+                    loop {
+                        let read = reader.read(&mut buf).unwrap();
+                        if read == 0 {
+                            break;
+                        }
+                        range.extend_from_slice(&buf[..read]);
+                    }
+                    range
+                })
+            })
+            .collect();
+        for (i, reader) in readers.into_iter().enumerate() {
+            assert_eq!(reader.join().unwrap(), &content[i * 1000..i * 1000 + 1500]);
+        }
+
+        let mut buf = [0u8; 100];
+        assert_eq!(object.read_content_at(9950, &mut buf).unwrap(), 50);
+        assert_eq!(&buf[..50], &content[9950..]);
+        assert_eq!(object.read_content_at(10_000, &mut buf).unwrap(), 0);
+        assert!(matches!(
+            object.read_content_at(10_001, &mut buf),
+            Err(ObjectFileError::RangeOutOfBounds { .. })
+        ));
+        assert!(object.content_range_reader(9000, Some(1001)).is_err());
+
+        // A plain handle reads at an offset the same way, without disturbing its own reads.
+        let mut plain = TuxObject::open(&path).unwrap();
+        assert_eq!(plain.read_content_at(5000, &mut buf).unwrap(), 100);
+        assert_eq!(&buf[..], &content[5000..5100]);
+        assert_eq!(plain.read_content_to_vec().unwrap(), content);
+    }
+
+    #[cfg(feature = "encryption")]
+    fn test_keys() -> std::collections::HashMap<String, EncryptionKey> {
+        std::collections::HashMap::from([("primary".to_owned(), EncryptionKey::new([7; 32]))])
+    }
+
+    /// Writes `content` sealed under the `primary` key of [test_keys], in 100 byte segments so
+    /// modest content spans several.
+    #[cfg(feature = "encryption")]
+    fn write_encrypted(
+        path: &std::path::Path,
+        algorithm: EncryptionAlgorithm,
+        options: CreateOptions,
+        content: &[u8],
+    ) {
+        let encryption = ContentEncryption::new(algorithm, "primary", EncryptionKey::new([7; 32]))
+            .with_segment_size(100);
+        let mut writer = TuxObject::create(path, options.with_encryption(encryption)).unwrap();
+        let mut encoder = writer.content_encoder().unwrap();
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_content_round_trips() {
+        let dir = TempDir::new("encrypted");
+        let content: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+
+        for algorithm in [
+            EncryptionAlgorithm::Aes256Gcm,
+            EncryptionAlgorithm::ChaCha20Poly1305,
+        ] {
+            // An exact multiple of the segment size, one byte over, and nothing at all.
+            for length in [content.len(), 301, 0] {
+                let path = dir.join(&format!("{}-{length}.tuxio", algorithm.name()));
+                write_encrypted(
+                    &path,
+                    algorithm,
+                    CreateOptions::new().with_metadata(sample_metadata()),
+                    &content[..length],
+                );
+
+                let mut object = TuxObject::open(&path).unwrap();
+                assert!(object.is_encrypted());
+                assert_eq!(
+                    object.metadata().get_header(&ENCRYPTION),
+                    Some(&ValueType::from(algorithm.name().to_owned()))
+                );
+                object.unlock(&test_keys()).unwrap();
+                assert_eq!(object.plaintext_length().unwrap(), length as u64);
+                assert_eq!(object.read_content_to_vec().unwrap(), &content[..length]);
+            }
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_ranges_cross_segment_boundaries() {
+        let dir = TempDir::new("encrypted-range");
+        let path = dir.join("object.tuxio");
+        let content: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::ChaCha20Poly1305,
+            CreateOptions::new(),
+            &content,
+        );
+
+        let mut object = TuxObject::open(&path).unwrap();
+        object.unlock(&test_keys()).unwrap();
+        for (offset, length) in [(0, 100), (95, 10), (250, 500), (990, 10), (1000, 0)] {
+            let mut range = Vec::new();
+            object
+                .content_range_reader(offset, Some(length))
+                .unwrap()
+                .read_to_end(&mut range)
+                .unwrap();
+            assert_eq!(range, &content[offset as usize..(offset + length) as usize]);
+        }
+        let mut tail = Vec::new();
+        object
+            .content_range_reader(640, None)
+            .unwrap()
+            .read_to_end(&mut tail)
+            .unwrap();
+        assert_eq!(tail, &content[640..]);
+        assert!(object.content_range_reader(1000, Some(1)).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn positional_reads_decrypt_across_segment_boundaries() {
+        let dir = TempDir::new("shared-encrypted");
+        let path = dir.join("object.tuxio");
+        let content: Vec<u8> = (0..1000u32).map(|i| (i * 3) as u8).collect();
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::Aes256Gcm,
+            CreateOptions::new(),
+            &content,
+        );
+
+        let mut object = TuxObject::open(&path).unwrap();
+        let mut buf = [0u8; 250];
+        assert!(matches!(
+            object.read_content_at(0, &mut buf),
+            Err(ObjectFileError::ContentLocked)
+        ));
+        object.unlock(&test_keys()).unwrap();
+        for offset in [0, 95, 399, 900] {
+            let read = object.read_content_at(offset, &mut buf).unwrap();
+            let end = (offset as usize + 250).min(content.len());
+            assert_eq!(&buf[..read], &content[offset as usize..end]);
+        }
+
+        let object = object.into_shared();
+        let mut range = Vec::new();
+        object
+            .content_range_reader(150, Some(700))
+            .unwrap()
+            .read_to_end(&mut range)
+            .unwrap();
+        assert_eq!(range, &content[150..850]);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_readers_seek_across_segments() {
+        let dir = TempDir::new("encrypted-seek");
+        let path = dir.join("object.tuxio");
+        let content: Vec<u8> = (0..1000u32).map(|i| (i * 5) as u8).collect();
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::Aes256Gcm,
+            CreateOptions::new(),
+            &content,
+        );
+
+        let mut object = TuxObject::open(&path).unwrap();
+        object.unlock(&test_keys()).unwrap();
+        let mut reader = object.content_range_reader(0, None).unwrap();
+        let mut buf = [0u8; 20];
+        // Forwards and backwards across segments, and within the segment already decrypted.
+        for position in [450, 10, 990 - 10, 95, 105, 0, 980] {
+            assert_eq!(reader.seek(SeekFrom::Start(position)).unwrap(), position);
+            reader.read_exact(&mut buf).unwrap();
+            let position = position as usize;
+            assert_eq!(&buf, &content[position..position + 20]);
+        }
+        assert_eq!(reader.seek(SeekFrom::Current(-30)).unwrap(), 970);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &content[970..]);
+    }
+
+    #[cfg(all(feature = "encryption", feature = "zstd"))]
+    #[test]
+    fn compressed_content_is_encrypted_after_compression() {
+        let dir = TempDir::new("encrypted-zstd");
+        let path = dir.join("object.tuxio");
+        let content = b"tuxio ".repeat(4096);
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::Aes256Gcm,
+            CreateOptions::new().with_compression(crate::CompressionTypes::ZSTD(
+                crate::compression_types::ZStdCompressionType(3),
+            )),
+            &content,
+        );
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert!(object.is_compressed());
+        // Compression still pays off, which it could not if it ran on ciphertext.
+        assert!(object.content_length() < content.len() as u64 / 10);
+        object.unlock(&test_keys()).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn locked_content_is_refused() {
+        let dir = TempDir::new("encrypted-locked");
+        let path = dir.join("object.tuxio");
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::Aes256Gcm,
+            CreateOptions::new(),
+            b"secret",
+        );
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert!(matches!(
+            object.read_content_to_vec(),
+            Err(ObjectFileError::ContentLocked)
+        ));
+        assert!(matches!(
+            object.content_range_reader(0, Some(1)),
+            Err(ObjectFileError::ContentLocked)
+        ));
+        assert!(matches!(
+            object.unlock(&std::collections::HashMap::new()),
+            Err(ObjectFileError::UnknownEncryptionKey(id)) if id == "primary"
+        ));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn wrong_keys_and_tampering_are_detected() {
+        let dir = TempDir::new("encrypted-tampered");
+        let path = dir.join("object.tuxio");
+        let content = vec![1u8; 450];
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::ChaCha20Poly1305,
+            CreateOptions::new(),
+            &content,
+        );
+
+        let mut object = TuxObject::open(&path).unwrap();
+        let wrong =
+            std::collections::HashMap::from([("primary".to_owned(), EncryptionKey::new([8; 32]))]);
+        assert!(matches!(
+            object.unlock(&wrong),
+            Err(ObjectFileError::KeyUnwrapFailed(id)) if id == "primary"
+        ));
+
+        // Flip a byte in the third segment; the two before it still read.
+        let content_start = object.layout().content_start as usize;
+        drop(object);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[content_start + 2 * (100 + SEGMENT_TAG_SIZE) + 5] ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut object = TuxObject::open(&path).unwrap();
+        object.unlock(&test_keys()).unwrap();
+        let mut prefix = Vec::new();
+        object
+            .content_range_reader(0, Some(200))
+            .unwrap()
+            .read_to_end(&mut prefix)
+            .unwrap();
+        assert_eq!(prefix, &content[..200]);
+        assert!(matches!(
+            object.read_content_to_vec(),
+            Err(ObjectFileError::ContentAuthentication(
+                ContentAuthenticationError { segment: 2 }
+            ))
+        ));
+    }
+
+    /// Truncating the content at a segment boundary leaves only whole, valid segments behind, so
+    /// it is the last segment flag in the nonce that has to catch it.
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn truncated_content_fails_authentication() {
+        let dir = TempDir::new("encrypted-truncated");
+        let path = dir.join("object.tuxio");
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::Aes256Gcm,
+            CreateOptions::new(),
+            &[2u8; 300],
+        );
+
+        let object = TuxObject::open(&path).unwrap();
+        let mut header = object.header().clone();
+        let content_start = object.layout().content_start as u64;
+        drop(object);
+        header.content_length = 2 * (100 + SEGMENT_TAG_SIZE as u64);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate((content_start + header.content_length) as usize);
+        let mut encoded = Vec::new();
+        crate::WritableObjectType::write_to_writer(&header, &mut encoded).unwrap();
+        bytes[..HEADER_SIZE].copy_from_slice(&encoded);
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut object = TuxObject::open(&path).unwrap();
+        object.unlock(&test_keys()).unwrap();
+        assert!(matches!(
+            object.read_content_to_vec(),
+            Err(ObjectFileError::ContentAuthentication(_))
+        ));
+    }
+
+    /// A metadata update needs no key: the ciphertext is carried across as is, and the encryption
+    /// metadata with it even when the caller's new metadata leaves it out.
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_objects_survive_a_metadata_rewrite() {
+        let dir = TempDir::new("encrypted-rewrite");
+        let path = dir.join("object.tuxio");
+        let content = b"tuxio ".repeat(100);
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::Aes256Gcm,
+            CreateOptions::new().with_layout(LayoutOptions::packed()),
+            &content,
+        );
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        object.set_metadata(sample_metadata()).unwrap();
+        object
+            .set_sections_in_place(MetadataMap::new(), Tags::new())
+            .unwrap();
+        assert!(object.is_encrypted());
+        assert!(object.metadata().get_header(&ENCRYPTION_KEY_ID).is_some());
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        object.unlock(&test_keys()).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_copies_keep_their_encryption() {
+        let dir = TempDir::new("encrypted-copy");
+        let path = dir.join("object.tuxio");
+        let content = b"tuxio ".repeat(100);
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::ChaCha20Poly1305,
+            CreateOptions::new(),
+            &content,
+        );
+
+        let mut source = TuxObject::open(&path).unwrap();
+        let mut copy = source
+            .copy_to(dir.join("copy.tuxio"), Some(sample_metadata()), None)
+            .unwrap();
+        assert!(copy.is_encrypted());
+        assert!(copy.metadata().get_header(&ENCRYPTION_KEY_ID).is_some());
+        assert!(matches!(
+            copy.read_content_to_vec(),
+            Err(ObjectFileError::ContentLocked)
+        ));
+        copy.unlock(&test_keys()).unwrap();
+        assert_eq!(copy.read_content_to_vec().unwrap(), content);
+    }
+
+    /// Rotation touches nothing but the key metadata: the ciphertext comes through byte for byte.
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn rewrapping_rotates_the_master_key_without_touching_the_content() {
+        let dir = TempDir::new("encrypted-rewrap");
+        let path = dir.join("object.tuxio");
+        let content = b"tuxio ".repeat(100);
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::Aes256Gcm,
+            CreateOptions::new().with_tags(sample_tags()),
+            &content,
+        );
+
+        let stored = |object: &mut TuxObject| {
+            let mut stored = Vec::new();
+            object
+                .stored_content_reader()
+                .unwrap()
+                .read_to_end(&mut stored)
+                .unwrap();
+            stored
+        };
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let ciphertext = stored(&mut object);
+        let rotated = EncryptionKey::new([9; 32]);
+        let wrong =
+            std::collections::HashMap::from([("primary".to_owned(), EncryptionKey::new([8; 32]))]);
+        assert!(matches!(
+            object.rewrap_key(&wrong, "rotated", rotated.clone()),
+            Err(ObjectFileError::KeyUnwrapFailed(_))
+        ));
+        let file_before = std::fs::metadata(&path).unwrap();
+        object
+            .rewrap_key(&test_keys(), "rotated", rotated.clone())
+            .unwrap();
+        // Left unlocked under the new key.
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+        // Published anew rather than written over, so the old wrapped key is never torn.
+        assert!(!crate::fs::lock::same_file(
+            &file_before,
+            &std::fs::metadata(&path).unwrap()
+        ));
+        assert_eq!(object.lock_mode(), None);
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert_eq!(stored(&mut object), ciphertext);
+        assert_eq!(object.read_tags().unwrap(), sample_tags());
+        assert!(matches!(
+            object.unlock(&test_keys()),
+            Err(ObjectFileError::UnknownEncryptionKey(id)) if id == "rotated"
+        ));
+        let rotated = std::collections::HashMap::from([("rotated".to_owned(), rotated)]);
+        object.unlock(&rotated).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn rewrapping_requires_encrypted_content() {
+        let dir = TempDir::new("rewrap-plain");
+        let path = dir.join("object.tuxio");
+        let mut writer = TuxObject::create(&path, CreateOptions::new()).unwrap();
+        writer.write_all(b"plain").unwrap();
+        writer.finish().unwrap();
+
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        assert!(matches!(
+            object.rewrap_key(&test_keys(), "primary", EncryptionKey::new([7; 32])),
+            Err(ObjectFileError::NotEncrypted)
+        ));
+    }
+
+    #[cfg(all(feature = "encryption", feature = "gzip"))]
+    #[test]
+    fn recompress_reseals_encrypted_content() {
+        let dir = TempDir::new("encrypted-recompress");
+        let path = dir.join("object.tuxio");
+        let content = b"tuxio ".repeat(1024);
+        write_encrypted(
+            &path,
+            EncryptionAlgorithm::ChaCha20Poly1305,
+            CreateOptions::new(),
+            &content,
+        );
+
+        let gzip = crate::CompressionTypes::Gzip(crate::compression_types::GzipCompressionType(6));
+        let mut object = TuxObject::open_writable(&path).unwrap();
+        let nonce = object.metadata().get_header(&ENCRYPTION_NONCE).cloned();
+        assert!(matches!(
+            object.recompress(gzip),
+            Err(ObjectFileError::ContentLocked)
+        ));
+        object.unlock(&test_keys()).unwrap();
+        object.recompress(gzip).unwrap();
+        assert_ne!(
+            object.metadata().get_header(&ENCRYPTION_NONCE).cloned(),
+            nonce
+        );
+        drop(object);
+
+        let mut object = TuxObject::open(&path).unwrap();
+        assert!(object.is_encrypted());
+        assert_eq!(object.compression(), gzip);
+        object.unlock(&test_keys()).unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), content);
+    }
+
     #[test]
     fn uncompressed_encoder_is_a_pass_through() {
         let dir = TempDir::new("passthrough");
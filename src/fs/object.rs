@@ -2,13 +2,20 @@ use std::{
     fs::{File, OpenOptions},
     io::{Cursor, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use crate::{
     CompressionTypes, MetadataMap, ObjectHeader, ReadableObjectType, Tags, TuxIOType, ValueType,
+    WritableObjectType,
     fs::{
-        ContentReader, CreateOptions, DEFAULT_ALIGNMENT, DecodedContentReader, HEADER_SIZE,
-        LayoutOptions, ObjectFileError, ObjectFileResult, ObjectWriter, SectionLayout,
+        CompressionPolicy, ContentReader, CreateOptions, DEFAULT_ALIGNMENT, DecodedContentReader,
+        HEADER_SIZE, LayoutOptions, LockMode, ObjectFileError, ObjectFileResult, ObjectWriter,
+        SectionLayout, carry_encryption_metadata, ensure_supported,
+        lock::{LockWait, lock_file, same_file},
+        shared::{PositionalContent, SegmentCache},
+        uncompressed_length_key,
+        versions::ensure_unshared,
         writer::encode_prefix,
     },
 };
@@ -24,6 +31,11 @@ pub struct TuxObject {
     header: ObjectHeader,
     metadata: MetadataMap,
     writable: bool,
+    /// The advisory lock this handle holds, if any.
+    lock: Option<LockMode>,
+    /// Set by [TuxObject::unlock], for an encrypted object.
+    #[cfg(feature = "encryption")]
+    cipher: Option<crate::fs::encryption::SegmentCipher>,
 }
 
 impl TuxObject {
@@ -75,10 +87,33 @@ impl TuxObject {
             header,
             metadata,
             writable,
+            lock: None,
+            #[cfg(feature = "encryption")]
+            cipher: None,
         }
     }
 
+    /// Records a lock the file was given before this handle was built around it.
+    pub(crate) fn set_lock(&mut self, lock: Option<LockMode>) {
+        self.lock = lock;
+    }
+
+    #[cfg(feature = "encryption")]
+    pub(crate) fn set_cipher(&mut self, cipher: Option<crate::fs::encryption::SegmentCipher>) {
+        self.cipher = cipher;
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
     fn from_file(mut file: File, path: PathBuf, writable: bool) -> ObjectFileResult<Self> {
+        let (header, metadata) = Self::read_prefix(&mut file)?;
+        Ok(Self::from_parts(file, path, header, metadata, writable))
+    }
+
+    /// Reads the header and the metadata section.
+    fn read_prefix(file: &mut File) -> ObjectFileResult<(ObjectHeader, MetadataMap)> {
         file.seek(SeekFrom::Start(0))?;
 
         let mut header_bytes = [0u8; HEADER_SIZE];
@@ -94,19 +129,16 @@ impl TuxObject {
             file.read_exact(&mut buffer)?;
             MetadataMap::read_from_reader(&mut Cursor::new(&buffer))?
         };
-
-        Ok(Self {
-            file,
-            path,
-            header,
-            metadata,
-            writable,
-        })
+        Ok((header, metadata))
     }
 
     pub fn header(&self) -> &ObjectHeader {
         &self.header
     }
+    /// See [ObjectHeader::generation].
+    pub fn generation(&self) -> u64 {
+        self.header.generation
+    }
     pub fn path(&self) -> &Path {
         &self.path
     }
@@ -123,6 +155,9 @@ impl TuxObject {
     pub fn is_compressed(&self) -> bool {
         !matches!(self.header.compression_type, CompressionTypes::None(_))
     }
+    pub fn is_encrypted(&self) -> bool {
+        self.header.is_encrypted()
+    }
     pub fn layout(&self) -> SectionLayout {
         SectionLayout {
             tags_start: self.header.tags_start,
@@ -133,6 +168,141 @@ impl TuxObject {
     pub fn file_size(&self) -> ObjectFileResult<u64> {
         Ok(self.file.metadata()?.len())
     }
+    /// Drops the file handle, keeping what was read when the object was opened.
+    pub fn into_head(self) -> crate::fs::store::ObjectHead {
+        crate::fs::store::ObjectHead {
+            header: self.header,
+            metadata: self.metadata,
+        }
+    }
+    /// Turns this handle into one that reads with `&self`, for sharing between threads.
+    ///
+    /// Keeps the cipher of an unlocked object, and any lock the file holds.
+    pub fn into_shared(self) -> crate::fs::SharedTuxObject {
+        crate::fs::SharedTuxObject::from_parts(
+            self.file,
+            self.path,
+            self.header,
+            self.metadata,
+            #[cfg(feature = "encryption")]
+            self.cipher,
+        )
+    }
+
+    /// Looks up the key for an encrypted object, after which its content reads decrypted.
+    ///
+    /// Does nothing for an object that is not encrypted, so a caller holding a [KeyProvider] can
+    /// unlock whatever it opens. Fails with [ObjectFileError::UnknownEncryptionKey] when `keys` does
+    /// not have the key the object names, and with [ObjectFileError::KeyUnwrapFailed] when the key
+    /// it has is the wrong one.
+    ///
+    /// [KeyProvider]: crate::fs::KeyProvider
+    #[cfg(feature = "encryption")]
+    pub fn unlock(
+        &mut self,
+        keys: &(impl crate::fs::KeyProvider + ?Sized),
+    ) -> ObjectFileResult<()> {
+        if self.is_encrypted() {
+            self.cipher = Some(crate::fs::encryption::SegmentCipher::from_metadata(
+                &self.metadata,
+                keys,
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Length of the content as read back: decrypted, but still compressed for a compressed object.
+    ///
+    /// The same as [TuxObject::content_length] unless the object is encrypted, and only known for an
+    /// encrypted one once it is unlocked.
+    pub fn plaintext_length(&self) -> ObjectFileResult<u64> {
+        if !self.is_encrypted() {
+            return Ok(self.header.content_length);
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.plaintext_length(self.header.content_length);
+        }
+        Err(ObjectFileError::ContentLocked)
+    }
+
+    // -- locking -------------------------------------------------------------------------------
+
+    /// Takes an advisory lock on the object, waiting for as long as another process holds a
+    /// conflicting one. Also converts a lock already held to `mode`.
+    ///
+    /// When the object was replaced while waiting — another locker's atomic update published a
+    /// new file — this handle is reopened on the new file, which is then locked in turn. The header
+    /// and metadata are read again either way, so they are current as of the lock. An encrypted
+    /// object stays unlocked for reading unless its content was replaced.
+    ///
+    /// The lock is released by [TuxObject::release_lock] or when the object is dropped, and is
+    /// kept across this handle's own atomic updates.
+    pub fn lock(&mut self, mode: LockMode) -> ObjectFileResult<()> {
+        self.acquire_lock(mode, LockWait::Forever)?;
+        Ok(())
+    }
+
+    /// [TuxObject::lock] without waiting: returns `false` straight away when another process holds
+    /// a conflicting lock.
+    pub fn try_lock(&mut self, mode: LockMode) -> ObjectFileResult<bool> {
+        self.acquire_lock(mode, LockWait::Never)
+    }
+
+    /// [TuxObject::lock], giving up and returning `false` once `timeout` has passed.
+    pub fn lock_timeout(&mut self, mode: LockMode, timeout: Duration) -> ObjectFileResult<bool> {
+        self.acquire_lock(mode, LockWait::timeout(timeout))
+    }
+
+    pub fn release_lock(&mut self) -> ObjectFileResult<()> {
+        if self.lock.take().is_some() {
+            self.file.unlock()?;
+        }
+        Ok(())
+    }
+
+    pub fn lock_mode(&self) -> Option<LockMode> {
+        self.lock
+    }
+
+    fn acquire_lock(&mut self, mode: LockMode, wait: LockWait) -> ObjectFileResult<bool> {
+        loop {
+            if !lock_file(&self.file, mode, wait)? {
+                return Ok(false);
+            }
+            self.lock = Some(mode);
+            let current = match std::fs::metadata(&self.path) {
+                Ok(current) => current,
+                Err(err) => {
+                    self.release_lock()?;
+                    return Err(err.into());
+                }
+            };
+            if same_file(&self.file.metadata()?, &current) {
+                let (header, metadata) = Self::read_prefix(&mut self.file)?;
+                self.header = header;
+                self.metadata = metadata;
+                return Ok(true);
+            }
+            self.reopen()?;
+        }
+    }
+
+    /// Moves this handle to the file now at its path, dropping the old one and any lock on it.
+    fn reopen(&mut self) -> ObjectFileResult<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(self.writable)
+            .open(&self.path)?;
+        #[allow(unused_mut)]
+        let mut reopened = Self::from_file(file, self.path.clone(), self.writable)?;
+        #[cfg(feature = "encryption")]
+        if crate::fs::encryption::same_encryption(&self.metadata, &reopened.metadata) {
+            reopened.cipher = self.cipher.take();
+        }
+        *self = reopened;
+        Ok(())
+    }
 
     // -- tags ----------------------------------------------------------------------------------
 
@@ -208,6 +378,72 @@ impl TuxObject {
         self.set_metadata(metadata)
     }
 
+    /// Replaces the metadata atomically, but only when `header_matches` accepts the object as it is
+    /// on disk. Returns whether it did.
+    ///
+    /// The check and the update happen under an exclusive lock, taken for the duration if this
+    /// handle does not already hold one, so no other *locking* writer can change the object in
+    /// between. `header_matches` sees the header and metadata read after the lock was taken.
+    pub fn set_metadata_if<F>(
+        &mut self,
+        metadata: MetadataMap,
+        header_matches: F,
+    ) -> ObjectFileResult<bool>
+    where
+        F: FnOnce(&ObjectHeader, &MetadataMap) -> bool,
+    {
+        self.update_locked(|object| {
+            if !header_matches(&object.header, &object.metadata) {
+                return Ok(false);
+            }
+            object.set_metadata(metadata)?;
+            Ok(true)
+        })
+    }
+
+    /// Replaces both sections atomically, but only while the object is still at generation
+    /// `expected` — as an S3 `If-Match` does with an ETag. Otherwise fails with
+    /// [ObjectFileError::PreconditionFailed], leaving this handle on the object as it now is.
+    ///
+    /// Checked under an exclusive lock, as with [TuxObject::set_metadata_if].
+    pub fn set_sections_if_generation(
+        &mut self,
+        expected: u64,
+        metadata: MetadataMap,
+        tags: Tags,
+    ) -> ObjectFileResult<()> {
+        self.update_locked(|object| {
+            let found = object.header.generation;
+            if found != expected {
+                return Err(ObjectFileError::PreconditionFailed {
+                    expected: Some(expected),
+                    found: Some(found),
+                });
+            }
+            object.set_sections(metadata, tags)
+        })
+    }
+
+    /// Runs `update` under an exclusive lock, then goes back to the lock the caller held, whether
+    /// or not the update went through.
+    fn update_locked<T>(
+        &mut self,
+        update: impl FnOnce(&mut Self) -> ObjectFileResult<T>,
+    ) -> ObjectFileResult<T> {
+        self.ensure_writable()?;
+        let held = self.lock;
+        self.lock(LockMode::Exclusive)?;
+        let result = update(self);
+        let restored = match held {
+            None => self.release_lock(),
+            Some(LockMode::Shared) => self.lock(LockMode::Shared),
+            Some(LockMode::Exclusive) => Ok(()),
+        };
+        let updated = result?;
+        restored?;
+        Ok(updated)
+    }
+
     /// Overwrites the metadata and tag sections in place, without touching the content.
     ///
     /// Much cheaper than [TuxObject::set_metadata] because the content never moves, but **not**
@@ -215,13 +451,17 @@ impl TuxObject {
     /// (a cache entry, say), and prefer the atomic setters for anything authoritative.
     ///
     /// Returns [ObjectFileError::ReservedSpaceExceeded] when the new sections do not fit in the
-    /// space already reserved — it deliberately does not fall back to the expensive rewrite.
+    /// space already reserved — it deliberately does not fall back to the expensive rewrite — and
+    /// [ObjectFileError::InPlaceUpdateOfVersionedObject] for a versioned object, whose file is
+    /// also a kept version.
     pub fn set_sections_in_place(
         &mut self,
-        metadata: MetadataMap,
+        mut metadata: MetadataMap,
         tags: Tags,
     ) -> ObjectFileResult<()> {
         self.ensure_writable()?;
+        ensure_unshared(&self.path)?;
+        carry_encryption_metadata(&self.metadata, &mut metadata);
 
         let metadata_size = metadata.size();
         let tags_size = tags.size();
@@ -239,6 +479,7 @@ impl TuxObject {
         let mut header = self.header.clone();
         header.tags_start = layout.tags_start;
         header.content_start = layout.content_start;
+        header.generation = header.generation.saturating_add(1);
 
         let prefix = encode_prefix(&header, &metadata, &tags, layout)?;
         self.file.seek(SeekFrom::Start(0))?;
@@ -250,33 +491,296 @@ impl TuxObject {
         Ok(())
     }
 
+    /// Appends `data` to the content in place, returning the new content length. For log-style
+    /// objects, which would otherwise be rewritten whole on every append.
+    ///
+    /// The bytes go after the content and are made durable before the header is updated to cover
+    /// them, with a single 32 byte write, so a crash never leaves a length that runs past what is
+    /// on disk: at worst the append is lost, and the next one writes over it. Like any update it
+    /// advances the generation, and it is done under an exclusive lock, taken for the duration if
+    /// this handle does not already hold one, so appenders that lock cannot interleave.
+    ///
+    /// Only for content stored as is; compressed and encrypted objects fail with
+    /// [ObjectFileError::AppendToEncodedContent]. Versioned objects fail with
+    /// [ObjectFileError::InPlaceUpdateOfVersionedObject], since appending would change the version
+    /// kept of them too.
+    pub fn append(&mut self, data: &[u8]) -> ObjectFileResult<u64> {
+        if self.is_compressed() || self.is_encrypted() {
+            return Err(ObjectFileError::AppendToEncodedContent);
+        }
+        ensure_unshared(&self.path)?;
+        self.update_locked(|object| {
+            // Checked again: the lock reloads the header, and the object may have been replaced.
+            if object.is_compressed() || object.is_encrypted() {
+                return Err(ObjectFileError::AppendToEncodedContent);
+            }
+            let mut header = object.header.clone();
+            let content_end = header.content_start as u64 + header.content_length;
+            header.content_length += data.len() as u64;
+            header.generation = header.generation.saturating_add(1);
+
+            object.file.seek(SeekFrom::Start(content_end))?;
+            object.file.write_all(data)?;
+            object.file.sync_data()?;
+
+            let mut header_bytes = Vec::with_capacity(HEADER_SIZE);
+            header.write_to_writer(&mut header_bytes)?;
+            object.file.seek(SeekFrom::Start(0))?;
+            object.file.write_all(&header_bytes)?;
+            object.file.sync_data()?;
+
+            object.header = header;
+            Ok(object.header.content_length)
+        })
+    }
+
+    /// Re-encodes the content under another codec, or another level of the same one.
+    ///
+    /// The content is decoded and compressed again into a fresh object, which is published over
+    /// this one atomically as with [TuxObject::set_metadata]. Metadata and tags carry over, apart
+    /// from [crate::fs::UNCOMPRESSED_LENGTH], which is recorded afresh for the new encoding — or
+    /// dropped when `compression` stores the content uncompressed.
+    ///
+    /// An encrypted object must be unlocked first, and is sealed again under the same master key,
+    /// with a new data key.
+    pub fn recompress(&mut self, compression: CompressionTypes) -> ObjectFileResult<()> {
+        let mut writer = self.replacement_writer(compression)?;
+        {
+            let mut encoder = writer.content_encoder()?;
+            let mut reader = self.decompressed_content_reader()?;
+            std::io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?;
+        }
+        let replacement = writer.finish()?;
+        *self = replacement;
+        Ok(())
+    }
+
+    /// Starts writing new content for this object, keeping its metadata, tags, compression and
+    /// layout. Finishing the writer publishes the object over this one atomically; this handle
+    /// goes on reading the old content, so use the object `finish` returns.
+    ///
+    /// The content goes through [ObjectWriter::content_encoder] when the object is compressed or
+    /// encrypted, as for any new object. An encrypted object must be unlocked first, and the new
+    /// content is sealed under the same master key, with a new data key. Derived metadata such as
+    /// an ETag is carried over unchanged, so update it through [ObjectWriter::metadata_mut].
+    pub fn content_writer(&mut self) -> ObjectFileResult<ObjectWriter> {
+        self.replacement_writer(self.header.compression_type)
+    }
+
+    /// Replaces the content with what `content` reads, keeping the metadata and tags. Atomic, as
+    /// with [TuxObject::set_metadata]. See [TuxObject::content_writer].
+    pub fn replace_content(&mut self, content: impl Read) -> ObjectFileResult<()> {
+        self.replace_content_with(content, |_| {})
+    }
+
+    /// [TuxObject::replace_content], calling `refresh` with the writer once the content is written
+    /// and before it is published, to bring metadata derived from the content up to date — an ETag
+    /// from [ObjectWriter::content_length] and a digest, say, or the modification time.
+    pub fn replace_content_with<F>(
+        &mut self,
+        mut content: impl Read,
+        refresh: F,
+    ) -> ObjectFileResult<()>
+    where
+        F: FnOnce(&mut ObjectWriter),
+    {
+        let mut writer = self.content_writer()?;
+        {
+            let mut encoder = writer.content_encoder()?;
+            std::io::copy(&mut content, &mut encoder)?;
+            encoder.finish()?;
+        }
+        refresh(&mut writer);
+        let replacement = writer.finish()?;
+        *self = replacement;
+        Ok(())
+    }
+
+    /// A writer for new content under `compression`, carrying everything else across: the sections,
+    /// the layout, the lock, and an encrypted object's master key.
+    fn replacement_writer(
+        &mut self,
+        compression: CompressionTypes,
+    ) -> ObjectFileResult<ObjectWriter> {
+        self.ensure_writable()?;
+        ensure_supported(compression)?;
+        self.ensure_readable()?;
+
+        let mut metadata = self.metadata.clone();
+        metadata.remove(&uncompressed_length_key());
+        // Sealed again from scratch, under a nonce of its own.
+        carry_encryption_metadata(&MetadataMap::new(), &mut metadata);
+        let tags = self.read_tags()?;
+        let options = CreateOptions {
+            metadata,
+            tags,
+            layout: self.rewrite_layout(),
+            compression,
+            compression_policy: CompressionPolicy::Fixed,
+            compression_workers: 0,
+            #[cfg(feature = "encryption")]
+            encryption: self.cipher.as_ref().map(|cipher| cipher.encryption()),
+            sync: true,
+            versioned: false,
+            sniff_content_type: false,
+        };
+        let mut writer = ObjectWriter::create(&self.path, options)?;
+        writer.carry_lock(self.lock, &self.file)?;
+        Ok(writer)
+    }
+
+    /// Rewraps an encrypted object's data key under a new master key.
+    ///
+    /// `old` must have the master key the object currently names. Only the metadata changes: the
+    /// content is copied across as it is stored, never decrypted or sealed again, and the object is
+    /// published atomically as with [TuxObject::set_metadata], so a crash part way through leaves
+    /// the key wrapped one way or the other, never torn. Done under an exclusive lock, taken for
+    /// the duration if this handle does not already hold one. Keys are not needed to read the
+    /// ciphertext, so this works on an object that was never unlocked, and leaves it unlocked.
+    ///
+    /// Fails with [ObjectFileError::KeyUnwrapFailed] when the key from `old` is not the one the data
+    /// key was wrapped under.
+    #[cfg(feature = "encryption")]
+    pub fn rewrap_key(
+        &mut self,
+        old: &(impl crate::fs::KeyProvider + ?Sized),
+        new_key_id: impl Into<String>,
+        new_key: crate::fs::EncryptionKey,
+    ) -> ObjectFileResult<()> {
+        self.ensure_writable()?;
+        if !self.is_encrypted() {
+            return Err(ObjectFileError::NotEncrypted);
+        }
+        let new_key_id = new_key_id.into();
+        self.update_locked(|object| {
+            // Read again: the lock reloads the metadata, and the object may have been replaced.
+            if !object.is_encrypted() {
+                return Err(ObjectFileError::NotEncrypted);
+            }
+            let cipher =
+                crate::fs::encryption::SegmentCipher::from_metadata(&object.metadata, old)?
+                    .rewrap(new_key_id, new_key)?;
+
+            let mut metadata = object.metadata.clone();
+            cipher.describe(&mut metadata);
+            let tags = object.read_tags()?;
+            object.rewrite_as_is(metadata, tags)?;
+            object.cipher = Some(cipher);
+            Ok(())
+        })
+    }
+
+    /// Copies the object to `dest`, replacing any object there, with new metadata or tags in place
+    /// of those it has — S3's `CopyObject` with a `REPLACE` directive. Returns the copy.
+    ///
+    /// When the sections still fit in front of the content, the content keeps its offset and is
+    /// copied by the kernel, which on a filesystem with reflinks shares its blocks rather than
+    /// copying any; only the prefix is written afresh. Otherwise, or where the kernel cannot copy
+    /// between the two files, the content is copied through as in a rewrite. Published atomically
+    /// either way, and `dest` must be in an existing directory.
+    ///
+    /// An encrypted object's copy keeps its encryption, whatever the new metadata says, and has to
+    /// be unlocked to read as this one does.
+    pub fn copy_to(
+        &mut self,
+        dest: impl Into<PathBuf>,
+        metadata: Option<MetadataMap>,
+        tags: Option<Tags>,
+    ) -> ObjectFileResult<TuxObject> {
+        let mut metadata = metadata.unwrap_or_else(|| self.metadata.clone());
+        carry_encryption_metadata(&self.metadata, &mut metadata);
+        let tags = match tags {
+            Some(tags) => tags,
+            None => self.read_tags()?,
+        };
+        let options = CreateOptions {
+            metadata,
+            tags,
+            layout: self.rewrite_layout(),
+            compression: self.header.compression_type,
+            compression_policy: CompressionPolicy::Fixed,
+            compression_workers: 0,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            sync: true,
+            versioned: false,
+            sniff_content_type: false,
+        };
+        let mut writer = ObjectWriter::create(dest, options)?;
+        writer.carry_bit_flags(self.header.bit_flags);
+        let content_length = self.header.content_length;
+        if !writer.copy_stored_content_in_kernel(&self.file, self.layout(), content_length)? {
+            let mut reader = self.stored_content_reader()?;
+            writer.copy_stored_content(&mut reader)?;
+        }
+        writer.finish()
+    }
+
     /// Rewrites the object with fresh section sizes, copying the content across.
-    fn rewrite(&mut self, metadata: MetadataMap, tags: Tags) -> ObjectFileResult<()> {
+    fn rewrite(&mut self, mut metadata: MetadataMap, tags: Tags) -> ObjectFileResult<()> {
+        carry_encryption_metadata(&self.metadata, &mut metadata);
+        self.rewrite_as_is(metadata, tags)
+    }
+
+    /// [TuxObject::rewrite] without protecting the encryption metadata, for the callers that are
+    /// changing it themselves.
+    fn rewrite_as_is(&mut self, metadata: MetadataMap, tags: Tags) -> ObjectFileResult<()> {
         self.ensure_writable()?;
 
         let options = CreateOptions {
             metadata,
             tags,
-            layout: LayoutOptions {
-                // Treat the current prefix as a floor. Without this every metadata edit would lay
-                // the file out from scratch and creep the content forward, so an object updated
-                // repeatedly would keep growing even when the sections did not.
-                min_content_start: self.header.content_start,
-                ..LayoutOptions::default()
-            },
+            layout: self.rewrite_layout(),
             compression: self.header.compression_type,
+            compression_policy: CompressionPolicy::Fixed,
+            compression_workers: 0,
+            // The ciphertext is copied as it is, so its existing encryption carries over.
+            #[cfg(feature = "encryption")]
+            encryption: None,
             sync: true,
+            versioned: false,
+            sniff_content_type: false,
         };
         let mut writer = ObjectWriter::create(&self.path, options)?;
+        writer.carry_bit_flags(self.header.bit_flags);
+        writer.carry_lock(self.lock, &self.file)?;
         {
             let mut reader = self.stored_content_reader()?;
-            std::io::copy(&mut reader, &mut writer)?;
+            writer.copy_stored_content(&mut reader)?;
         }
-        let replacement = writer.finish()?;
+        #[allow(unused_mut)]
+        let mut replacement = writer.finish()?;
+        #[cfg(feature = "encryption")]
+        replacement.set_cipher(self.cipher.take());
         *self = replacement;
         Ok(())
     }
 
+    /// Layout for a rewrite of this object.
+    fn rewrite_layout(&self) -> LayoutOptions {
+        LayoutOptions {
+            // Treat the current prefix as a floor. Without this every metadata edit would lay the
+            // file out from scratch and creep the content forward, so an object updated repeatedly
+            // would keep growing even when the sections did not.
+            min_content_start: self.header.content_start,
+            ..LayoutOptions::default()
+        }
+    }
+
+    /// Errors when the content is encrypted and there is no key to read it with.
+    fn ensure_readable(&self) -> ObjectFileResult<()> {
+        #[cfg(feature = "encryption")]
+        if self.cipher.is_some() {
+            return Ok(());
+        }
+        if self.is_encrypted() {
+            Err(ObjectFileError::ContentLocked)
+        } else {
+            Ok(())
+        }
+    }
+
     fn ensure_writable(&self) -> ObjectFileResult<()> {
         if self.writable {
             Ok(())
@@ -288,18 +792,25 @@ impl TuxObject {
     // -- content -------------------------------------------------------------------------------
 
     /// Reads the content exactly as stored, which for a compressed object means the compressed
-    /// bytes. See [TuxObject::decompressed_content_reader] to read through the codec.
+    /// bytes, and for an encrypted one the ciphertext. See [TuxObject::decompressed_content_reader]
+    /// to read through the codec.
     pub fn stored_content_reader(&mut self) -> ObjectFileResult<ContentReader<'_>> {
         let content_start = self.header.content_start as u64;
         let content_length = self.header.content_length;
         self.file.seek(SeekFrom::Start(content_start))?;
-        Ok(ContentReader::new(&mut self.file, content_length))
+        Ok(ContentReader::new(
+            &mut self.file,
+            content_start,
+            0,
+            content_length,
+        ))
     }
 
-    /// Reads a byte range of the stored content.
+    /// Reads a byte range of the stored content, decrypted when the object is encrypted.
     ///
     /// `length` of `None` reads to the end. Rejects compressed objects, where a byte offset into
-    /// the stored bytes does not correspond to an offset in the content.
+    /// the stored bytes does not correspond to an offset in the content. An encrypted object only
+    /// decrypts the segments the range touches.
     pub fn content_range_reader(
         &mut self,
         offset: u64,
@@ -308,7 +819,7 @@ impl TuxObject {
         if self.is_compressed() {
             return Err(ObjectFileError::RangedReadOnCompressed);
         }
-        let content_length = self.header.content_length;
+        let content_length = self.plaintext_length()?;
         let available =
             content_length
                 .checked_sub(offset)
@@ -326,15 +837,65 @@ impl TuxObject {
             });
         }
 
-        self.file
-            .seek(SeekFrom::Start(self.header.content_start as u64 + offset))?;
-        Ok(ContentReader::new(&mut self.file, length))
+        self.plaintext_reader(offset, length)
+    }
+
+    /// Reads content from `offset` into `buf` without moving the file's cursor, returning how many
+    /// bytes were read: all of `buf` unless the content ends first.
+    ///
+    /// Decrypted for an encrypted object, and rejected for a compressed one, as with
+    /// [TuxObject::content_range_reader]. An `offset` past the end is
+    /// [ObjectFileError::RangeOutOfBounds]; one exactly at the end reads nothing.
+    pub fn read_content_at(&self, offset: u64, buf: &mut [u8]) -> ObjectFileResult<usize> {
+        if self.is_compressed() {
+            return Err(ObjectFileError::RangedReadOnCompressed);
+        }
+        self.ensure_readable()?;
+        let content_length = self.plaintext_length()?;
+        PositionalContent {
+            file: &self.file,
+            header: &self.header,
+            #[cfg(feature = "encryption")]
+            cipher: self.cipher.as_ref(),
+        }
+        .read_at(offset, content_length, buf, &mut SegmentCache::default())
+    }
+
+    /// Reads `length` bytes of content from `offset`, through the cipher for an encrypted object.
+    fn plaintext_reader(
+        &mut self,
+        offset: u64,
+        length: u64,
+    ) -> ObjectFileResult<ContentReader<'_>> {
+        self.ensure_readable()?;
+        let content_start = self.header.content_start as u64;
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return Ok(ContentReader::decrypting(
+                &mut self.file,
+                cipher,
+                content_start,
+                self.header.content_length,
+                offset,
+                length,
+            )?);
+        }
+        self.file.seek(SeekFrom::Start(content_start + offset))?;
+        Ok(ContentReader::new(
+            &mut self.file,
+            content_start,
+            offset,
+            length,
+        ))
     }
 
     /// Reads the content through the object's codec, or straight through when uncompressed.
+    ///
+    /// An encrypted object is decrypted first, and must have been unlocked with `TuxObject::unlock`.
     pub fn decompressed_content_reader(&mut self) -> ObjectFileResult<DecodedContentReader<'_>> {
         let compression = self.header.compression_type;
-        let reader = self.stored_content_reader()?;
+        let length = self.plaintext_length()?;
+        let reader = self.plaintext_reader(0, length)?;
         match compression {
             CompressionTypes::None(_) => Ok(DecodedContentReader::Stored(reader)),
             #[cfg(feature = "zstd")]
@@ -343,7 +904,7 @@ impl TuxObject {
             ))),
             #[cfg(feature = "gzip")]
             CompressionTypes::Gzip(_) => Ok(DecodedContentReader::Gzip(Box::new(
-                flate2::read::GzDecoder::new(reader),
+                flate2::read::MultiGzDecoder::new(reader),
             ))),
             #[allow(unreachable_patterns)]
             other => Err(ObjectFileError::UnsupportedCompression(other)),
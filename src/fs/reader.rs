@@ -1,43 +1,174 @@
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
 
 /// A reader bounded to the content section of an object file.
 ///
 /// Reads stop at the end of the content even though the underlying file handle could keep going,
 /// so a caller cannot accidentally read padding or a neighbouring section.
+///
+/// For an encrypted object it reads through the cipher, so `remaining` counts plaintext bytes.
+///
+/// It also seeks, so the content can go to parsers that want `Read + Seek`. Positions are offsets
+/// into the content, with 0 its first byte, and a seek outside the reader's range — all of the
+/// content, or the range it was opened on — stops at the nearer end of it.
 #[derive(Debug)]
 pub struct ContentReader<'object> {
     file: &'object mut File,
-    remaining: u64,
+    /// Where the content starts in the file.
+    content_start: u64,
+    /// The reader's range, as offsets into the content, and where in it the next read starts.
+    start: u64,
+    position: u64,
+    end: u64,
+    #[cfg(feature = "encryption")]
+    decryption: Option<Decryption<'object>>,
+}
+
+/// Where a decrypting [ContentReader] is in the segment stream.
+#[cfg(feature = "encryption")]
+#[derive(Debug)]
+struct Decryption<'object> {
+    cipher: &'object crate::fs::encryption::SegmentCipher,
+    stored_length: u64,
+    /// The segment the file is positioned at.
+    next_segment: u64,
+    segment_count: u64,
+    /// Stored bytes from the start of `next_segment` to the end of the content.
+    stored_remaining: u64,
+    /// The current segment's plaintext, and how much of it has been handed out.
+    plaintext: Vec<u8>,
+    position: usize,
+    /// Bytes to skip at the start of the first segment, to land on the requested offset.
+    skip: usize,
 }
 
 impl<'object> ContentReader<'object> {
-    pub(crate) fn new(file: &'object mut File, remaining: u64) -> Self {
-        Self { file, remaining }
+    /// A reader over `length` bytes starting `offset` bytes into content that starts at
+    /// `content_start` in the file. `file` must already be positioned at that offset.
+    pub(crate) fn new(
+        file: &'object mut File,
+        content_start: u64,
+        offset: u64,
+        length: u64,
+    ) -> Self {
+        Self {
+            file,
+            content_start,
+            start: offset,
+            position: offset,
+            end: offset + length,
+            #[cfg(feature = "encryption")]
+            decryption: None,
+        }
+    }
+
+    /// A reader over `length` plaintext bytes starting `offset` bytes into encrypted content of
+    /// `stored_length` bytes, starting at `content_start` in the file. Seeks to the segment holding
+    /// `offset` and skips into it on the first read.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn decrypting(
+        file: &'object mut File,
+        cipher: &'object crate::fs::encryption::SegmentCipher,
+        content_start: u64,
+        stored_length: u64,
+        offset: u64,
+        length: u64,
+    ) -> std::io::Result<Self> {
+        let mut decryption = Decryption {
+            cipher,
+            stored_length,
+            next_segment: 0,
+            segment_count: cipher.segment_count(stored_length),
+            stored_remaining: stored_length,
+            plaintext: Vec::new(),
+            position: 0,
+            skip: 0,
+        };
+        decryption.seek(file, content_start, offset)?;
+        // A read of nothing would otherwise never look at a tag, and so never notice content that was
+        // cut down to a single empty segment.
+        if length == 0 && decryption.next_segment < decryption.segment_count {
+            decryption.load_segment(file)?;
+        }
+        Ok(Self {
+            file,
+            content_start,
+            start: offset,
+            position: offset,
+            end: offset + length,
+            decryption: Some(decryption),
+        })
     }
 
     /// Bytes still available from this reader.
     pub fn remaining(&self) -> u64 {
-        self.remaining
+        self.end - self.position
     }
 
     /// True once the whole range has been consumed.
     pub fn is_empty(&self) -> bool {
-        self.remaining == 0
+        self.position == self.end
     }
 }
 
 impl Read for ContentReader<'_> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.remaining == 0 || buf.is_empty() {
+        if self.is_empty() || buf.is_empty() {
             return Ok(0);
         }
-        let limit = self.remaining.min(buf.len() as u64) as usize;
+        let limit = self.remaining().min(buf.len() as u64) as usize;
+        #[cfg(feature = "encryption")]
+        let read = match &mut self.decryption {
+            Some(decryption) => decryption.read(self.file, &mut buf[..limit])?,
+            None => self.file.read(&mut buf[..limit])?,
+        };
+        #[cfg(not(feature = "encryption"))]
         let read = self.file.read(&mut buf[..limit])?;
-        self.remaining -= read as u64;
+        self.position += read as u64;
         Ok(read)
     }
 }
 
+impl Seek for ContentReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = content_seek_target(pos, self.position, self.end)?.clamp(self.start, self.end);
+        if target != self.position {
+            #[cfg(feature = "encryption")]
+            if let Some(decryption) = &mut self.decryption {
+                decryption.seek(self.file, self.content_start, target)?;
+                self.position = target;
+                return Ok(target);
+            }
+            self.file
+                .seek(SeekFrom::Start(self.content_start + target))?;
+            self.position = target;
+        }
+        Ok(target)
+    }
+
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+/// Where a seek on a content reader lands, as an offset into the content and before clamping to
+/// the reader's range: [SeekFrom::End] counts from `end`, the end of that range.
+pub(crate) fn content_seek_target(pos: SeekFrom, position: u64, end: u64) -> std::io::Result<u64> {
+    match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(delta) => end.checked_add_signed(delta),
+        SeekFrom::Current(delta) => position.checked_add_signed(delta),
+    }
+    .ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
 /// Streams the content section of an object through a decompressor.
 ///
 /// Only produced by [crate::fs::TuxObject::decompressed_content_reader]; for uncompressed objects
@@ -46,8 +177,10 @@ pub enum DecodedContentReader<'object> {
     Stored(ContentReader<'object>),
     #[cfg(feature = "zstd")]
     Zstd(Box<zstd::stream::read::Decoder<'static, std::io::BufReader<ContentReader<'object>>>>),
+    /// Reads every member, not just the first: [crate::fs::ContentEncoder::ParallelGzip] writes
+    /// content as several.
     #[cfg(feature = "gzip")]
-    Gzip(Box<flate2::read::GzDecoder<ContentReader<'object>>>),
+    Gzip(Box<flate2::read::MultiGzDecoder<ContentReader<'object>>>),
 }
 
 impl Read for DecodedContentReader<'_> {
@@ -61,3 +194,58 @@ impl Read for DecodedContentReader<'_> {
         }
     }
 }
+
+#[cfg(feature = "encryption")]
+impl Decryption<'_> {
+    /// Moves to plaintext `offset`: within the segment already decrypted when it holds `offset`,
+    /// otherwise by seeking the file to the segment that does, to be read on the next read.
+    fn seek(&mut self, file: &mut File, content_start: u64, offset: u64) -> std::io::Result<()> {
+        let segment_size = self.cipher.segment_size() as u64;
+        let segment = offset / segment_size;
+        let within = (offset % segment_size) as usize;
+        if !self.plaintext.is_empty()
+            && segment + 1 == self.next_segment
+            && within <= self.plaintext.len()
+        {
+            self.position = within;
+            return Ok(());
+        }
+        let stored_start = segment * self.cipher.stored_segment_size();
+        file.seek(SeekFrom::Start(content_start + stored_start))?;
+        self.next_segment = segment;
+        self.stored_remaining = self.stored_length.saturating_sub(stored_start);
+        self.plaintext.clear();
+        self.position = 0;
+        self.skip = within;
+        Ok(())
+    }
+
+    fn read(&mut self, file: &mut File, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.next_segment == self.segment_count {
+                return Ok(0);
+            }
+            self.load_segment(file)?;
+        }
+        let available = &self.plaintext[self.position..];
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read;
+        Ok(read)
+    }
+
+    /// Reads the next stored segment and authenticates it into `plaintext`.
+    fn load_segment(&mut self, file: &mut File) -> std::io::Result<()> {
+        let stored = self.cipher.stored_segment_size().min(self.stored_remaining);
+        self.plaintext.resize(stored as usize, 0);
+        file.read_exact(&mut self.plaintext)?;
+        self.stored_remaining -= stored;
+
+        let last = self.next_segment + 1 == self.segment_count;
+        self.cipher
+            .open(self.next_segment, last, &mut self.plaintext)?;
+        self.next_segment += 1;
+        self.position = std::mem::take(&mut self.skip).min(self.plaintext.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+//! Positional reads, which leave the file's cursor alone so one handle can serve many readers.
+//!
+//! [TuxObject] reads by seeking its file, so reading takes `&mut self` and one range at a time.
+//! [SharedTuxObject] only ever reads at an offset (`pread`), so all of its methods take `&self`:
+//! put it in an [Arc] and every request can read its own range of the same open file at once.
+
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    CompressionTypes, MetadataMap, ObjectHeader, ReadableObjectType, Tags, ValueType,
+    fs::{ObjectFileError, ObjectFileResult, SectionLayout, TuxObject},
+};
+
+/// Reads into `buf` from `offset` in the file, without using its cursor.
+#[cfg(unix)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+/// Reads into `buf` from `offset` in the file. Moves the cursor; see the `read_exact_at` below.
+#[cfg(windows)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Reads exactly `buf.len()` bytes from `offset` in the file, without using its cursor.
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Reads exactly `buf.len()` bytes from `offset` in the file.
+///
+/// Windows has no read that leaves the cursor alone, so this one moves it; nothing relies on where
+/// it is, since every seeking read seeks first.
+#[cfg(windows)]
+pub(crate) fn read_exact_at(
+    file: &File,
+    mut buf: &mut [u8],
+    mut offset: u64,
+) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                buf = &mut std::mem::take(&mut buf)[read..];
+                offset += read as u64;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// What a positional read of the content needs, borrowed from a [TuxObject] or a
+/// [SharedTuxObject].
+pub(crate) struct PositionalContent<'a> {
+    pub(crate) file: &'a File,
+    pub(crate) header: &'a ObjectHeader,
+    #[cfg(feature = "encryption")]
+    pub(crate) cipher: Option<&'a crate::fs::encryption::SegmentCipher>,
+}
+
+/// The last segment an encrypted read decrypted, so reading on through it in small pieces does
+/// not decrypt it again for each.
+#[derive(Debug, Default)]
+pub(crate) struct SegmentCache {
+    #[cfg(feature = "encryption")]
+    segment: Option<(u64, Vec<u8>)>,
+}
+
+impl PositionalContent<'_> {
+    /// Fills `buf` with the content from `offset`, as much of it as there is, returning how much
+    /// that was. `content_length` is the length of the content as read back.
+    pub(crate) fn read_at(
+        &self,
+        offset: u64,
+        content_length: u64,
+        buf: &mut [u8],
+        #[allow(unused_variables)] cache: &mut SegmentCache,
+    ) -> ObjectFileResult<usize> {
+        let available =
+            content_length
+                .checked_sub(offset)
+                .ok_or(ObjectFileError::RangeOutOfBounds {
+                    offset,
+                    end: offset,
+                    content_length,
+                })?;
+        let length = available.min(buf.len() as u64) as usize;
+        let buf = &mut buf[..length];
+        let content_start = self.header.content_start as u64;
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = self.cipher {
+            let segment_size = cipher.segment_size() as u64;
+            let segment_count = cipher.segment_count(self.header.content_length);
+            let mut filled = 0;
+            while filled < length {
+                let position = offset + filled as u64;
+                let index = position / segment_size;
+                let plaintext = match &mut cache.segment {
+                    Some((cached, plaintext)) if *cached == index => plaintext,
+                    slot => {
+                        let stored_start = index * cipher.stored_segment_size();
+                        let stored = cipher
+                            .stored_segment_size()
+                            .min(self.header.content_length - stored_start);
+                        let mut segment = vec![0; stored as usize];
+                        read_exact_at(self.file, &mut segment, content_start + stored_start)?;
+                        cipher.open(index, index + 1 == segment_count, &mut segment)?;
+                        &mut slot.insert((index, segment)).1
+                    }
+                };
+                let skip = (position % segment_size) as usize;
+                let taken = (plaintext.len() - skip).min(length - filled);
+                buf[filled..filled + taken].copy_from_slice(&plaintext[skip..skip + taken]);
+                filled += taken;
+            }
+            return Ok(length);
+        }
+        read_exact_at(self.file, buf, content_start + offset)?;
+        Ok(length)
+    }
+}
+
+/// An object handle for concurrent readers: every method takes `&self` and reads at an offset,
+/// so one handle, in an [Arc], serves any number of range requests at once.
+///
+/// Made from a [TuxObject] with [TuxObject::into_shared] — after `TuxObject::unlock`, for an
+/// encrypted object — or opened directly. Read-only; like any open handle it keeps showing the
+/// object it was opened on after an update replaces it.
+#[derive(Debug)]
+pub struct SharedTuxObject {
+    file: File,
+    path: PathBuf,
+    header: ObjectHeader,
+    metadata: MetadataMap,
+    #[cfg(feature = "encryption")]
+    cipher: Option<crate::fs::encryption::SegmentCipher>,
+}
+
+impl SharedTuxObject {
+    /// Opens an object for shared reading.
+    pub fn open(path: impl Into<PathBuf>) -> ObjectFileResult<Self> {
+        Ok(TuxObject::open(path)?.into_shared())
+    }
+
+    pub(crate) fn from_parts(
+        file: File,
+        path: PathBuf,
+        header: ObjectHeader,
+        metadata: MetadataMap,
+        #[cfg(feature = "encryption")] cipher: Option<crate::fs::encryption::SegmentCipher>,
+    ) -> Self {
+        Self {
+            file,
+            path,
+            header,
+            metadata,
+            #[cfg(feature = "encryption")]
+            cipher,
+        }
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn header(&self) -> &ObjectHeader {
+        &self.header
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn metadata(&self) -> &MetadataMap {
+        &self.metadata
+    }
+    /// See [ObjectHeader::generation].
+    pub fn generation(&self) -> u64 {
+        self.header.generation
+    }
+    /// Length of the stored content, which for a compressed object is the compressed length.
+    pub fn content_length(&self) -> u64 {
+        self.header.content_length
+    }
+    pub fn compression(&self) -> CompressionTypes {
+        self.header.compression_type
+    }
+    pub fn is_compressed(&self) -> bool {
+        !matches!(self.header.compression_type, CompressionTypes::None(_))
+    }
+    pub fn is_encrypted(&self) -> bool {
+        self.header.is_encrypted()
+    }
+    pub fn layout(&self) -> SectionLayout {
+        SectionLayout {
+            tags_start: self.header.tags_start,
+            content_start: self.header.content_start,
+        }
+    }
+
+    /// See [TuxObject::plaintext_length].
+    pub fn plaintext_length(&self) -> ObjectFileResult<u64> {
+        if !self.is_encrypted() {
+            return Ok(self.header.content_length);
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.plaintext_length(self.header.content_length);
+        }
+        Err(ObjectFileError::ContentLocked)
+    }
+
+    pub fn read_tags(&self) -> ObjectFileResult<Tags> {
+        let space = self.layout().tags_space();
+        if space == 0 {
+            return Ok(Tags::new());
+        }
+        let mut buffer = vec![0u8; space];
+        read_exact_at(&self.file, &mut buffer, self.header.tags_start as u64)?;
+        Ok(Tags::read_from_reader(&mut Cursor::new(&buffer))?)
+    }
+
+    /// Looks up a single tag, skipping over the values it does not need.
+    pub fn find_tag(&self, key: &str) -> ObjectFileResult<Option<ValueType>> {
+        let space = self.layout().tags_space();
+        if space == 0 {
+            return Ok(None);
+        }
+        let mut buffer = vec![0u8; space];
+        read_exact_at(&self.file, &mut buffer, self.header.tags_start as u64)?;
+        Ok(Tags::find_from_reader(
+            &mut Cursor::new(&buffer),
+            &key.to_owned(),
+        )?)
+    }
+
+    /// See [TuxObject::read_content_at].
+    pub fn read_content_at(&self, offset: u64, buf: &mut [u8]) -> ObjectFileResult<usize> {
+        let content_length = self.readable_length()?;
+        self.positional()
+            .read_at(offset, content_length, buf, &mut SegmentCache::default())
+    }
+
+    /// Streams a byte range of the content, from `offset` for `length` bytes or to the end.
+    ///
+    /// Each reader keeps its own position, so any number can be open on the handle at once. Rejects
+    /// compressed objects, as [TuxObject::content_range_reader] does.
+    pub fn content_range_reader(
+        &self,
+        offset: u64,
+        length: Option<u64>,
+    ) -> ObjectFileResult<SharedContentReader<&Self>> {
+        SharedContentReader::new(self, offset, length)
+    }
+
+    /// [SharedTuxObject::content_range_reader], holding the handle itself, for a body that
+    /// outlives the borrow.
+    pub fn into_content_range_reader(
+        self: Arc<Self>,
+        offset: u64,
+        length: Option<u64>,
+    ) -> ObjectFileResult<SharedContentReader<Arc<Self>>> {
+        SharedContentReader::new(self, offset, length)
+    }
+
+    /// The content length, once the content is known to be readable at an offset.
+    fn readable_length(&self) -> ObjectFileResult<u64> {
+        if self.is_compressed() {
+            return Err(ObjectFileError::RangedReadOnCompressed);
+        }
+        self.plaintext_length()
+    }
+
+    fn positional(&self) -> PositionalContent<'_> {
+        PositionalContent {
+            file: &self.file,
+            header: &self.header,
+            #[cfg(feature = "encryption")]
+            cipher: self.cipher.as_ref(),
+        }
+    }
+}
+
+/// A [Read] over a range of a [SharedTuxObject]'s content, tracking its own position.
+///
+/// Generic over how the object is held: borrowed, or in an [Arc].
+#[derive(Debug)]
+pub struct SharedContentReader<O> {
+    object: O,
+    position: u64,
+    end: u64,
+    content_length: u64,
+    cache: SegmentCache,
+}
+
+impl<O: Deref<Target = SharedTuxObject>> SharedContentReader<O> {
+    fn new(object: O, offset: u64, length: Option<u64>) -> ObjectFileResult<Self> {
+        let content_length = object.readable_length()?;
+        let available =
+            content_length
+                .checked_sub(offset)
+                .ok_or(ObjectFileError::RangeOutOfBounds {
+                    offset,
+                    end: offset,
+                    content_length,
+                })?;
+        let length = length.unwrap_or(available);
+        if length > available {
+            return Err(ObjectFileError::RangeOutOfBounds {
+                offset,
+                end: offset.saturating_add(length),
+                content_length,
+            });
+        }
+        Ok(Self {
+            object,
+            position: offset,
+            end: offset + length,
+            content_length,
+            cache: SegmentCache::default(),
+        })
+    }
+
+    /// Bytes still available from this reader.
+    pub fn remaining(&self) -> u64 {
+        self.end - self.position
+    }
+
+    /// True once the whole range has been consumed.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.end
+    }
+}
+
+impl<O: Deref<Target = SharedTuxObject>> Read for SharedContentReader<O> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let limit = self.remaining().min(buf.len() as u64) as usize;
+        if limit == 0 {
+            return Ok(0);
+        }
+        let read = self
+            .object
+            .positional()
+            .read_at(
+                self.position,
+                self.content_length,
+                &mut buf[..limit],
+                &mut self.cache,
+            )
+            .map_err(|err| match err {
+                ObjectFileError::IO(err) => err,
+                err => std::io::Error::other(err),
+            })?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
@@ -0,0 +1,253 @@
+//! Guessing an object's `Content-Type` from the start of its content.
+//!
+//! Opted into with [CreateOptions::with_content_type_sniffing]: the writer keeps the first
+//! [SNIFF_BYTES] of the content as it streams past, and [crate::fs::ObjectWriter::finish] fills in
+//! [CONTENT_TYPE] from them when the metadata has none. Nothing is read back from the file.
+
+use http::header::CONTENT_TYPE;
+
+use crate::{MetadataMap, TuxIOType, fs::CreateOptions};
+
+/// How much of the content is looked at.
+pub const SNIFF_BYTES: usize = 512;
+
+/// What [sniff_content_type] calls valid UTF-8 without control characters.
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Magic numbers, and the type they announce.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    // An empty archive, and one written in spanned mode.
+    (b"PK\x05\x06", "application/zip"),
+    (b"PK\x07\x08", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+];
+
+/// Guesses the type of content starting with `sample`, which should be its first [SNIFF_BYTES] —
+/// or all of it, when there is less.
+///
+/// Knows PNG, JPEG, GIF, PDF, zip, gzip and zstd by their magic numbers, then JSON and plain text
+/// by being valid UTF-8. `None` for empty content and anything else, which S3 would call
+/// `binary/octet-stream`.
+pub fn sniff_content_type(sample: &[u8]) -> Option<&'static str> {
+    if let Some((_, content_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| sample.starts_with(signature))
+    {
+        return Some(content_type);
+    }
+    let text = utf8_prefix(sample)?;
+    if text.is_empty() {
+        return None;
+    }
+    if looks_like_json(text) {
+        return Some("application/json");
+    }
+    // Whitespace, and escape for terminal colours, are the only control characters text has.
+    let is_text = text
+        .chars()
+        .all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'));
+    is_text.then_some(TEXT_CONTENT_TYPE)
+}
+
+/// `sample` as text, when it is valid UTF-8 up to a character the sample may have cut short.
+fn utf8_prefix(sample: &[u8]) -> Option<&str> {
+    match std::str::from_utf8(sample) {
+        Ok(text) => Some(text),
+        // `error_len` is `None` only for a sequence the end of the input interrupted.
+        Err(err) if err.error_len().is_none() => {
+            Some(std::str::from_utf8(&sample[..err.valid_up_to()]).expect("checked valid"))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Whether `text` opens the way a JSON object or array does — enough to tell it from text that
+/// merely starts with a bracket, such as `[INFO] started`.
+fn looks_like_json(text: &str) -> bool {
+    let mut chars = text
+        .trim_start_matches('\u{feff}')
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace());
+    let second = |chars: &mut dyn Iterator<Item = char>, allowed: &dyn Fn(char) -> bool| {
+        // A sample that ends after the bracket gives the benefit of the doubt.
+        chars.next().is_none_or(allowed)
+    };
+    match chars.next() {
+        Some('{') => second(&mut chars, &|c| matches!(c, '"' | '}')),
+        Some('[') => second(&mut chars, &|c| {
+            matches!(c, '{' | '[' | '"' | '-' | ']' | 't' | 'f' | 'n') || c.is_ascii_digit()
+        }),
+        _ => false,
+    }
+}
+
+/// The start of the content, collected as it is written.
+#[derive(Debug, Default)]
+pub(crate) struct ContentSniffer {
+    sample: Vec<u8>,
+}
+
+impl ContentSniffer {
+    pub(crate) fn feed(&mut self, bytes: &[u8]) {
+        let taken = SNIFF_BYTES
+            .saturating_sub(self.sample.len())
+            .min(bytes.len());
+        self.sample.extend_from_slice(&bytes[..taken]);
+    }
+
+    pub(crate) fn content_type(&self) -> Option<&'static str> {
+        sniff_content_type(&self.sample)
+    }
+
+    /// Fills in the content type, unless `metadata` already has one.
+    pub(crate) fn fill_in(&self, metadata: &mut MetadataMap) {
+        if metadata.contains_header(&CONTENT_TYPE) {
+            return;
+        }
+        if let Some(content_type) = self.content_type() {
+            metadata.insert_header(CONTENT_TYPE, content_type.to_owned().into());
+        }
+    }
+
+    /// Extra metadata room to reserve for the longest type a sniff can fill in, so filling it in
+    /// does not outgrow the prefix and force the content to move.
+    pub(crate) fn reserve() -> usize {
+        let mut metadata = MetadataMap::new();
+        let empty = metadata.size();
+        metadata.insert_header(CONTENT_TYPE, TEXT_CONTENT_TYPE.to_owned().into());
+        metadata.size() - empty
+    }
+}
+
+impl CreateOptions {
+    /// Fills in [CONTENT_TYPE] from the start of the content when the metadata has none by the
+    /// time the object is finished. See [sniff_content_type] for what is recognized.
+    pub fn with_content_type_sniffing(mut self, sniff_content_type: bool) -> Self {
+        self.sniff_content_type = sniff_content_type;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::fs::testing::TempDir;
+    use crate::{CompressionTypes, fs::TuxObject};
+
+    #[test]
+    fn content_is_recognized_by_its_start() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some("image/png")),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", Some("image/jpeg")),
+            (b"GIF89a\x01\0\x01\0", Some("image/gif")),
+            (b"%PDF-1.7\n", Some("application/pdf")),
+            (b"PK\x03\x04\x14\0", Some("application/zip")),
+            (b"\x1f\x8b\x08\0", Some("application/gzip")),
+            (b"\x28\xb5\x2f\xfd\x04\0", Some("application/zstd")),
+            (b"  {\"key\": [1, 2]}", Some("application/json")),
+            (b"\xef\xbb\xbf[{\"a\": 1}]", Some("application/json")),
+            (b"[]", Some("application/json")),
+            (b"[INFO] started\n", Some(TEXT_CONTENT_TYPE)),
+            (
+                "h\u{e9}llo, w\u{f6}rld\r\n".as_bytes(),
+                Some(TEXT_CONTENT_TYPE),
+            ),
+            (b"", None),
+            (b"\0\x01\x02\x03", None),
+            (b"caf\xc3", Some(TEXT_CONTENT_TYPE)),
+            (b"caf\xc3(", None),
+        ];
+        for (sample, expected) in cases {
+            assert_eq!(sniff_content_type(sample), *expected, "{sample:?}");
+        }
+    }
+
+    fn content_type(object: &TuxObject) -> Option<&str> {
+        object.metadata().get_header(&CONTENT_TYPE)?.as_str()
+    }
+
+    #[test]
+    fn writers_fill_in_the_sniffed_type() {
+        let dir = TempDir::new("writer");
+        let path = dir.0.join("object.tuxio");
+        let options = CreateOptions::new().with_content_type_sniffing(true);
+
+        let mut writer = TuxObject::create(&path, options.clone()).unwrap();
+        // Written a byte at a time, to sniff across writes.
+        for byte in b"{\"hello\": \"world\"}" {
+            writer.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(writer.sniffed_content_type(), Some("application/json"));
+        let object = writer.finish().unwrap();
+        assert_eq!(content_type(&object), Some("application/json"));
+
+        // A type the caller gave is kept.
+        let mut metadata = MetadataMap::new();
+        metadata.insert_header(CONTENT_TYPE, "text/csv".to_owned().into());
+        let mut writer = TuxObject::create(&path, options.clone().with_metadata(metadata)).unwrap();
+        writer.write_all(b"a,b\n1,2\n").unwrap();
+        assert_eq!(content_type(&writer.finish().unwrap()), Some("text/csv"));
+
+        // Without opting in, nothing is filled in.
+        let mut writer = TuxObject::create(&path, CreateOptions::new()).unwrap();
+        writer.write_all(b"plain text").unwrap();
+        assert_eq!(writer.sniffed_content_type(), None);
+        assert_eq!(content_type(&writer.finish().unwrap()), None);
+    }
+
+    /// The sniff sees the content as written, not as it is stored.
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_content_is_sniffed_before_compression() {
+        let dir = TempDir::new("compressed");
+        let path = dir.0.join("object.tuxio");
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(CompressionTypes::ZSTD(
+                    crate::compression_types::ZStdCompressionType(3),
+                ))
+                .with_content_type_sniffing(true),
+        )
+        .unwrap();
+        {
+            let mut encoder = writer.content_encoder().unwrap();
+            encoder.write_all(b"%PDF-1.7\n").unwrap();
+            encoder.write_all(&[b'x'; 4096]).unwrap();
+            encoder.finish().unwrap();
+        }
+        assert_eq!(
+            content_type(&writer.finish().unwrap()),
+            Some("application/pdf")
+        );
+    }
+
+    #[test]
+    fn uncompressed_encoders_are_sniffed_once() {
+        let dir = TempDir::new("stored");
+        let path = dir.0.join("object.tuxio");
+        let mut writer = TuxObject::create(
+            &path,
+            CreateOptions::new()
+                .with_compression(CompressionTypes::default())
+                .with_content_type_sniffing(true),
+        )
+        .unwrap();
+        {
+            let mut encoder = writer.content_encoder().unwrap();
+            encoder.write_all(b"{").unwrap();
+            encoder.finish().unwrap();
+        }
+        // Seen twice, `{{` would have been taken for text.
+        assert_eq!(writer.sniffed_content_type(), Some("application/json"));
+    }
+}
@@ -0,0 +1,641 @@
+//! A directory of objects addressed by key, in the manner of an S3 bucket.
+//!
+//! [ObjectStore] maps each key to a path under its root — see [OBJECT_EXTENSION] and the escaping
+//! rules in the `keys` module — and takes care of what every caller of [TuxObject::create] would
+//! otherwise repeat: creating parent directories on the way in, and pruning the empty ones a delete
+//! leaves behind. Writes still go through an [ObjectWriter], so they stay atomic.
+//!
+//! ```no_run
+//! use std::io::Write;
+//! use tux_io_encoding::fs::{CreateOptions, store::ObjectStore};
+//!
+//! let store = ObjectStore::new("/var/lib/objects").with_shard_levels(2);
+//! let mut writer = store.put("photos/cat.jpg", CreateOptions::new())?;
+//! writer.write_all(b"meow")?;
+//! writer.finish()?;
+//!
+//! assert_eq!(store.list("photos/")?, ["photos/cat.jpg"]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Keys that differ only in case share a file on a case-insensitive filesystem.
+//!
+//! With the `multipart` feature, `MultipartUpload` assembles an object from parts sent separately.
+
+#[cfg(feature = "tokio")]
+mod async_store;
+mod keys;
+mod listing;
+#[cfg(feature = "multipart")]
+mod multipart;
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "tokio")]
+pub use async_store::*;
+pub use keys::{MAX_KEY_LENGTH, OBJECT_EXTENSION};
+pub use listing::*;
+#[cfg(feature = "multipart")]
+pub use multipart::*;
+
+use self::listing::{Page, PageBuilder, WalkEntry};
+use crate::{
+    MetadataMap, ObjectHeader,
+    fs::{
+        CreateOptions, ObjectFileResult, ObjectWriter, TuxObject, object_files,
+        versions::{self, ObjectVersion},
+        writer::create_temp_file,
+    },
+};
+
+/// Most shard levels a store can have: one per byte of the key's hash.
+pub const MAX_SHARD_LEVELS: u8 = 8;
+
+/// How many times a write retries when a concurrent delete prunes the directory it just created.
+const CREATE_ATTEMPTS: usize = 4;
+
+/// An object's header and metadata, without its tags or content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectHead {
+    pub header: ObjectHeader,
+    pub metadata: MetadataMap,
+}
+
+/// Where a store keeps its objects. Shared by [ObjectStore] and its async twin.
+#[derive(Debug, Clone)]
+struct StoreLayout {
+    root: PathBuf,
+    shard_levels: u8,
+}
+
+impl StoreLayout {
+    fn object_path(&self, key: &str) -> ObjectFileResult<PathBuf> {
+        let relative = keys::key_to_relative_path(key)?;
+        Ok(self
+            .root
+            .join(keys::shard_path(key, self.shard_levels))
+            .join(relative))
+    }
+
+    /// The directory a listing of `prefix` has to walk. Shards scatter keys with a common prefix,
+    /// so a sharded store is always walked whole.
+    fn list_start(&self, prefix: &str) -> PathBuf {
+        if self.shard_levels == 0 {
+            self.root.join(keys::prefix_to_relative_dir(prefix))
+        } else {
+            self.root.clone()
+        }
+    }
+
+    /// Where a walk in key order starts for `prefix` in an unsharded store: the directory, and the
+    /// prefix every key under it has.
+    fn walk_start(&self, prefix: &str) -> (PathBuf, String) {
+        let key_prefix = match prefix.rfind('/') {
+            Some(end) => prefix[..=end].to_owned(),
+            None => String::new(),
+        };
+        (self.list_start(prefix), key_prefix)
+    }
+
+    /// The key of the object at `path`, when it is one of this store's and sits in its own shard.
+    fn key_at(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let mut components = relative.components();
+        let shards: PathBuf = components
+            .by_ref()
+            .take(self.shard_levels as usize)
+            .collect();
+        let key = keys::relative_path_to_key(components.as_path())?;
+        (shards == keys::shard_path(&key, self.shard_levels)).then_some(key)
+    }
+
+    /// The directories between `path` and the root, nearest first, for pruning once they empty.
+    fn parents_below_root<'path>(&self, path: &'path Path) -> impl Iterator<Item = &'path Path> {
+        path.ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root) && *dir != self.root)
+    }
+}
+
+/// A directory of objects addressed by key. See the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    layout: StoreLayout,
+}
+
+impl ObjectStore {
+    /// A store rooted at `root`. Nothing is created until the first [ObjectStore::put].
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            layout: StoreLayout {
+                root: root.into(),
+                shard_levels: 0,
+            },
+        }
+    }
+
+    /// Spreads objects across `levels` levels of 256 directories each, chosen by a hash of the
+    /// key, so no one directory grows too large. At most [MAX_SHARD_LEVELS].
+    ///
+    /// Part of where every object lives: opening an existing store with a different value finds
+    /// none of its objects.
+    pub fn with_shard_levels(mut self, levels: u8) -> Self {
+        self.layout.shard_levels = levels.min(MAX_SHARD_LEVELS);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.layout.root
+    }
+    pub fn shard_levels(&self) -> u8 {
+        self.layout.shard_levels
+    }
+
+    /// Where the object under `key` is stored, whether or not it exists.
+    pub fn object_path(&self, key: &str) -> ObjectFileResult<PathBuf> {
+        self.layout.object_path(key)
+    }
+
+    /// Starts writing the object under `key`, replacing any existing one once finished.
+    pub fn put(&self, key: &str, options: CreateOptions) -> ObjectFileResult<ObjectWriter> {
+        let path = self.object_path(key)?;
+        let mut attempt = 1;
+        loop {
+            create_parent(&path)?;
+            match ObjectWriter::create(&path, options.clone()) {
+                Err(err) if err.is_not_found() && attempt < CREATE_ATTEMPTS => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Opens the object under `key` for reading, or `None` when there is none.
+    pub fn get(&self, key: &str) -> ObjectFileResult<Option<TuxObject>> {
+        TuxObject::open_optional(self.object_path(key)?)
+    }
+
+    /// Opens the object under `key` for reading and updating its sections.
+    pub fn get_writable(&self, key: &str) -> ObjectFileResult<Option<TuxObject>> {
+        match TuxObject::open_writable(self.object_path(key)?) {
+            Ok(object) => Ok(Some(object)),
+            Err(err) if err.is_not_found() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads only the header and metadata of the object under `key`.
+    pub fn head(&self, key: &str) -> ObjectFileResult<Option<ObjectHead>> {
+        Ok(self.get(key)?.map(TuxObject::into_head))
+    }
+
+    /// Deletes the object under `key`, returning whether there was one.
+    ///
+    /// A versioned object is deleted with a delete marker, keeping its versions: see
+    /// [versions::place_delete_marker].
+    pub fn delete(&self, key: &str) -> ObjectFileResult<bool> {
+        let path = self.object_path(key)?;
+        if versions::is_versioned(&path) {
+            let existed = path.exists();
+            versions::place_delete_marker(&path)?;
+            return Ok(existed);
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        }
+        self.prune(&path);
+        Ok(true)
+    }
+
+    /// The history of the object under `key`, newest first. See [versions::list_versions].
+    pub fn list_versions(&self, key: &str) -> ObjectFileResult<Vec<ObjectVersion>> {
+        versions::list_versions(self.object_path(key)?)
+    }
+
+    /// Opens a version of the object under `key` for reading. See [versions::open_version].
+    pub fn open_version(&self, key: &str, version_id: &str) -> ObjectFileResult<TuxObject> {
+        versions::open_version(self.object_path(key)?, version_id)
+    }
+
+    /// Removes a version of the object under `key` for good. See [versions::delete_version].
+    pub fn delete_version(&self, key: &str, version_id: &str) -> ObjectFileResult<bool> {
+        versions::delete_version(self.object_path(key)?, version_id)
+    }
+
+    /// Copies the object under `from` to `to`, byte for byte, replacing any object already there.
+    /// Returns `false` when there is nothing under `from`.
+    ///
+    /// The copy is written to a temporary file and renamed into place, so it is atomic like any
+    /// other write — but it is not recorded as a version of `to`, nor the rename of
+    /// [ObjectStore::rename] as a delete of `from`. Write through [ObjectStore::put] to keep
+    /// versions.
+    pub fn copy(&self, from: &str, to: &str) -> ObjectFileResult<bool> {
+        let from_path = self.object_path(from)?;
+        let to_path = self.object_path(to)?;
+        let mut source = match File::open(&from_path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut attempt = 1;
+        let (mut temp, temp_path) = loop {
+            create_parent(&to_path)?;
+            match create_temp_file(&to_path) {
+                Err(err) if err.is_not_found() && attempt < CREATE_ATTEMPTS => attempt += 1,
+                result => break result?,
+            }
+        };
+        let copied = std::io::copy(&mut source, &mut temp)
+            .and_then(|_| temp.sync_all())
+            .and_then(|()| std::fs::rename(&temp_path, &to_path));
+        if let Err(err) = copied {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err.into());
+        }
+        sync_parent(&to_path);
+        Ok(true)
+    }
+
+    /// Moves the object under `from` to `to`, replacing any object already there. Returns `false`
+    /// when there is nothing under `from`.
+    ///
+    /// A single rename, so atomic, and the content is never copied.
+    pub fn rename(&self, from: &str, to: &str) -> ObjectFileResult<bool> {
+        let from_path = self.object_path(from)?;
+        let to_path = self.object_path(to)?;
+        let mut attempt = 1;
+        loop {
+            create_parent(&to_path)?;
+            match std::fs::rename(&from_path, &to_path) {
+                Ok(()) => break,
+                // Either the source is gone, or a delete pruned the destination directory.
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    if !from_path.exists() {
+                        return Ok(false);
+                    }
+                    if attempt == CREATE_ATTEMPTS {
+                        return Err(err.into());
+                    }
+                    attempt += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        sync_parent(&to_path);
+        self.prune(&from_path);
+        Ok(true)
+    }
+
+    /// Every key starting with `prefix`, in byte order.
+    pub fn list(&self, prefix: &str) -> ObjectFileResult<Vec<String>> {
+        let mut keys: Vec<String> = object_files(self.layout.list_start(prefix))?
+            .iter()
+            .filter_map(|path| self.layout.key_at(path))
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// One page of the keys matching `options`, in byte order, with common prefixes rolled up as
+    /// S3's ListObjectsV2 does.
+    ///
+    /// An unsharded store is walked in key order from the prefix's directory, skipping directories
+    /// that hold nothing after [ListOptions::start_after] or only keys of a common prefix already
+    /// listed, and stopping one key past a full page — so a page costs about what it lists. Shards
+    /// scatter keys, so a sharded store has to be walked and sorted whole for **every** page: paging
+    /// through `n` keys reads the store `n / max_keys` times over. Prefer an unsharded store where
+    /// large listings matter.
+    ///
+    /// Asking for [ListOptions::details] opens each listed object, reading only its header and
+    /// metadata. An object deleted between the walk and that read is left out of the page.
+    pub fn list_objects(&self, options: &ListOptions) -> ObjectFileResult<ObjectListing> {
+        let page = self.list_page(options)?;
+        let mut objects = Vec::with_capacity(page.keys.len());
+        for key in page.keys {
+            match &options.details {
+                None => objects.push(ListedObject::new(key)),
+                Some(metadata) => {
+                    if let Some(head) = self.head(&key)? {
+                        objects.push(ListedObject::with_head(key, head, metadata));
+                    }
+                }
+            }
+        }
+        Ok(ObjectListing {
+            objects,
+            common_prefixes: page.common_prefixes,
+            is_truncated: page.is_truncated,
+            next_start_after: page.next_start_after,
+        })
+    }
+
+    /// The keys and common prefixes of the page `options` selects. See [ObjectStore::list_objects].
+    fn list_page(&self, options: &ListOptions) -> ObjectFileResult<Page> {
+        let mut page = PageBuilder::new(options);
+        if self.layout.shard_levels > 0 {
+            for key in self.list(&options.prefix)? {
+                if !page.push(key) {
+                    break;
+                }
+            }
+            return Ok(page.finish());
+        }
+        let (start, key_prefix) = self.layout.walk_start(&options.prefix);
+        let mut pending = vec![walk_dir(&start, &key_prefix)?];
+        while let Some(entries) = pending.last_mut() {
+            let Some(entry) = entries.pop() else {
+                pending.pop();
+                continue;
+            };
+            if entry.is_dir {
+                if page.wants(&entry.key) {
+                    pending.push(walk_dir(&entry.path, &entry.key)?);
+                }
+            } else if !page.push(entry.key) {
+                break;
+            }
+        }
+        Ok(page.finish())
+    }
+
+    /// Removes the directories left empty above `path`, stopping at the first that is not.
+    fn prune(&self, path: &Path) {
+        for dir in self.layout.parents_below_root(path) {
+            if std::fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// The entries of `dir`, whose keys all start with `key_prefix`, in [WalkEntry::walk_order].
+fn walk_dir(dir: &Path, key_prefix: &str) -> ObjectFileResult<Vec<WalkEntry>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // Pruned by a delete since it was seen, or never there.
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut walk = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if (file_type.is_dir() || file_type.is_file())
+            && let Some(entry) = WalkEntry::new(entry.path(), file_type.is_dir(), key_prefix)
+        {
+            walk.push(entry);
+        }
+    }
+    WalkEntry::walk_order(&mut walk);
+    Ok(walk)
+}
+
+fn create_parent(path: &Path) -> ObjectFileResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Flushes the directory entry of a rename into `path`.
+fn sync_parent(path: &Path) {
+    if let Some(parent) = path.parent()
+        && let Ok(dir) = File::open(parent)
+    {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use http::header::CONTENT_TYPE;
+
+    use super::*;
+    use crate::fs::testing::TempDir;
+
+    fn put(store: &ObjectStore, key: &str, content: &[u8]) {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(CONTENT_TYPE.into(), "text/plain".to_owned().into());
+        let mut writer = store
+            .put(key, CreateOptions::new().with_metadata(metadata))
+            .unwrap();
+        writer.write_all(content).unwrap();
+        writer.finish().unwrap();
+    }
+
+    fn read(store: &ObjectStore, key: &str) -> Option<Vec<u8>> {
+        let mut object = store.get(key).unwrap()?;
+        let mut content = Vec::new();
+        object
+            .stored_content_reader()
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        Some(content)
+    }
+
+    #[test]
+    fn objects_round_trip_by_key() {
+        for levels in [0, 2] {
+            let dir = TempDir::new(&format!("round-trip-{levels}"));
+            let store = ObjectStore::new(dir.0.join("bucket")).with_shard_levels(levels);
+
+            assert!(store.get("missing").unwrap().is_none());
+            assert!(store.head("missing").unwrap().is_none());
+            put(&store, "docs/readme.md", b"hello");
+            put(&store, "docs/../escape", b"contained");
+
+            assert_eq!(read(&store, "docs/readme.md").unwrap(), b"hello");
+            assert_eq!(read(&store, "docs/../escape").unwrap(), b"contained");
+            assert!(
+                store
+                    .object_path("docs/../escape")
+                    .unwrap()
+                    .starts_with(store.root())
+            );
+            let head = store.head("docs/readme.md").unwrap().unwrap();
+            assert_eq!(head.header.content_length, 5);
+            assert!(head.metadata.get_header(&CONTENT_TYPE).is_some());
+        }
+    }
+
+    #[test]
+    fn listing_filters_by_prefix_in_key_order() {
+        for levels in [0, 1] {
+            let dir = TempDir::new(&format!("list-{levels}"));
+            let store = ObjectStore::new(&dir.0).with_shard_levels(levels);
+            for key in ["b/2", "a", "b/1", "b/10/x", "ba", "c/"] {
+                put(&store, key, key.as_bytes());
+            }
+            // A writer in progress leaves a temporary file that must not show up.
+            let _writer = store.put("b/3", CreateOptions::new()).unwrap();
+
+            assert_eq!(
+                store.list("").unwrap(),
+                ["a", "b/1", "b/10/x", "b/2", "ba", "c/"]
+            );
+            assert_eq!(store.list("b/1").unwrap(), ["b/1", "b/10/x"]);
+            assert_eq!(store.list("b").unwrap(), ["b/1", "b/10/x", "b/2", "ba"]);
+            assert!(store.list("z/").unwrap().is_empty());
+        }
+    }
+
+    /// Walking by directory has to meet keys in the order a sorted listing has them, though
+    /// escaping orders names differently: `%` sorts before `~`, while `é` sorts after it.
+    #[test]
+    fn pages_walk_keys_in_byte_order() {
+        let keys = [
+            "a", "a b", "a~", "a\u{e9}", "a/", "a/b", "a0", "a.tuxio", "b/c/d", "b/c/e", "b/x",
+            "b!",
+        ];
+        for levels in [0, 1] {
+            let dir = TempDir::new(&format!("pages-{levels}"));
+            let store = ObjectStore::new(&dir.0).with_shard_levels(levels);
+            for key in keys {
+                put(&store, key, key.as_bytes());
+            }
+            for base in [
+                ListOptions::new(),
+                ListOptions::new().with_delimiter("/"),
+                ListOptions::new().with_prefix("a"),
+                ListOptions::new().with_prefix("b/").with_delimiter("/"),
+                ListOptions::new().with_delimiter("c/"),
+            ] {
+                for max_keys in [1, 2, 5, 1000] {
+                    let expected = Page::select(store.list(&base.prefix).unwrap(), &{
+                        let mut options = base.clone();
+                        options.max_keys = usize::MAX;
+                        options
+                    });
+                    let mut options = base.clone().with_max_keys(max_keys);
+                    let (mut keys, mut common_prefixes) = (Vec::new(), Vec::new());
+                    loop {
+                        let listing = store.list_objects(&options).unwrap();
+                        keys.extend(listing.objects.into_iter().map(|object| object.key));
+                        common_prefixes.extend(listing.common_prefixes);
+                        match listing.next_start_after {
+                            Some(next) => options = options.with_start_after(next),
+                            None => break,
+                        }
+                    }
+                    assert_eq!(keys, expected.keys, "{base:?} by {max_keys}");
+                    assert_eq!(common_prefixes, expected.common_prefixes, "{base:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn listed_objects_carry_the_details_asked_for() {
+        let dir = TempDir::new("list-objects");
+        let store = ObjectStore::new(&dir.0).with_shard_levels(1);
+        for key in ["logs/1", "logs/2", "logs/old/1", "readme"] {
+            put(&store, key, key.as_bytes());
+        }
+
+        let listing = store
+            .list_objects(
+                &ListOptions::new()
+                    .with_prefix("logs/")
+                    .with_delimiter("/")
+                    .with_details([CONTENT_TYPE, http::header::ETAG]),
+            )
+            .unwrap();
+        assert_eq!(listing.common_prefixes, ["logs/old/"]);
+        assert_eq!(listing.objects.len(), 2);
+        let first = &listing.objects[0];
+        assert_eq!(first.key, "logs/1");
+        assert_eq!(first.content_length, Some(6));
+        assert_eq!(first.metadata.0.len(), 1);
+        assert!(first.metadata.get_header(&CONTENT_TYPE).is_some());
+
+        let listing = store
+            .list_objects(&ListOptions::new().with_max_keys(1))
+            .unwrap();
+        assert_eq!(listing.objects, [ListedObject::new("logs/1".to_owned())]);
+        assert_eq!(listing.next_start_after.as_deref(), Some("logs/1"));
+    }
+
+    #[test]
+    fn versioned_keys_are_deleted_with_a_marker() {
+        let dir = TempDir::new("versions");
+        let store = ObjectStore::new(&dir.0).with_shard_levels(1);
+        let mut writer = store
+            .put("docs/a", CreateOptions::new().with_versioning(true))
+            .unwrap();
+        writer.write_all(b"one").unwrap();
+        let first = writer.finish().unwrap();
+        put(&store, "docs/a", b"two");
+
+        assert!(store.delete("docs/a").unwrap());
+        assert!(store.get("docs/a").unwrap().is_none());
+        // Neither the versions nor their directory show up as keys.
+        assert!(store.list("").unwrap().is_empty());
+
+        let versions = store.list_versions("docs/a").unwrap();
+        assert_eq!(versions.len(), 3);
+        assert!(versions[0].is_delete_marker && versions[0].is_latest);
+        let first_id = versions::stamped_version_id(first.metadata()).unwrap();
+        let mut kept = store.open_version("docs/a", first_id).unwrap();
+        assert_eq!(kept.read_content_to_vec().unwrap(), b"one");
+
+        assert!(
+            store
+                .delete_version("docs/a", &versions[0].version_id)
+                .unwrap()
+        );
+        assert_eq!(read(&store, "docs/a").unwrap(), b"two");
+    }
+
+    #[test]
+    fn deleting_prunes_empty_directories() {
+        let dir = TempDir::new("delete");
+        let store = ObjectStore::new(&dir.0).with_shard_levels(1);
+        put(&store, "a/b/c", b"deep");
+
+        assert!(store.delete("a/b/c").unwrap());
+        assert!(!store.delete("a/b/c").unwrap());
+        assert!(store.get("a/b/c").unwrap().is_none());
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn copy_and_rename_move_whole_objects() {
+        let dir = TempDir::new("copy-rename");
+        let store = ObjectStore::new(&dir.0);
+        put(&store, "source", b"payload");
+        put(&store, "existing/target", b"old");
+
+        assert!(store.copy("source", "existing/target").unwrap());
+        assert_eq!(read(&store, "existing/target").unwrap(), b"payload");
+        assert_eq!(
+            store.head("existing/target").unwrap(),
+            store.head("source").unwrap()
+        );
+
+        assert!(store.rename("source", "moved/here").unwrap());
+        assert!(store.get("source").unwrap().is_none());
+        assert_eq!(read(&store, "moved/here").unwrap(), b"payload");
+
+        assert!(!store.copy("source", "elsewhere").unwrap());
+        assert!(!store.rename("source", "elsewhere").unwrap());
+        assert_eq!(store.list("").unwrap(), ["existing/target", "moved/here"]);
+    }
+
+    #[test]
+    fn invalid_keys_are_refused() {
+        let store = ObjectStore::new("unused");
+        assert!(matches!(
+            store.put("", CreateOptions::new()),
+            Err(crate::fs::ObjectFileError::InvalidObjectKey { .. })
+        ));
+    }
+}
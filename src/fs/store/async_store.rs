@@ -0,0 +1,426 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs::File;
+
+use super::{
+    CREATE_ATTEMPTS, ListOptions, ListedObject, MAX_SHARD_LEVELS, ObjectHead, ObjectListing,
+    StoreLayout,
+    listing::{Page, PageBuilder, WalkEntry},
+};
+use crate::fs::{
+    AsyncObjectWriter, AsyncTuxObject, CreateOptions, ObjectFileError, ObjectFileResult,
+    async_io::create_temp_file,
+    object_files,
+    versions::{self, ObjectVersion},
+};
+
+/// Runs version bookkeeping — a few reads, links and removals — and directory walks on the
+/// blocking pool.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> ObjectFileResult<T> + Send + 'static,
+) -> ObjectFileResult<T> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// The async counterpart of [crate::fs::store::ObjectStore], laying objects out the same way: either
+/// can open a store the other wrote.
+#[derive(Debug, Clone)]
+pub struct AsyncObjectStore {
+    layout: StoreLayout,
+}
+
+impl AsyncObjectStore {
+    /// A store rooted at `root`. Nothing is created until the first [AsyncObjectStore::put].
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            layout: StoreLayout {
+                root: root.into(),
+                shard_levels: 0,
+            },
+        }
+    }
+
+    /// See [crate::fs::store::ObjectStore::with_shard_levels].
+    pub fn with_shard_levels(mut self, levels: u8) -> Self {
+        self.layout.shard_levels = levels.min(MAX_SHARD_LEVELS);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.layout.root
+    }
+    pub fn shard_levels(&self) -> u8 {
+        self.layout.shard_levels
+    }
+
+    /// Where the object under `key` is stored, whether or not it exists.
+    pub fn object_path(&self, key: &str) -> ObjectFileResult<PathBuf> {
+        self.layout.object_path(key)
+    }
+
+    /// Starts writing the object under `key`, replacing any existing one once finished.
+    pub async fn put(
+        &self,
+        key: &str,
+        options: CreateOptions,
+    ) -> ObjectFileResult<AsyncObjectWriter> {
+        let path = self.object_path(key)?;
+        let mut attempt = 1;
+        loop {
+            create_parent(&path).await?;
+            match AsyncObjectWriter::create(&path, options.clone()).await {
+                Err(err) if err.is_not_found() && attempt < CREATE_ATTEMPTS => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Opens the object under `key` for reading, or `None` when there is none.
+    pub async fn get(&self, key: &str) -> ObjectFileResult<Option<AsyncTuxObject>> {
+        AsyncTuxObject::open_optional(self.object_path(key)?).await
+    }
+
+    /// Opens the object under `key` for reading and updating its sections.
+    pub async fn get_writable(&self, key: &str) -> ObjectFileResult<Option<AsyncTuxObject>> {
+        match AsyncTuxObject::open_writable(self.object_path(key)?).await {
+            Ok(object) => Ok(Some(object)),
+            Err(err) if err.is_not_found() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads only the header and metadata of the object under `key`.
+    pub async fn head(&self, key: &str) -> ObjectFileResult<Option<ObjectHead>> {
+        Ok(self.get(key).await?.map(AsyncTuxObject::into_head))
+    }
+
+    /// See [crate::fs::store::ObjectStore::delete].
+    pub async fn delete(&self, key: &str) -> ObjectFileResult<bool> {
+        let path = self.object_path(key)?;
+        if tokio::fs::metadata(versions::versions_dir(&path))
+            .await
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            let existed = tokio::fs::try_exists(&path).await?;
+            blocking(move || versions::place_delete_marker(&path)).await?;
+            return Ok(existed);
+        }
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        }
+        self.prune(&path).await;
+        Ok(true)
+    }
+
+    /// See [crate::fs::store::ObjectStore::list_versions].
+    pub async fn list_versions(&self, key: &str) -> ObjectFileResult<Vec<ObjectVersion>> {
+        let path = self.object_path(key)?;
+        blocking(move || versions::list_versions(&path)).await
+    }
+
+    /// See [crate::fs::store::ObjectStore::open_version].
+    pub async fn open_version(
+        &self,
+        key: &str,
+        version_id: &str,
+    ) -> ObjectFileResult<AsyncTuxObject> {
+        let path = versions::version_path(&self.object_path(key)?, version_id)?;
+        match AsyncTuxObject::open(path).await {
+            Err(err) if err.is_not_found() => {
+                Err(ObjectFileError::NoSuchVersion(version_id.to_owned()))
+            }
+            result => result,
+        }
+    }
+
+    /// See [crate::fs::store::ObjectStore::delete_version].
+    pub async fn delete_version(&self, key: &str, version_id: &str) -> ObjectFileResult<bool> {
+        let path = self.object_path(key)?;
+        let version_id = version_id.to_owned();
+        blocking(move || versions::delete_version(&path, &version_id)).await
+    }
+
+    /// See [crate::fs::store::ObjectStore::copy].
+    pub async fn copy(&self, from: &str, to: &str) -> ObjectFileResult<bool> {
+        let from_path = self.object_path(from)?;
+        let to_path = self.object_path(to)?;
+        let mut source = match File::open(&from_path).await {
+            Ok(source) => source,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut attempt = 1;
+        let (mut temp, temp_path) = loop {
+            create_parent(&to_path).await?;
+            match create_temp_file(&to_path).await {
+                Err(err) if err.is_not_found() && attempt < CREATE_ATTEMPTS => attempt += 1,
+                result => break result?,
+            }
+        };
+        let copied = async {
+            tokio::io::copy(&mut source, &mut temp).await?;
+            temp.sync_all().await?;
+            tokio::fs::rename(&temp_path, &to_path).await
+        }
+        .await;
+        if let Err(err) = copied {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(err.into());
+        }
+        sync_parent(&to_path).await;
+        Ok(true)
+    }
+
+    /// See [crate::fs::store::ObjectStore::rename].
+    pub async fn rename(&self, from: &str, to: &str) -> ObjectFileResult<bool> {
+        let from_path = self.object_path(from)?;
+        let to_path = self.object_path(to)?;
+        let mut attempt = 1;
+        loop {
+            create_parent(&to_path).await?;
+            match tokio::fs::rename(&from_path, &to_path).await {
+                Ok(()) => break,
+                // Either the source is gone, or a delete pruned the destination directory.
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    if !tokio::fs::try_exists(&from_path).await? {
+                        return Ok(false);
+                    }
+                    if attempt == CREATE_ATTEMPTS {
+                        return Err(err.into());
+                    }
+                    attempt += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        sync_parent(&to_path).await;
+        self.prune(&from_path).await;
+        Ok(true)
+    }
+
+    /// Every key starting with `prefix`, in byte order.
+    pub async fn list(&self, prefix: &str) -> ObjectFileResult<Vec<String>> {
+        let start = self.layout.list_start(prefix);
+        let mut keys: Vec<String> = blocking(move || object_files(start))
+            .await?
+            .iter()
+            .filter_map(|path| self.layout.key_at(path))
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// See [crate::fs::store::ObjectStore::list_objects].
+    pub async fn list_objects(&self, options: &ListOptions) -> ObjectFileResult<ObjectListing> {
+        let page = self.list_page(options).await?;
+        let mut objects = Vec::with_capacity(page.keys.len());
+        for key in page.keys {
+            match &options.details {
+                None => objects.push(ListedObject::new(key)),
+                Some(metadata) => {
+                    if let Some(head) = self.head(&key).await? {
+                        objects.push(ListedObject::with_head(key, head, metadata));
+                    }
+                }
+            }
+        }
+        Ok(ObjectListing {
+            objects,
+            common_prefixes: page.common_prefixes,
+            is_truncated: page.is_truncated,
+            next_start_after: page.next_start_after,
+        })
+    }
+
+    /// See [crate::fs::store::ObjectStore::list_objects] for how the store is walked.
+    async fn list_page(&self, options: &ListOptions) -> ObjectFileResult<Page> {
+        let mut page = PageBuilder::new(options);
+        if self.layout.shard_levels > 0 {
+            for key in self.list(&options.prefix).await? {
+                if !page.push(key) {
+                    break;
+                }
+            }
+            return Ok(page.finish());
+        }
+        let (start, key_prefix) = self.layout.walk_start(&options.prefix);
+        let mut pending = vec![walk_dir(&start, &key_prefix).await?];
+        while let Some(entries) = pending.last_mut() {
+            let Some(entry) = entries.pop() else {
+                pending.pop();
+                continue;
+            };
+            if entry.is_dir {
+                if page.wants(&entry.key) {
+                    pending.push(walk_dir(&entry.path, &entry.key).await?);
+                }
+            } else if !page.push(entry.key) {
+                break;
+            }
+        }
+        Ok(page.finish())
+    }
+
+    /// Removes the directories left empty above `path`, stopping at the first that is not.
+    async fn prune(&self, path: &Path) {
+        for dir in self.layout.parents_below_root(path) {
+            if tokio::fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// As in [crate::fs::store::ObjectStore::list_objects]: the entries of `dir`, in walk order.
+async fn walk_dir(dir: &Path, key_prefix: &str) -> ObjectFileResult<Vec<WalkEntry>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut walk = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let file_type = entry.file_type().await?;
+        if (file_type.is_dir() || file_type.is_file())
+            && let Some(entry) = WalkEntry::new(entry.path(), file_type.is_dir(), key_prefix)
+        {
+            walk.push(entry);
+        }
+    }
+    WalkEntry::walk_order(&mut walk);
+    Ok(walk)
+}
+
+async fn create_parent(path: &Path) -> ObjectFileResult<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    Ok(())
+}
+
+/// Flushes the directory entry of a rename into `path`.
+async fn sync_parent(path: &Path) {
+    if let Some(parent) = path.parent()
+        && let Ok(dir) = File::open(parent).await
+    {
+        let _ = dir.sync_all().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::fs::store::ObjectStore;
+    use crate::fs::testing::TempDir;
+
+    #[tokio::test]
+    async fn operations_match_the_blocking_store() {
+        let dir = TempDir::new("ops");
+        let store = AsyncObjectStore::new(&dir.0).with_shard_levels(1);
+        let blocking = ObjectStore::new(&dir.0).with_shard_levels(1);
+
+        let mut writer = store.put("nested/key", CreateOptions::new()).await.unwrap();
+        writer.write_all(b"async").await.unwrap();
+        writer.finish().await.unwrap();
+
+        let mut object = blocking.get("nested/key").unwrap().unwrap();
+        assert_eq!(object.read_content_to_vec().unwrap(), b"async");
+        assert_eq!(
+            store.head("nested/key").await.unwrap(),
+            blocking.head("nested/key").unwrap()
+        );
+
+        assert!(store.copy("nested/key", "copy").await.unwrap());
+        assert!(store.rename("nested/key", "moved").await.unwrap());
+        assert!(!store.rename("nested/key", "moved").await.unwrap());
+        assert_eq!(store.list("").await.unwrap(), ["copy", "moved"]);
+        assert_eq!(blocking.list("").unwrap(), ["copy", "moved"]);
+        let listing = store
+            .list_objects(&ListOptions::new().with_details([]))
+            .await
+            .unwrap();
+        assert_eq!(
+            listing,
+            blocking
+                .list_objects(&ListOptions::new().with_details([]))
+                .unwrap()
+        );
+        assert_eq!(listing.objects[1].content_length, Some(5));
+
+        let mut object = store.get("moved").await.unwrap().unwrap();
+        assert_eq!(object.read_content_to_vec().await.unwrap(), b"async");
+        assert!(store.delete("copy").await.unwrap());
+        assert!(store.delete("moved").await.unwrap());
+        assert!(!store.delete("moved").await.unwrap());
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn unsharded_pages_match_the_blocking_store() {
+        let dir = TempDir::new("pages");
+        let store = AsyncObjectStore::new(&dir.0);
+        let blocking = ObjectStore::new(&dir.0);
+        for key in ["a", "a~", "a\u{e9}", "a/b", "a/c/d", "b"] {
+            let mut writer = store.put(key, CreateOptions::new()).await.unwrap();
+            writer.write_all(key.as_bytes()).await.unwrap();
+            writer.finish().await.unwrap();
+        }
+        let mut options = ListOptions::new().with_delimiter("c/").with_max_keys(2);
+        loop {
+            let listing = store.list_objects(&options).await.unwrap();
+            assert_eq!(listing, blocking.list_objects(&options).unwrap());
+            match listing.next_start_after {
+                Some(next) => options = options.with_start_after(next),
+                None => break,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn versions_match_the_blocking_store() {
+        let dir = TempDir::new("versions");
+        let store = AsyncObjectStore::new(&dir.0);
+        let blocking = ObjectStore::new(&dir.0);
+
+        let mut ids = Vec::new();
+        for (content, versioned) in [(&b"one"[..], true), (b"two", false)] {
+            let mut writer = store
+                .put("key", CreateOptions::new().with_versioning(versioned))
+                .await
+                .unwrap();
+            writer.write_all(content).await.unwrap();
+            let object = writer.finish().await.unwrap();
+            ids.push(
+                versions::stamped_version_id(object.metadata())
+                    .unwrap()
+                    .to_owned(),
+            );
+        }
+        assert!(store.delete("key").await.unwrap());
+        assert!(store.get("key").await.unwrap().is_none());
+
+        let history = store.list_versions("key").await.unwrap();
+        assert_eq!(history, blocking.list_versions("key").unwrap());
+        assert_eq!(history[1].version_id, ids[1]);
+        assert_eq!(history[2].version_id, ids[0]);
+        let mut first = store.open_version("key", &ids[0]).await.unwrap();
+        assert_eq!(first.read_content_to_vec().await.unwrap(), b"one");
+
+        assert!(
+            store
+                .delete_version("key", &history[0].version_id)
+                .await
+                .unwrap()
+        );
+        let mut restored = store.get("key").await.unwrap().unwrap();
+        assert_eq!(restored.read_content_to_vec().await.unwrap(), b"two");
+    }
+}
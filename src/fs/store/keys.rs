@@ -0,0 +1,216 @@
+//! Mapping object keys to paths under a store's root, and back.
+//!
+//! Each `/`-separated segment of a key becomes one path component. Bytes outside
+//! `[A-Za-z0-9._~-]` are percent-encoded, and so is a `.` that would start a component — which
+//! rules out `.`, `..` and anything looking like a temporary file. An empty segment is written as a
+//! lone `%`, which no encoded segment can otherwise be. Objects are files named after their last
+//! segment plus [OBJECT_EXTENSION]; to keep the key `a` (the file `a.tuxio`) apart from the
+//! directory holding `a.tuxio/b`, a segment ending in that extension has its dot encoded.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use crate::fs::{ObjectFileError, ObjectFileResult};
+
+/// Appended to the file name of every object in a store.
+pub const OBJECT_EXTENSION: &str = ".tuxio";
+/// Longest key a store accepts, in bytes. The same limit S3 has.
+pub const MAX_KEY_LENGTH: usize = 1024;
+/// Longest file name most filesystems allow, in bytes.
+const MAX_COMPONENT_LENGTH: usize = 255;
+
+fn invalid(key: &str, reason: &'static str) -> ObjectFileError {
+    ObjectFileError::InvalidObjectKey {
+        key: key.to_owned(),
+        reason,
+    }
+}
+
+fn escape_segment(segment: &str, out: &mut String) {
+    if segment.is_empty() {
+        out.push('%');
+        return;
+    }
+    let start = out.len();
+    for (index, byte) in segment.bytes().enumerate() {
+        let safe = byte.is_ascii_alphanumeric()
+            || matches!(byte, b'-' | b'_' | b'~')
+            || (byte == b'.' && index > 0);
+        if safe {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    if out[start..].ends_with(OBJECT_EXTENSION) {
+        let dot = out.len() - OBJECT_EXTENSION.len();
+        out.replace_range(dot..dot + 1, "%2E");
+    }
+}
+
+fn unescape_segment(component: &str) -> Option<String> {
+    if component == "%" {
+        return Some(String::new());
+    }
+    let mut bytes = Vec::with_capacity(component.len());
+    let mut rest = component.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The path of `key` relative to the shard directories.
+pub(crate) fn key_to_relative_path(key: &str) -> ObjectFileResult<PathBuf> {
+    if key.is_empty() {
+        return Err(invalid(key, "keys must not be empty"));
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(invalid(key, "keys are limited to 1024 bytes"));
+    }
+    let mut path = PathBuf::new();
+    let mut segments = key.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        let mut component = String::new();
+        escape_segment(segment, &mut component);
+        if segments.peek().is_none() {
+            component.push_str(OBJECT_EXTENSION);
+        }
+        if component.len() > MAX_COMPONENT_LENGTH {
+            return Err(invalid(
+                key,
+                "a segment is too long for a file name once escaped",
+            ));
+        }
+        path.push(component);
+    }
+    Ok(path)
+}
+
+/// The directory, relative to the shard directories, holding every key that starts with `prefix`.
+pub(crate) fn prefix_to_relative_dir(prefix: &str) -> PathBuf {
+    let mut dir = PathBuf::new();
+    if let Some((complete, _)) = prefix.rsplit_once('/') {
+        for segment in complete.split('/') {
+            let mut component = String::new();
+            escape_segment(segment, &mut component);
+            dir.push(component);
+        }
+    }
+    dir
+}
+
+/// The key stored at `path`, relative to the shard directories, or `None` for a file that is not an
+/// object of the store — a temporary file, say.
+pub(crate) fn relative_path_to_key(path: &Path) -> Option<String> {
+    let mut segments = Vec::new();
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next() {
+        let std::path::Component::Normal(component) = component else {
+            return None;
+        };
+        segments.push(component_to_segment(
+            component,
+            components.peek().is_none(),
+        )?);
+    }
+    (!segments.is_empty()).then(|| segments.join("/"))
+}
+
+/// The key segment one path component stands for: the last of a key when `is_object`, otherwise
+/// one of the directories before it. `None` for a component no key maps to.
+pub(crate) fn component_to_segment(component: &OsStr, is_object: bool) -> Option<String> {
+    let mut component = component.to_str()?;
+    if is_object {
+        component = component.strip_suffix(OBJECT_EXTENSION)?;
+    }
+    if component.starts_with('.') {
+        return None;
+    }
+    unescape_segment(component)
+}
+
+/// The shard directories for `key`: `levels` components of two hex digits each, taken from a
+/// 64 bit FNV-1a hash so the placement never changes between builds.
+pub(crate) fn shard_path(key: &str, levels: u8) -> PathBuf {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash.to_be_bytes()
+        .iter()
+        .take(levels as usize)
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_survive_the_round_trip_through_a_path() {
+        for key in [
+            "plain",
+            "photos/2026/cat.jpg",
+            "a/../b",
+            "./hidden/.file",
+            "trailing/",
+            "//double",
+            "spaces and ünïcödé/%25",
+            "a.tuxio",
+            "a.tuxio/b",
+            ".tuxtmp",
+        ] {
+            let path = key_to_relative_path(key).unwrap();
+            for component in path.components() {
+                let std::path::Component::Normal(component) = component else {
+                    panic!("{key:?} escaped to {path:?}");
+                };
+                assert!(!component.to_str().unwrap().starts_with('.'));
+            }
+            assert_eq!(relative_path_to_key(&path).as_deref(), Some(key));
+        }
+    }
+
+    #[test]
+    fn an_object_and_a_directory_never_share_a_name() {
+        let file = key_to_relative_path("a").unwrap();
+        let dir = key_to_relative_path("a.tuxio/b").unwrap();
+        assert_ne!(file, dir.parent().unwrap());
+    }
+
+    #[test]
+    fn unusable_keys_are_rejected() {
+        assert!(key_to_relative_path("").is_err());
+        assert!(key_to_relative_path(&"k".repeat(MAX_KEY_LENGTH + 1)).is_err());
+        assert!(key_to_relative_path(&"/".repeat(300)).is_ok());
+        assert!(key_to_relative_path(&"é".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn foreign_files_are_not_keys() {
+        assert_eq!(relative_path_to_key(Path::new(".a.tuxio.1.2.tuxtmp")), None);
+        assert_eq!(relative_path_to_key(Path::new("notes.txt")), None);
+        assert_eq!(relative_path_to_key(Path::new("bad%zz.tuxio")), None);
+    }
+
+    #[test]
+    fn shards_are_stable() {
+        assert_eq!(shard_path("key", 0), PathBuf::new());
+        // Pinned: a change here would strand every object in an existing sharded store.
+        assert_eq!(shard_path("key", 2), Path::new("3d").join("c9"));
+        assert_eq!(shard_path("key", 2).components().count(), 2);
+        assert_ne!(shard_path("key", 2), shard_path("other", 2));
+    }
+}
@@ -0,0 +1,349 @@
+//! Listings of a store in the manner of S3's ListObjectsV2: keys in byte order, optionally rolled
+//! up into common prefixes at a delimiter, a page at a time.
+
+use std::path::PathBuf;
+
+use http::HeaderName;
+
+use super::{ObjectHead, keys};
+use crate::MetadataMap;
+
+/// The page size S3 uses when not given one.
+pub const DEFAULT_MAX_KEYS: usize = 1000;
+
+/// What [crate::fs::store::ObjectStore::list_objects] lists.
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    /// Only keys starting with this are listed.
+    pub prefix: String,
+    /// Keys with this after the prefix are rolled up into one common prefix, ending in the
+    /// delimiter, in place of the keys themselves. Usually `/`.
+    pub delimiter: Option<String>,
+    /// Only keys, and common prefixes, after this one are listed. The previous page's
+    /// [ObjectListing::next_start_after].
+    pub start_after: Option<String>,
+    /// Most keys and common prefixes, together, in one page.
+    pub max_keys: usize,
+    /// Opens each listed object for its content length and these metadata entries. `None` lists
+    /// keys alone, without opening anything.
+    pub details: Option<Vec<HeaderName>>,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            delimiter: None,
+            start_after: None,
+            max_keys: DEFAULT_MAX_KEYS,
+            details: None,
+        }
+    }
+}
+
+impl ListOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+    pub fn with_delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.delimiter = Some(delimiter.into());
+        self
+    }
+    pub fn with_start_after(mut self, start_after: impl Into<String>) -> Self {
+        self.start_after = Some(start_after.into());
+        self
+    }
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+    /// Lists each object's content length and the metadata entries named by `metadata`, which may
+    /// be empty to read the content length alone.
+    pub fn with_details(mut self, metadata: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.details = Some(metadata.into_iter().collect());
+        self
+    }
+}
+
+/// One page of a listing.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ObjectListing {
+    pub objects: Vec<ListedObject>,
+    /// Each ends in the delimiter. Empty without one.
+    pub common_prefixes: Vec<String>,
+    /// Whether there is more to list after this page.
+    pub is_truncated: bool,
+    /// The `start_after` for the next page, when there is one.
+    pub next_start_after: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedObject {
+    pub key: String,
+    /// The stored length of the content, as in the header. Only read when
+    /// [ListOptions::details] asked for it.
+    pub content_length: Option<u64>,
+    /// The requested metadata entries the object has.
+    pub metadata: MetadataMap,
+}
+
+impl ListedObject {
+    pub(crate) fn new(key: String) -> Self {
+        Self {
+            key,
+            content_length: None,
+            metadata: MetadataMap::new(),
+        }
+    }
+
+    /// An entry with the details `metadata` selects from `head`.
+    pub(crate) fn with_head(key: String, head: ObjectHead, metadata: &[HeaderName]) -> Self {
+        let mut selected = MetadataMap::new();
+        for name in metadata {
+            if let Some(value) = head.metadata.get_header(name) {
+                selected.insert_header(name.clone(), value.clone());
+            }
+        }
+        Self {
+            key,
+            content_length: Some(head.header.content_length),
+            metadata: selected,
+        }
+    }
+}
+
+/// A page of keys and common prefixes, before any object is opened for its details.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Page {
+    pub(crate) keys: Vec<String>,
+    pub(crate) common_prefixes: Vec<String>,
+    pub(crate) is_truncated: bool,
+    pub(crate) next_start_after: Option<String>,
+}
+
+impl Page {
+    /// The page `options` selects from `keys`, every key in the store starting with the prefix, in
+    /// byte order.
+    #[cfg(test)]
+    pub(crate) fn select(keys: Vec<String>, options: &ListOptions) -> Self {
+        let mut page = PageBuilder::new(options);
+        for key in keys {
+            if !page.push(key) {
+                break;
+            }
+        }
+        page.finish()
+    }
+}
+
+/// Builds a [Page] from keys fed to it in byte order, and tells a walk which directories it can
+/// leave unread.
+pub(crate) struct PageBuilder<'options> {
+    options: &'options ListOptions,
+    page: Page,
+    /// The last key or common prefix put on the page.
+    last: Option<String>,
+}
+
+impl<'options> PageBuilder<'options> {
+    pub(crate) fn new(options: &'options ListOptions) -> Self {
+        Self {
+            options,
+            page: Page::default(),
+            last: None,
+        }
+    }
+
+    fn delimiter(&self) -> Option<&'options str> {
+        self.options
+            .delimiter
+            .as_deref()
+            .filter(|delimiter| !delimiter.is_empty())
+    }
+
+    /// The common prefix `key` is rolled up into, when its part after the prefix has the
+    /// delimiter in it.
+    fn common_prefix<'key>(&self, key: &'key str) -> Option<&'key str> {
+        let delimiter = self.delimiter()?;
+        let prefix_length = self.options.prefix.len();
+        let end = key.get(prefix_length..)?.find(delimiter)?;
+        Some(&key[..prefix_length + end + delimiter.len()])
+    }
+
+    /// Whether a key starting with `key_prefix` could still be put on the page. `false` lets a walk
+    /// skip a directory whose keys all start with it.
+    pub(crate) fn wants(&self, key_prefix: &str) -> bool {
+        let prefix = self.options.prefix.as_str();
+        if self.page.is_truncated
+            || !(key_prefix.starts_with(prefix) || prefix.starts_with(key_prefix))
+        {
+            return false;
+        }
+        let start_after = self.options.start_after.as_deref();
+        // Every key under it sorts before `start_after`, unless `start_after` is itself under it.
+        if start_after.is_some_and(|after| key_prefix < after && !after.starts_with(key_prefix)) {
+            return false;
+        }
+        // Every key under it rolls up into a common prefix that is already listed.
+        match self.common_prefix(key_prefix) {
+            Some(common_prefix) => {
+                self.last.as_deref() != Some(common_prefix)
+                    && start_after.is_none_or(|after| common_prefix > after)
+            }
+            None => true,
+        }
+    }
+
+    /// Adds the next key in byte order. Returns `false` once the page is full and there is more
+    /// to list, after which no more keys are needed.
+    pub(crate) fn push(&mut self, key: String) -> bool {
+        if !key.starts_with(&self.options.prefix)
+            || self
+                .options
+                .start_after
+                .as_deref()
+                .is_some_and(|after| key.as_str() <= after)
+        {
+            return true;
+        }
+        let common_prefix = self.common_prefix(&key).map(str::to_owned);
+        if let Some(common_prefix) = &common_prefix {
+            // The same prefix as the key before, or one an earlier page already returned.
+            if self.last.as_ref() == Some(common_prefix)
+                || self
+                    .options
+                    .start_after
+                    .as_deref()
+                    .is_some_and(|after| common_prefix.as_str() <= after)
+            {
+                return true;
+            }
+        }
+        if self.page.keys.len() + self.page.common_prefixes.len() == self.options.max_keys {
+            self.page.is_truncated = true;
+            return false;
+        }
+        match common_prefix {
+            Some(common_prefix) => {
+                self.last = Some(common_prefix.clone());
+                self.page.common_prefixes.push(common_prefix);
+            }
+            None => {
+                self.last = Some(key.clone());
+                self.page.keys.push(key);
+            }
+        }
+        true
+    }
+
+    pub(crate) fn finish(mut self) -> Page {
+        if self.page.is_truncated {
+            self.page.next_start_after = self.last.or_else(|| self.options.start_after.clone());
+        }
+        self.page
+    }
+}
+
+/// A file or directory met walking an unsharded store.
+#[derive(Debug)]
+pub(crate) struct WalkEntry {
+    pub(crate) path: PathBuf,
+    /// The object's key, or for a directory the prefix, ending in `/`, every key under it has.
+    pub(crate) key: String,
+    pub(crate) is_dir: bool,
+}
+
+impl WalkEntry {
+    /// The entry for `path` in a directory whose keys all start with `key_prefix`, or `None` when
+    /// nothing in the store is kept there.
+    pub(crate) fn new(path: PathBuf, is_dir: bool, key_prefix: &str) -> Option<Self> {
+        let segment = keys::component_to_segment(path.file_name()?, !is_dir)?;
+        let mut key = format!("{key_prefix}{segment}");
+        if is_dir {
+            key.push('/');
+        }
+        Some(Self { path, key, is_dir })
+    }
+
+    /// Puts one directory's entries in the order a walk visits them to meet keys in byte order,
+    /// last first so they can be popped.
+    ///
+    /// Escaping does not keep byte order, so entries are ordered by what they hold rather than by
+    /// name. A directory sorts as its prefix with the `/`: no sibling's key falls between the keys
+    /// under it, and the object named like it, which is that prefix without the `/`, comes first.
+    pub(crate) fn walk_order(entries: &mut [WalkEntry]) {
+        entries.sort_unstable_by(|a, b| b.key.cmp(&a.key));
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        ["a", "b/1", "b/2", "b/c/3", "ba", "c/", "d"]
+            .map(str::to_owned)
+            .to_vec()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn delimiters_roll_keys_up_into_common_prefixes() {
+        let page = Page::select(keys(), &ListOptions::new().with_delimiter("/"));
+        assert_eq!(page.keys, strings(&["a", "ba", "d"]));
+        assert_eq!(page.common_prefixes, strings(&["b/", "c/"]));
+        assert!(!page.is_truncated);
+
+        let nested: Vec<String> = keys()
+            .into_iter()
+            .filter(|key| key.starts_with("b/"))
+            .collect();
+        let page = Page::select(
+            nested,
+            &ListOptions::new().with_prefix("b/").with_delimiter("/"),
+        );
+        assert_eq!(page.keys, strings(&["b/1", "b/2"]));
+        assert_eq!(page.common_prefixes, strings(&["b/c/"]));
+    }
+
+    #[test]
+    fn pages_resume_after_keys_and_common_prefixes() {
+        let mut options = ListOptions::new().with_delimiter("/").with_max_keys(2);
+        let mut keys_seen = Vec::new();
+        let mut prefixes_seen = Vec::new();
+        loop {
+            let page = Page::select(keys(), &options);
+            assert!(page.keys.len() + page.common_prefixes.len() <= 2);
+            keys_seen.extend(page.keys);
+            prefixes_seen.extend(page.common_prefixes);
+            match page.next_start_after {
+                Some(next) => options = options.with_start_after(next),
+                None => break,
+            }
+        }
+        assert_eq!(keys_seen, strings(&["a", "ba", "d"]));
+        assert_eq!(prefixes_seen, strings(&["b/", "c/"]));
+    }
+
+    #[test]
+    fn truncation_is_only_reported_when_more_remain() {
+        let page = Page::select(keys(), &ListOptions::new().with_max_keys(7));
+        assert_eq!(page.keys.len(), 7);
+        assert!(!page.is_truncated);
+        assert_eq!(page.next_start_after, None);
+
+        let page = Page::select(
+            keys(),
+            &ListOptions::new().with_max_keys(0).with_start_after("b/1"),
+        );
+        assert!(page.keys.is_empty());
+        assert!(page.is_truncated);
+        assert_eq!(page.next_start_after.as_deref(), Some("b/1"));
+    }
+}
@@ -0,0 +1,430 @@
+//! S3-style multipart uploads: an object's content sent in numbered parts, possibly over many
+//! requests, and assembled into one object at the end.
+//!
+//! Parts are kept as plain files under [MULTIPART_DIR] in the store's root, one directory per
+//! upload. A leading dot keeps that directory out of the way of keys, which never map to one.
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use md5::{Digest, Md5};
+
+use crate::fs::{
+    CreateOptions, ObjectFileError, ObjectFileResult, TuxObject,
+    store::{ObjectStore, sync_parent},
+    writer::{create_temp_file, next_temp_counter},
+};
+
+/// Directory under a store's root that holds the parts of uploads in progress.
+pub const MULTIPART_DIR: &str = ".multipart";
+/// Highest part number an upload accepts; numbers start at 1. The same range S3 has.
+pub const MAX_PART_NUMBER: u32 = 10_000;
+
+/// A part as an upload's completion names it: its number, and the ETag
+/// [MultipartUpload::upload_part] returned for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletedPart {
+    pub number: u32,
+    /// The part's MD5 in hex, quoted as an HTTP ETag is. Compared without the quotes, so a client
+    /// that drops them still matches.
+    pub etag: String,
+}
+
+/// An upload in progress into one key of an [ObjectStore].
+///
+/// Nothing about the upload but its parts is stored: the key and the [CreateOptions] the object is
+/// written with are the caller's to keep between [MultipartUpload::begin] and
+/// [MultipartUpload::resume].
+#[derive(Debug, Clone)]
+pub struct MultipartUpload {
+    store: ObjectStore,
+    key: String,
+    upload_id: String,
+    options: CreateOptions,
+}
+
+impl MultipartUpload {
+    /// Starts an upload into `key`, which the finished object is written to with `options`.
+    pub fn begin(store: &ObjectStore, key: &str, options: CreateOptions) -> ObjectFileResult<Self> {
+        // Checked now rather than at completion, after every part has been sent.
+        store.object_path(key)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        let upload = Self {
+            store: store.clone(),
+            key: key.to_owned(),
+            upload_id: format!(
+                "{nanos:x}-{:x}-{:x}",
+                std::process::id(),
+                next_temp_counter()
+            ),
+            options,
+        };
+        std::fs::create_dir_all(upload.dir())?;
+        Ok(upload)
+    }
+
+    /// Picks up an upload begun earlier, failing with [ObjectFileError::NoSuchUpload] when it was
+    /// completed, aborted, or never begun.
+    pub fn resume(
+        store: &ObjectStore,
+        key: &str,
+        upload_id: &str,
+        options: CreateOptions,
+    ) -> ObjectFileResult<Self> {
+        store.object_path(key)?;
+        let upload = Self {
+            store: store.clone(),
+            key: key.to_owned(),
+            upload_id: upload_id.to_owned(),
+            options,
+        };
+        // An id is only ever generated here, so anything else cannot name an upload — and must not
+        // be let near a path.
+        let well_formed = !upload_id.is_empty()
+            && upload_id
+                .bytes()
+                .all(|byte| byte.is_ascii_hexdigit() || byte == b'-');
+        if !well_formed || !upload.dir().is_dir() {
+            return Err(ObjectFileError::NoSuchUpload(upload_id.to_owned()));
+        }
+        Ok(upload)
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    /// Names the upload to [MultipartUpload::resume].
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    /// Stores part `number` of the content, replacing any part already uploaded under it, and
+    /// returns what completion needs to name it.
+    ///
+    /// Parts may arrive in any order, and at any size: S3's 5 MiB minimum is not enforced.
+    pub fn upload_part(
+        &self,
+        number: u32,
+        mut content: impl Read,
+    ) -> ObjectFileResult<CompletedPart> {
+        check_part_number(number)?;
+        let path = self.part_path(number);
+        let (mut temp, temp_path) = match create_temp_file(&path) {
+            Ok(created) => created,
+            Err(err) if err.is_not_found() => {
+                return Err(ObjectFileError::NoSuchUpload(self.upload_id.clone()));
+            }
+            Err(err) => return Err(err),
+        };
+        let written = (|| {
+            let digest = copy_hashing(&mut content, &mut temp)?;
+            temp.sync_all()?;
+            std::fs::rename(&temp_path, &path)?;
+            Ok::<_, std::io::Error>(digest)
+        })();
+        let digest = match written {
+            Ok(digest) => digest,
+            Err(err) => {
+                let _ = std::fs::remove_file(&temp_path);
+                return Err(err.into());
+            }
+        };
+        sync_parent(&path);
+        Ok(CompletedPart {
+            number,
+            etag: format!("\"{}\"", hex(&digest)),
+        })
+    }
+
+    /// Assembles `parts`, in ascending order of number, into the object, and removes the upload.
+    ///
+    /// Each part is checked against the ETag it is named with as it is copied, and the object's
+    /// [http::header::ETAG] metadata is set to S3's composite form: the MD5 of the parts' MD5s,
+    /// then a dash and the number of parts. Published atomically, as any write to the store is. A
+    /// part that is missing or does not match fails with [ObjectFileError::InvalidPart], leaving
+    /// the upload as it was.
+    pub fn complete(self, parts: &[CompletedPart]) -> ObjectFileResult<TuxObject> {
+        if parts.is_empty() {
+            return Err(ObjectFileError::InvalidPart {
+                number: 0,
+                reason: "an upload is completed with at least one part",
+            });
+        }
+        if let Some(pair) = parts
+            .windows(2)
+            .find(|pair| pair[0].number >= pair[1].number)
+        {
+            return Err(ObjectFileError::InvalidPart {
+                number: pair[1].number,
+                reason: "parts must be listed in ascending order of number, each once",
+            });
+        }
+        if !self.dir().is_dir() {
+            return Err(ObjectFileError::NoSuchUpload(self.upload_id.clone()));
+        }
+
+        // The composite ETag is worked out from the ETags claimed for the parts, which every part is
+        // checked against below, so it goes into the metadata before the content rather than
+        // after — where it could outgrow the reserved prefix and have the content copied again.
+        let mut digests = Md5::new();
+        for part in parts {
+            check_part_number(part.number)?;
+            let digest =
+                unhex(part.etag.trim_matches('"')).ok_or(ObjectFileError::InvalidPart {
+                    number: part.number,
+                    reason: "the ETag does not match the part uploaded under this number",
+                })?;
+            digests.update(digest);
+        }
+        let etag = format!("\"{}-{}\"", hex(&digests.finalize()), parts.len());
+        let mut options = self.options.clone();
+        options
+            .metadata
+            .insert_header(http::header::ETAG, etag.into());
+
+        let mut writer = self.store.put(&self.key, options)?;
+        {
+            let mut encoder = writer.content_encoder()?;
+            for part in parts {
+                let mut file = match File::open(self.part_path(part.number)) {
+                    Ok(file) => file,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        return Err(ObjectFileError::InvalidPart {
+                            number: part.number,
+                            reason: "no part was uploaded under this number",
+                        });
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+                let digest = copy_hashing(&mut file, &mut encoder)?;
+                if hex(&digest) != part.etag.trim_matches('"') {
+                    return Err(ObjectFileError::InvalidPart {
+                        number: part.number,
+                        reason: "the ETag does not match the part uploaded under this number",
+                    });
+                }
+            }
+            encoder.finish()?;
+        }
+        let object = writer.finish()?;
+        // The object is published; parts left behind now would only waste space.
+        let _ = std::fs::remove_dir_all(self.dir());
+        Ok(object)
+    }
+
+    /// Abandons the upload, removing its parts.
+    pub fn abort(self) -> ObjectFileResult<()> {
+        match std::fs::remove_dir_all(self.dir()) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.store.root().join(MULTIPART_DIR).join(&self.upload_id)
+    }
+
+    fn part_path(&self, number: u32) -> PathBuf {
+        self.dir().join(format!("{number:05}.part"))
+    }
+}
+
+fn check_part_number(number: u32) -> ObjectFileResult<()> {
+    if (1..=MAX_PART_NUMBER).contains(&number) {
+        Ok(())
+    } else {
+        Err(ObjectFileError::InvalidPart {
+            number,
+            reason: "part numbers run from 1 to 10000",
+        })
+    }
+}
+
+/// Copies `source` into `target`, returning the MD5 of what was copied.
+fn copy_hashing(source: &mut impl Read, target: &mut impl Write) -> std::io::Result<[u8; 16]> {
+    let mut digest = Md5::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = match source.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        digest.update(&buffer[..read]);
+        target.write_all(&buffer[..read])?;
+    }
+    Ok(digest.finalize().into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The MD5 digest written as `hex`, in lowercase as [hex] writes it.
+fn unhex(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32
+        || !hex
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return None;
+    }
+    let mut digest = [0u8; 16];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::ETAG;
+
+    use super::*;
+    use crate::{
+        TuxIOType,
+        fs::{LayoutOptions, testing::TempDir},
+    };
+
+    fn md5(content: &[u8]) -> [u8; 16] {
+        Md5::digest(content).into()
+    }
+
+    #[test]
+    fn parts_are_assembled_in_order_with_a_composite_etag() {
+        let dir = TempDir::new("complete");
+        let store = ObjectStore::new(&dir.0).with_shard_levels(1);
+        let upload = MultipartUpload::begin(&store, "videos/a.mp4", CreateOptions::new()).unwrap();
+
+        let chunks: [&[u8]; 3] = [b"first part, ", b"second part, ", b"third"];
+        // Sent out of order, and part 2 twice: the later upload replaces the earlier.
+        let third = upload.upload_part(3, chunks[2]).unwrap();
+        upload.upload_part(2, &b"stale"[..]).unwrap();
+        let first = upload.upload_part(1, chunks[0]).unwrap();
+        let second = upload.upload_part(2, chunks[1]).unwrap();
+        assert_eq!(second.etag, format!("\"{}\"", hex(&md5(chunks[1]))));
+
+        // Resumed under the id, as a later request would.
+        let resumed = MultipartUpload::resume(
+            &store,
+            "videos/a.mp4",
+            upload.upload_id(),
+            CreateOptions::new(),
+        )
+        .unwrap();
+        let mut object = resumed.complete(&[first, second, third]).unwrap();
+
+        let mut content = Vec::new();
+        object
+            .stored_content_reader()
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, chunks.concat());
+
+        let digests: Vec<u8> = chunks.iter().flat_map(|chunk| md5(chunk)).collect();
+        let etag = format!("\"{}-3\"", hex(&md5(&digests)));
+        assert_eq!(
+            object
+                .metadata()
+                .get_header(&ETAG)
+                .and_then(|etag| etag.as_str()),
+            Some(etag.as_str())
+        );
+
+        assert!(!upload.dir().exists());
+        assert_eq!(store.list("").unwrap(), ["videos/a.mp4"]);
+    }
+
+    /// The ETag is in the metadata from the start, so even a packed layout, with no room to add
+    /// it at the end, is written once rather than rewritten.
+    #[test]
+    fn the_composite_etag_needs_no_room_left_at_the_end() {
+        let dir = TempDir::new("packed");
+        let store = ObjectStore::new(&dir.0);
+        let options = CreateOptions::new().with_layout(LayoutOptions::packed());
+        let upload = MultipartUpload::begin(&store, "object", options).unwrap();
+        let part = upload.upload_part(1, &b"content"[..]).unwrap();
+        let object = upload.complete(&[part]).unwrap();
+
+        assert!(object.metadata().get_header(&ETAG).is_some());
+        // A rewrite would have left slack behind the metadata.
+        assert_eq!(object.layout().metadata_space(), object.metadata().size());
+    }
+
+    #[test]
+    fn parts_that_do_not_match_are_rejected() {
+        let dir = TempDir::new("invalid");
+        let store = ObjectStore::new(&dir.0);
+        let upload = MultipartUpload::begin(&store, "object", CreateOptions::new()).unwrap();
+
+        for number in [0, MAX_PART_NUMBER + 1] {
+            assert!(matches!(
+                upload.upload_part(number, &b"x"[..]),
+                Err(ObjectFileError::InvalidPart { .. })
+            ));
+        }
+
+        let first = upload.upload_part(1, &b"one"[..]).unwrap();
+        let wrong = CompletedPart {
+            number: 1,
+            etag: hex(&md5(b"two")),
+        };
+        let missing = CompletedPart {
+            number: 2,
+            etag: hex(&md5(b"two")),
+        };
+        for parts in [
+            vec![],
+            vec![wrong],
+            vec![first.clone(), missing],
+            vec![first.clone(), first.clone()],
+        ] {
+            assert!(matches!(
+                upload.clone().complete(&parts),
+                Err(ObjectFileError::InvalidPart { .. })
+            ));
+        }
+        // A failed completion publishes nothing and keeps the parts.
+        assert!(store.get("object").unwrap().is_none());
+
+        // An ETag without its quotes still matches.
+        let unquoted = CompletedPart {
+            number: 1,
+            etag: first.etag.trim_matches('"').to_owned(),
+        };
+        upload.complete(&[unquoted]).unwrap();
+        assert!(store.get("object").unwrap().is_some());
+    }
+
+    #[test]
+    fn aborted_uploads_cannot_be_resumed() {
+        let dir = TempDir::new("abort");
+        let store = ObjectStore::new(&dir.0);
+        let upload = MultipartUpload::begin(&store, "object", CreateOptions::new()).unwrap();
+        upload.upload_part(1, &b"one"[..]).unwrap();
+        let upload_id = upload.upload_id().to_owned();
+        upload.clone().abort().unwrap();
+
+        assert!(!upload.dir().exists());
+        assert!(matches!(
+            upload.upload_part(2, &b"two"[..]),
+            Err(ObjectFileError::NoSuchUpload(_))
+        ));
+        for id in [upload_id.as_str(), "../..", ""] {
+            assert!(matches!(
+                MultipartUpload::resume(&store, "object", id, CreateOptions::new()),
+                Err(ObjectFileError::NoSuchUpload(_))
+            ));
+        }
+        assert!(store.list("").unwrap().is_empty());
+    }
+}
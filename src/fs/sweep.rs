@@ -0,0 +1,186 @@
+//! Collecting the temporary files writers leave behind when they die before cleaning up.
+//!
+//! A writer removes its temp file when it is dropped, but not when the process is killed or the
+//! machine goes down, and nothing else ever looks at those files again.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::fs::{ObjectFileResult, TEMP_FILE_EXTENSION};
+
+/// What [sweep_temp_files] found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SweepReport {
+    pub removed: Vec<PathBuf>,
+    /// Total size of the removed files.
+    pub removed_bytes: u64,
+    /// Temp files left alone because the process that wrote them is still running.
+    pub in_use: usize,
+    /// Temp files left alone because they were modified too recently.
+    pub too_recent: usize,
+}
+
+/// Removes writers' temp files under `dir`, recursively, that were last modified more than
+/// `older_than` ago and whose writing process is no longer running.
+///
+/// Only files named the way writers name them — see [TEMP_FILE_EXTENSION] — are considered, and
+/// symlinks are not followed. The process id in the name is checked on Unix; elsewhere, and for
+/// files written by another machine onto a shared filesystem, only the age protects a file still
+/// being written, so `older_than` should be longer than any write takes.
+pub fn sweep_temp_files(
+    dir: impl AsRef<Path>,
+    older_than: Duration,
+) -> ObjectFileResult<SweepReport> {
+    let now = SystemTime::now();
+    let mut report = SweepReport::default();
+    let mut pending = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+                continue;
+            }
+            let Some(pid) = entry.file_name().to_str().and_then(temp_file_pid) else {
+                continue;
+            };
+            if !file_type.is_file() {
+                continue;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                // Published or cleaned up by its writer since the directory was read.
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let age = now
+                .duration_since(metadata.modified()?)
+                .unwrap_or(Duration::ZERO);
+            if age < older_than {
+                report.too_recent += 1;
+            } else if process_is_running(pid) {
+                report.in_use += 1;
+            } else {
+                let path = entry.path();
+                match std::fs::remove_file(&path) {
+                    Ok(()) => {
+                        report.removed_bytes += metadata.len();
+                        report.removed.push(path);
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+    }
+    report.removed.sort();
+    Ok(report)
+}
+
+/// The process id in a temp file name, `.{file name}.{pid}.{counter}.tuxtmp`, or `None` for any
+/// other name.
+fn temp_file_pid(name: &str) -> Option<u32> {
+    let rest = name
+        .strip_prefix('.')?
+        .strip_suffix(TEMP_FILE_EXTENSION)?
+        .strip_suffix('.')?;
+    let (rest, counter) = rest.rsplit_once('.')?;
+    let (stem, pid) = rest.rsplit_once('.')?;
+    if stem.is_empty() || counter.parse::<u64>().is_err() {
+        return None;
+    }
+    pid.parse().ok()
+}
+
+#[cfg(unix)]
+fn process_is_running(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    // Zero and negative ids address process groups rather than a process.
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // Signal 0 checks the process exists without sending anything. EPERM means it does, but
+    // belongs to another user.
+    // SAFETY: kill has no memory safety preconditions.
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_is_running(pid: u32) -> bool {
+    pid == std::process::id()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::fs::testing::TempDir;
+
+    /// Creates a file at `path`, last modified `age` ago.
+    fn aged_file(path: &Path, age: Duration) {
+        let file = File::create(path).unwrap();
+        file.set_len(10).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn temp_file_names_are_recognised() {
+        assert_eq!(temp_file_pid(".object.tuxio.123.4.tuxtmp"), Some(123));
+        assert_eq!(temp_file_pid(".a.b.c.7.0.tuxtmp"), Some(7));
+        assert_eq!(temp_file_pid("object.tuxio.123.4.tuxtmp"), None);
+        assert_eq!(temp_file_pid("..123.4.tuxtmp"), None);
+        assert_eq!(temp_file_pid(".object.pid.4.tuxtmp"), None);
+        assert_eq!(temp_file_pid(".object.123.4.tmp"), None);
+    }
+
+    #[test]
+    fn only_stale_files_of_dead_writers_are_removed() {
+        let dir = TempDir::new("stale");
+        let nested = dir.0.join("nested");
+        std::fs::create_dir(&nested).unwrap();
+        let hour = Duration::from_secs(60 * 60);
+        // Far above any pid_max, so never a running process.
+        let dead = i32::MAX;
+        let live = std::process::id();
+
+        let stale = nested.join(format!(".object.tuxio.{dead}.0.tuxtmp"));
+        aged_file(&stale, 2 * hour);
+        aged_file(
+            &dir.0.join(format!(".object.tuxio.{dead}.1.tuxtmp")),
+            hour / 2,
+        );
+        aged_file(
+            &dir.0.join(format!(".object.tuxio.{live}.2.tuxtmp")),
+            2 * hour,
+        );
+        aged_file(&dir.0.join("object.tuxio"), 2 * hour);
+
+        let report = sweep_temp_files(&dir.0, hour).unwrap();
+        assert_eq!(
+            report,
+            SweepReport {
+                removed: vec![stale.clone()],
+                removed_bytes: 10,
+                in_use: 1,
+                too_recent: 1,
+            }
+        );
+        assert!(!stale.exists());
+        assert!(dir.0.join("object.tuxio").exists());
+    }
+}
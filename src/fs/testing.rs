@@ -0,0 +1,49 @@
+//! Fixtures shared by the tests under [crate::fs].
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::fs::{CreateOptions, TuxObject};
+
+/// A scratch directory that removes itself, so these tests need no extra dependency.
+pub(crate) struct TempDir(pub(crate) PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        // Tests run in parallel threads of one process, so the pid and clock alone can collide.
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "tux-io-encoding-{}-{}-{}-{}",
+            name,
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Writes an object holding `content` at `path`, returning it as published.
+pub(crate) fn write_object(path: &Path, options: CreateOptions, content: &[u8]) -> TuxObject {
+    let mut writer = TuxObject::create(path, options).unwrap();
+    writer.write_all(content).unwrap();
+    writer.finish().unwrap()
+}
@@ -0,0 +1,384 @@
+//! Handing the content's bytes on disk to the kernel, so serving an object does not copy it through
+//! userspace.
+//!
+//! [TuxObject::content_file_range] says where in the file a range of the content lies, for any
+//! zero-copy mechanism a server already uses; on Linux, [send_content_to] does the `sendfile` itself.
+//! [TuxObject::copy_to] shares the content's blocks with the copy where the filesystem allows it.
+
+use std::{fs::File, io::Read};
+
+use crate::{
+    CompressionTypes, ObjectHeader,
+    fs::{ObjectFileError, ObjectFileResult, SharedTuxObject, TuxObject, shared::read_at},
+};
+
+/// Where a range of an object's content lies in its file.
+///
+/// Only made for content stored as it reads — neither compressed nor encrypted — so the bytes in
+/// the file are the bytes to send. Reading it reads them, positionally, advancing the range.
+#[derive(Debug, Clone)]
+pub struct ContentFileRange<'object> {
+    file: &'object File,
+    offset: u64,
+    length: u64,
+}
+
+impl<'object> ContentFileRange<'object> {
+    fn new(
+        file: &'object File,
+        header: &ObjectHeader,
+        offset: u64,
+        length: Option<u64>,
+    ) -> ObjectFileResult<Self> {
+        if !matches!(header.compression_type, CompressionTypes::None(_)) {
+            return Err(ObjectFileError::RangedReadOnCompressed);
+        }
+        if header.is_encrypted() {
+            return Err(ObjectFileError::EncryptedFileRange);
+        }
+        let content_length = header.content_length;
+        let available =
+            content_length
+                .checked_sub(offset)
+                .ok_or(ObjectFileError::RangeOutOfBounds {
+                    offset,
+                    end: offset,
+                    content_length,
+                })?;
+        let length = length.unwrap_or(available);
+        if length > available {
+            return Err(ObjectFileError::RangeOutOfBounds {
+                offset,
+                end: offset.saturating_add(length),
+                content_length,
+            });
+        }
+        Ok(Self {
+            file,
+            offset: header.content_start as u64 + offset,
+            length,
+        })
+    }
+
+    /// The object's file. Borrowed, so it stays open for as long as the range is used.
+    pub fn file(&self) -> &'object File {
+        self.file
+    }
+    /// Where the range starts in the file — not in the content.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Moves the start of the range on by `sent` bytes, which must not be more than its length.
+    fn advance(&mut self, sent: u64) {
+        self.offset += sent;
+        self.length -= sent;
+    }
+}
+
+impl Read for ContentFileRange<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let limit = self.length.min(buf.len() as u64) as usize;
+        if limit == 0 {
+            return Ok(0);
+        }
+        let read = read_at(self.file, &mut buf[..limit], self.offset)?;
+        self.advance(read as u64);
+        Ok(read)
+    }
+}
+
+impl TuxObject {
+    /// Where a byte range of the content lies in the object's file, from `offset` for `length`
+    /// bytes or to the end, for sending it with `sendfile`, `splice` or the like.
+    ///
+    /// Rejects compressed and encrypted objects, whose stored bytes are not the content.
+    pub fn content_file_range(
+        &self,
+        offset: u64,
+        length: Option<u64>,
+    ) -> ObjectFileResult<ContentFileRange<'_>> {
+        ContentFileRange::new(self.file(), self.header(), offset, length)
+    }
+}
+
+impl SharedTuxObject {
+    /// See [TuxObject::content_file_range].
+    pub fn content_file_range(
+        &self,
+        offset: u64,
+        length: Option<u64>,
+    ) -> ObjectFileResult<ContentFileRange<'_>> {
+        ContentFileRange::new(self.file(), self.header(), offset, length)
+    }
+}
+
+/// Most bytes Linux moves in one `sendfile` call.
+#[cfg(target_os = "linux")]
+const MAX_SENDFILE: u64 = 0x7fff_f000;
+
+/// Sends `range` to `target`, a socket or any other writable file, with `sendfile`, returning how
+/// many bytes were sent.
+///
+/// When the kernel cannot `sendfile` between the two, this copies through userspace instead. The
+/// range advances as it is sent, so after an error — [std::io::ErrorKind::WouldBlock] from a
+/// non-blocking socket, say — it holds what is left to send.
+#[cfg(target_os = "linux")]
+pub fn send_content_to(
+    target: &impl std::os::fd::AsFd,
+    range: &mut ContentFileRange<'_>,
+) -> ObjectFileResult<u64> {
+    use std::os::fd::AsRawFd;
+
+    let target = target.as_fd();
+    let mut sent = 0;
+    while !range.is_empty() {
+        let mut offset = libc::off_t::try_from(range.offset)
+            .map_err(|_| std::io::Error::other("file offset does not fit in off_t"))?;
+        let count = range.length.min(MAX_SENDFILE) as usize;
+        // SAFETY: both descriptors are borrowed open files, and `offset` outlives the call.
+        let result = unsafe {
+            libc::sendfile(
+                target.as_raw_fd(),
+                range.file.as_raw_fd(),
+                &mut offset,
+                count,
+            )
+        };
+        match result {
+            0 => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+            written if written > 0 => {
+                range.advance(written as u64);
+                sent += written as u64;
+            }
+            _ => {
+                let err = std::io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => {}
+                    // The pair is not one sendfile handles. That shows on the first call, before
+                    // anything was sent.
+                    Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP) if sent == 0 => {
+                        let mut target = File::from(target.try_clone_to_owned()?);
+                        return Ok(copy_through_userspace(range, &mut target)?);
+                    }
+                    _ => return Err(err.into()),
+                }
+            }
+        }
+    }
+    Ok(sent)
+}
+
+/// Copies `range` to `target` through a buffer, advancing the range by each write only once it is
+/// made, so after an error the range still holds every byte `target` did not take.
+#[cfg(target_os = "linux")]
+fn copy_through_userspace(
+    range: &mut ContentFileRange<'_>,
+    target: &mut File,
+) -> std::io::Result<u64> {
+    use std::io::Write;
+
+    let mut buf = vec![0; range.length.min(64 * 1024) as usize];
+    let mut sent = 0;
+    while !range.is_empty() {
+        let limit = range.length.min(buf.len() as u64) as usize;
+        let read = read_at(range.file, &mut buf[..limit], range.offset)?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut pending = &buf[..read];
+        while !pending.is_empty() {
+            match target.write(pending) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    range.advance(written as u64);
+                    sent += written as u64;
+                    pending = &pending[written..];
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(sent)
+}
+
+/// Most bytes Linux moves in one `copy_file_range` call.
+#[cfg(target_os = "linux")]
+const MAX_COPY_FILE_RANGE: u64 = 0x4000_0000;
+
+/// Copies `length` bytes at `offset` in `source` to the same offset in `target` without reading
+/// them into userspace, returning whether it could.
+///
+/// Tries `FICLONE` first, which shares the blocks of the whole file on filesystems with reflinks
+/// (Btrfs, XFS) and copies nothing at all, then `copy_file_range`, which keeps the copy inside
+/// the kernel, or on the server of a network filesystem. `target` is cut off after the range
+/// either way. `false` means neither works between the two files, and nothing was written.
+#[cfg(target_os = "linux")]
+pub(crate) fn copy_range_in_kernel(
+    source: &File,
+    target: &File,
+    offset: u64,
+    length: u64,
+) -> ObjectFileResult<bool> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: both descriptors are borrowed open files.
+    if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == 0 {
+        target.set_len(offset + length)?;
+        return Ok(true);
+    }
+    let mut copied = 0;
+    while copied < length {
+        let position = libc::loff_t::try_from(offset + copied)
+            .map_err(|_| std::io::Error::other("file offset does not fit in loff_t"))?;
+        let (mut source_offset, mut target_offset) = (position, position);
+        let count = (length - copied).min(MAX_COPY_FILE_RANGE) as usize;
+        // SAFETY: both descriptors are borrowed open files, and the offsets outlive the call.
+        let result = unsafe {
+            libc::copy_file_range(
+                source.as_raw_fd(),
+                &mut source_offset,
+                target.as_raw_fd(),
+                &mut target_offset,
+                count,
+                0,
+            )
+        };
+        match result {
+            0 => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+            written if written > 0 => copied += written as u64,
+            _ => {
+                let err = std::io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => {}
+                    // Files on different filesystems, or a kernel or filesystem without it.
+                    Some(libc::EXDEV | libc::ENOSYS | libc::EOPNOTSUPP | libc::EINVAL)
+                        if copied == 0 =>
+                    {
+                        return Ok(false);
+                    }
+                    _ => return Err(err.into()),
+                }
+            }
+        }
+    }
+    target.set_len(offset + length)?;
+    Ok(true)
+}
+
+/// Elsewhere the content is always copied through userspace.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn copy_range_in_kernel(
+    _source: &File,
+    _target: &File,
+    _offset: u64,
+    _length: u64,
+) -> ObjectFileResult<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::CreateOptions;
+    use crate::fs::testing::{TempDir, write_object};
+
+    #[test]
+    fn file_ranges_point_at_the_content() {
+        let dir = TempDir::new("range");
+        let path = dir.0.join("object.tuxio");
+        write_object(&path, CreateOptions::new(), b"Hello, world!");
+
+        let object = TuxObject::open(&path).unwrap();
+        let mut range = object.content_file_range(7, Some(5)).unwrap();
+        assert_eq!(range.offset(), object.layout().content_start as u64 + 7);
+        assert_eq!(range.length(), 5);
+        let mut read = String::new();
+        range.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "world");
+        assert!(range.is_empty());
+
+        assert!(matches!(
+            object.content_file_range(7, Some(7)),
+            Err(ObjectFileError::RangeOutOfBounds { .. })
+        ));
+        let shared = object.into_shared();
+        assert_eq!(shared.content_file_range(0, None).unwrap().length(), 13);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn content_is_sent_to_a_socket() {
+        let dir = TempDir::new("socket");
+        let path = dir.0.join("object.tuxio");
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i * 13) as u8).collect();
+        write_object(&path, CreateOptions::new(), &content);
+
+        let object = TuxObject::open(&path).unwrap();
+        let (sender, mut receiver) = std::os::unix::net::UnixStream::pair().unwrap();
+        let reading = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            receiver.read_to_end(&mut received).unwrap();
+            received
+        });
+        let mut range = object.content_file_range(1000, Some(90_000)).unwrap();
+        assert_eq!(send_content_to(&sender, &mut range).unwrap(), 90_000);
+        assert!(range.is_empty());
+        drop(sender);
+        assert_eq!(reading.join().unwrap(), &content[1000..91_000]);
+    }
+
+    /// `sendfile` refuses a target opened for appending, so this goes through the fallback.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn content_is_copied_where_sendfile_is_refused() {
+        let dir = TempDir::new("fallback");
+        let path = dir.0.join("object.tuxio");
+        write_object(&path, CreateOptions::new(), b"Hello, world!");
+        let target_path = dir.0.join("target");
+        let target = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&target_path)
+            .unwrap();
+
+        let object = TuxObject::open(&path).unwrap();
+        let mut range = object.content_file_range(0, None).unwrap();
+        assert_eq!(send_content_to(&target, &mut range).unwrap(), 13);
+        assert_eq!(std::fs::read(&target_path).unwrap(), b"Hello, world!");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn copies_cut_short_leave_the_unsent_bytes_in_the_range() {
+        let dir = TempDir::new("cut-short");
+        let path = dir.0.join("object.tuxio");
+        let content: Vec<u8> = (0..1_000_000u32).map(|i| (i * 7) as u8).collect();
+        write_object(&path, CreateOptions::new(), &content);
+
+        let object = TuxObject::open(&path).unwrap();
+        let (sender, mut receiver) = std::os::unix::net::UnixStream::pair().unwrap();
+        sender.set_nonblocking(true).unwrap();
+        let mut target = File::from(std::os::fd::OwnedFd::from(sender));
+        let mut range = object.content_file_range(0, None).unwrap();
+        let err = copy_through_userspace(&mut range, &mut target).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+        // Whatever the socket took, and what is left in the range, make up the content exactly.
+        drop(target);
+        let mut received = Vec::new();
+        receiver.read_to_end(&mut received).unwrap();
+        assert_eq!(range.length() as usize, content.len() - received.len());
+        let mut rest = Vec::new();
+        range.read_to_end(&mut rest).unwrap();
+        received.extend(rest);
+        assert_eq!(received, content);
+    }
+}
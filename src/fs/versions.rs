@@ -0,0 +1,519 @@
+//! Keeping the versions an object is overwritten with, as a versioned S3 bucket does.
+//!
+//! A versioned object keeps every version it is published as in a directory beside it,
+//! `.versions/<file name>/<version id>`. Each is a hard link to the file as it was published, so
+//! keeping a version costs a directory entry rather than a copy, and each records its id in its
+//! metadata under [VERSION_ID]. Deleting one with [place_delete_marker] removes the object but
+//! leaves a delete marker behind, so it reads as absent while every version can still be opened
+//! with [open_version] — and [delete_version] on the marker brings the object back.
+//!
+//! Versioning is turned on by writing with [CreateOptions::versioned], and stays on for as long as
+//! the directory exists: every later write to the path — [ObjectWriter::finish], and through it
+//! [TuxObject::set_metadata] and the other atomic updates — keeps the version it replaces, whether
+//! or not it asked to. The in-place updates, [TuxObject::set_sections_in_place] and
+//! [TuxObject::append], would change the kept copy along with the current version, so they fail
+//! with [ObjectFileError::InPlaceUpdateOfVersionedObject] on a versioned object, and on a kept
+//! version itself.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use http::HeaderName;
+
+use crate::{
+    MetadataMap,
+    fs::{
+        CreateOptions, ObjectFileError, ObjectFileResult, ObjectWriter, TuxObject,
+        writer::{next_temp_counter, temp_path_for},
+    },
+};
+
+/// Metadata key holding the id of the version an object is.
+pub const VERSION_ID: HeaderName = HeaderName::from_static("x-tuxio-version-id");
+/// Directory, beside a versioned object, holding its versions.
+pub const VERSIONS_DIR: &str = ".versions";
+/// Id of a version written before versioning was turned on, which has no id of its own. S3's name
+/// for it.
+pub const NULL_VERSION_ID: &str = "null";
+/// Extension of a delete marker's file, after its version id.
+const DELETE_MARKER_EXTENSION: &str = "deleted";
+
+/// One entry in an object's version history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectVersion {
+    pub version_id: String,
+    pub is_delete_marker: bool,
+    /// Whether this is the version the object now reads as — or, for a delete marker, whether the
+    /// object now reads as deleted because of it.
+    pub is_latest: bool,
+}
+
+/// Where the versions of the object at `path` are kept.
+pub fn versions_dir(path: &Path) -> PathBuf {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path.file_name().unwrap_or_default();
+    parent.join(VERSIONS_DIR).join(name)
+}
+
+/// Whether writes to `path` keep the versions they replace.
+pub fn is_versioned(path: impl AsRef<Path>) -> bool {
+    versions_dir(path.as_ref()).is_dir()
+}
+
+/// Whether `path` is a version kept in a [VERSIONS_DIR], rather than an object in its own right.
+pub(crate) fn is_kept_version(path: &Path) -> bool {
+    path.parent().is_some_and(|parent| {
+        parent
+            .components()
+            .any(|dir| dir.as_os_str() == VERSIONS_DIR)
+    })
+}
+
+/// Fails when changing the file at `path` where it is would change a kept version too.
+pub(crate) fn ensure_unshared(path: &Path) -> ObjectFileResult<()> {
+    if is_kept_version(path) || is_versioned(path) {
+        return Err(ObjectFileError::InPlaceUpdateOfVersionedObject);
+    }
+    Ok(())
+}
+
+/// The object's history, newest first. Empty for an object that was never versioned.
+pub fn list_versions(path: impl AsRef<Path>) -> ObjectFileResult<Vec<ObjectVersion>> {
+    let path = path.as_ref();
+    let entries = match std::fs::read_dir(versions_dir(path)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut versions = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let (version_id, is_delete_marker) = match name.split_once('.') {
+            Some((version_id, DELETE_MARKER_EXTENSION)) => (version_id, true),
+            Some(_) => continue,
+            None => (name, false),
+        };
+        if is_version_id(version_id) {
+            versions.push(ObjectVersion {
+                version_id: version_id.to_owned(),
+                is_delete_marker,
+                is_latest: false,
+            });
+        }
+    }
+    // Ids start with the time they were written at, in fixed-width hex; the null version predates
+    // them all.
+    versions.sort_unstable_by(|a, b| {
+        let key = |version: &ObjectVersion| {
+            (
+                version.version_id != NULL_VERSION_ID,
+                version.version_id.clone(),
+            )
+        };
+        key(b).cmp(&key(a))
+    });
+    // The object itself says which version it is, which holds even if a write raced another to
+    // publish after taking an older id.
+    let latest = match current_version_id(path)? {
+        Some(current) => versions
+            .iter_mut()
+            .find(|version| !version.is_delete_marker && version.version_id == current),
+        None => versions
+            .first_mut()
+            .filter(|version| version.is_delete_marker),
+    };
+    if let Some(latest) = latest {
+        latest.is_latest = true;
+    }
+    Ok(versions)
+}
+
+/// Opens a version of the object for reading. Fails with [ObjectFileError::NoSuchVersion] when
+/// there is no such version, or it is a delete marker.
+pub fn open_version(path: impl AsRef<Path>, version_id: &str) -> ObjectFileResult<TuxObject> {
+    match TuxObject::open(version_path(path.as_ref(), version_id)?) {
+        Err(err) if err.is_not_found() => {
+            Err(ObjectFileError::NoSuchVersion(version_id.to_owned()))
+        }
+        result => result,
+    }
+}
+
+/// Deletes the object at `path` the way a versioned bucket does: the object is removed, and a
+/// delete marker is placed in its history, keeping what was deleted as a version. Returns the
+/// marker's version id.
+///
+/// Turns versioning on, when it was not already.
+pub fn place_delete_marker(path: impl AsRef<Path>) -> ObjectFileResult<String> {
+    let path = path.as_ref();
+    let dir = versions_dir(path);
+    std::fs::create_dir_all(&dir)?;
+    keep_current(path, &dir)?;
+    let version_id = new_version_id();
+    File::create_new(dir.join(format!("{version_id}.{DELETE_MARKER_EXTENSION}")))?;
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    Ok(version_id)
+}
+
+/// Removes a version, or a delete marker, from the object's history for good, returning whether
+/// there was one.
+///
+/// Removing the latest entry makes the one before it the latest, as S3 does: the object reads as
+/// the previous version again, or stays deleted when that is a delete marker. So removing the
+/// delete marker a delete placed undoes the delete.
+pub fn delete_version(path: impl AsRef<Path>, version_id: &str) -> ObjectFileResult<bool> {
+    let path = path.as_ref();
+    let versions = list_versions(path)?;
+    let Some(index) = versions
+        .iter()
+        .position(|version| version.version_id == version_id)
+    else {
+        return Ok(false);
+    };
+    let removed = &versions[index];
+    let mut entry = version_path(path, version_id)?;
+    if removed.is_delete_marker {
+        entry.set_extension(DELETE_MARKER_EXTENSION);
+    }
+    match std::fs::remove_file(&entry) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    }
+    if !removed.is_latest {
+        return Ok(true);
+    }
+    match versions
+        .iter()
+        .find(|version| version.version_id != version_id)
+    {
+        Some(previous) if !previous.is_delete_marker => {
+            // Linked under a temporary name first, so the object is swapped in with one rename.
+            let temp_path = temp_path_for(path);
+            std::fs::hard_link(version_path(path, &previous.version_id)?, &temp_path)?;
+            if let Err(err) = std::fs::rename(&temp_path, path) {
+                let _ = std::fs::remove_file(&temp_path);
+                return Err(err.into());
+            }
+        }
+        _ => match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        },
+    }
+    Ok(true)
+}
+
+/// Sets, or for an unversioned write clears, the version id in metadata a writer is about to
+/// publish. Ids are all one length, so the one set when the writer is created reserves the room
+/// for the one set when it finishes.
+///
+/// A write to a kept version itself, at `path` in a [VERSIONS_DIR], leaves the id as it is: it is
+/// the id the version is kept under.
+pub(crate) fn stamp_version_id(metadata: &mut MetadataMap, versioned: bool, path: &Path) {
+    if is_kept_version(path) {
+        return;
+    }
+    if versioned {
+        metadata.insert_header(VERSION_ID, new_version_id().into());
+    } else {
+        metadata.remove_header(&VERSION_ID);
+    }
+}
+
+/// Keeps the file at `temp_path`, about to be published at `path`, as version `version_id`,
+/// after keeping the object it is replacing if that is not kept already. Returns where the
+/// version is kept, for removing it again should the publish fail.
+pub(crate) fn keep_published(
+    path: &Path,
+    temp_path: &Path,
+    version_id: &str,
+    replacing: bool,
+) -> ObjectFileResult<PathBuf> {
+    let dir = versions_dir(path);
+    std::fs::create_dir_all(&dir)?;
+    if replacing {
+        keep_current(path, &dir)?;
+    }
+    let kept = dir.join(version_id);
+    std::fs::hard_link(temp_path, &kept)?;
+    Ok(kept)
+}
+
+/// The version id in metadata a writer stamped.
+pub(crate) fn stamped_version_id(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get_header(&VERSION_ID)
+        .and_then(|version_id| version_id.as_str())
+}
+
+/// Keeps the object at `path` in `dir` under its own version id, unless it is there already —
+/// which it is, unless it was written before versioning was turned on.
+fn keep_current(path: &Path, dir: &Path) -> ObjectFileResult<()> {
+    let Some(version_id) = current_version_id(path)? else {
+        return Ok(());
+    };
+    match std::fs::hard_link(path, dir.join(version_id)) {
+        Ok(()) => Ok(()),
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::AlreadyExists | std::io::ErrorKind::NotFound
+            ) =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The version id of the object at `path`, or `None` when there is no object.
+fn current_version_id(path: &Path) -> ObjectFileResult<Option<String>> {
+    let current = match TuxObject::open(path) {
+        Ok(current) => current,
+        Err(err) if err.is_not_found() => return Ok(None),
+        Err(ObjectFileError::IO(err)) => return Err(err.into()),
+        // Not readable as an object, but it is what was there, so it is kept all the same.
+        Err(_) => return Ok(Some(NULL_VERSION_ID.to_owned())),
+    };
+    let version_id = stamped_version_id(current.metadata())
+        .filter(|version_id| is_version_id(version_id))
+        .unwrap_or(NULL_VERSION_ID);
+    Ok(Some(version_id.to_owned()))
+}
+
+/// Where version `version_id` of the object at `path` is kept, whether or not it is.
+pub(crate) fn version_path(path: &Path, version_id: &str) -> ObjectFileResult<PathBuf> {
+    // Ids are only ever generated here, so anything else cannot name a version — and must not be
+    // let near a path.
+    if !is_version_id(version_id) {
+        return Err(ObjectFileError::NoSuchVersion(version_id.to_owned()));
+    }
+    Ok(versions_dir(path).join(version_id))
+}
+
+fn is_version_id(version_id: &str) -> bool {
+    version_id == NULL_VERSION_ID
+        || (!version_id.is_empty()
+            && version_id
+                .bytes()
+                .all(|byte| byte.is_ascii_hexdigit() || byte == b'-'))
+}
+
+/// A fresh version id: the time in nanoseconds, then the process and a counter to tell apart ids
+/// taken at the same instant. Fixed width, so ids sort in the order they were taken.
+fn new_version_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    format!(
+        "{nanos:016x}-{:08x}-{:08x}",
+        std::process::id(),
+        next_temp_counter() as u32
+    )
+}
+
+impl CreateOptions {
+    /// Keeps the versions of the object the write replaces. See the [module docs](self) — once on,
+    /// versioning stays on for every write to the path.
+    pub fn with_versioning(mut self, versioned: bool) -> Self {
+        self.versioned = versioned;
+        self
+    }
+}
+
+impl ObjectWriter {
+    /// The version this object will be published as, when it is versioned. Set afresh by
+    /// [ObjectWriter::finish].
+    pub fn version_id(&self) -> Option<&str> {
+        stamped_version_id(self.metadata())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Tags,
+        fs::testing::{TempDir, write_object},
+    };
+
+    fn content(object: &mut TuxObject) -> Vec<u8> {
+        object.read_content_to_vec().unwrap()
+    }
+
+    fn version_id(object: &TuxObject) -> String {
+        stamped_version_id(object.metadata()).unwrap().to_owned()
+    }
+
+    #[test]
+    fn overwrites_keep_the_versions_they_replace() {
+        let dir = TempDir::new("overwrite");
+        let path = dir.0.join("object.tuxio");
+        // Written before versioning was on, so it is kept as the null version.
+        let unversioned = write_object(&path, CreateOptions::new(), b"zero");
+        assert_eq!(stamped_version_id(unversioned.metadata()), None);
+        assert!(!is_versioned(&path));
+
+        let first = write_object(&path, CreateOptions::new().with_versioning(true), b"one");
+        assert!(is_versioned(&path));
+        // Versioning stays on without being asked for again, through updates as much as writes.
+        let second = write_object(&path, CreateOptions::new(), b"two");
+        let mut updated = TuxObject::open_writable(&path).unwrap();
+        updated
+            .modify_metadata(|metadata| {
+                metadata.insert(
+                    http::header::CONTENT_TYPE.into(),
+                    "text/plain".to_owned().into(),
+                );
+            })
+            .unwrap();
+        let third = version_id(&updated);
+        assert_ne!(third, version_id(&second));
+
+        let versions = list_versions(&path).unwrap();
+        let ids: Vec<_> = versions
+            .iter()
+            .map(|version| version.version_id.as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                third.as_str(),
+                &version_id(&second),
+                &version_id(&first),
+                NULL_VERSION_ID
+            ]
+        );
+        assert!(versions[0].is_latest);
+        assert!(versions[1..].iter().all(|version| !version.is_latest));
+
+        assert_eq!(
+            content(&mut open_version(&path, NULL_VERSION_ID).unwrap()),
+            b"zero"
+        );
+        assert_eq!(
+            content(&mut open_version(&path, &version_id(&first)).unwrap()),
+            b"one"
+        );
+        let mut kept = open_version(&path, &third).unwrap();
+        assert_eq!(content(&mut kept), b"two");
+        assert!(
+            kept.metadata()
+                .get_header(&http::header::CONTENT_TYPE)
+                .is_some()
+        );
+
+        for missing in ["0123-4567", "../object.tuxio", ""] {
+            assert!(matches!(
+                open_version(&path, missing),
+                Err(ObjectFileError::NoSuchVersion(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn delete_markers_hide_the_object_until_removed() {
+        let dir = TempDir::new("delete");
+        let path = dir.0.join("object.tuxio");
+        let first = version_id(&write_object(
+            &path,
+            CreateOptions::new().with_versioning(true),
+            b"one",
+        ));
+        let second = version_id(&write_object(&path, CreateOptions::new(), b"two"));
+
+        let marker = place_delete_marker(&path).unwrap();
+        assert!(!path.exists());
+        let versions = list_versions(&path).unwrap();
+        assert_eq!(versions[0].version_id, marker);
+        assert!(versions[0].is_delete_marker && versions[0].is_latest);
+        assert!(matches!(
+            open_version(&path, &marker),
+            Err(ObjectFileError::NoSuchVersion(_))
+        ));
+        assert_eq!(content(&mut open_version(&path, &second).unwrap()), b"two");
+
+        // Removing the marker brings the object back as it was.
+        assert!(delete_version(&path, &marker).unwrap());
+        assert!(!delete_version(&path, &marker).unwrap());
+        assert_eq!(content(&mut TuxObject::open(&path).unwrap()), b"two");
+
+        // Removing the current version falls back to the one before it.
+        assert!(delete_version(&path, &second).unwrap());
+        assert_eq!(content(&mut TuxObject::open(&path).unwrap()), b"one");
+        let versions = list_versions(&path).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert!(versions[0].version_id == first && versions[0].is_latest);
+
+        assert!(delete_version(&path, &first).unwrap());
+        assert!(!path.exists());
+        assert!(list_versions(&path).unwrap().is_empty());
+        // The history is empty, but versioning stays on.
+        assert!(is_versioned(&path));
+    }
+
+    #[test]
+    fn unversioned_writes_drop_a_carried_version_id() {
+        let dir = TempDir::new("unversioned");
+        let versioned = write_object(
+            &dir.0.join("a.tuxio"),
+            CreateOptions::new().with_versioning(true),
+            b"a",
+        );
+        let copy = write_object(
+            &dir.0.join("b.tuxio"),
+            CreateOptions::new().with_metadata(versioned.metadata().clone()),
+            b"b",
+        );
+        assert_eq!(stamped_version_id(copy.metadata()), None);
+        assert!(list_versions(dir.0.join("b.tuxio")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn kept_versions_are_never_changed_in_place() {
+        let dir = TempDir::new("in-place");
+        let path = dir.0.join("object.tuxio");
+        let id = version_id(&write_object(
+            &path,
+            CreateOptions::new().with_versioning(true),
+            b"one",
+        ));
+        let kept = version_path(&path, &id).unwrap();
+        for path in [&path, &kept] {
+            let mut object = TuxObject::open_writable(path).unwrap();
+            assert!(matches!(
+                object.append(b"two"),
+                Err(ObjectFileError::InPlaceUpdateOfVersionedObject)
+            ));
+            assert!(matches!(
+                object.set_sections_in_place(object.metadata().clone(), Tags::new()),
+                Err(ObjectFileError::InPlaceUpdateOfVersionedObject)
+            ));
+        }
+        assert_eq!(content(&mut open_version(&path, &id).unwrap()), b"one");
+
+        // Rewriting a kept version keeps the id it is kept under.
+        let mut rewritten = TuxObject::open_writable(&kept).unwrap();
+        rewritten
+            .modify_metadata(|metadata| {
+                metadata.insert(
+                    http::header::CONTENT_TYPE.into(),
+                    "text/plain".to_owned().into(),
+                );
+            })
+            .unwrap();
+        assert_eq!(version_id(&rewritten), id);
+    }
+}
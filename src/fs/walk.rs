@@ -0,0 +1,74 @@
+//! Finding the object files under a directory, for the stores' listings, [crate::fs::lifecycle]
+//! and tools that work through a whole tree of objects.
+
+use std::path::{Path, PathBuf};
+
+use crate::fs::{ObjectFileResult, TEMP_FILE_EXTENSION};
+
+/// Every regular file under `dir`, recursively and in path order, skipping writers' temporary
+/// files.
+///
+/// Directories whose names start with a dot are not entered: they hold the versions kept of
+/// versioned objects, in [crate::fs::versions::VERSIONS_DIR], and the parts of a store's
+/// multipart uploads, which are not objects in their own right. Symlinks are not followed, so a link back up the tree cannot
+/// send the walk in circles. A directory that is gone by the time it is read — `dir` included —
+/// holds nothing.
+pub fn object_files(dir: impl AsRef<Path>) -> ObjectFileResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // Pruned by a delete since it was seen, or never there.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    pending.push(path);
+                }
+            } else if file_type.is_file()
+                && path
+                    .extension()
+                    .is_none_or(|extension| extension != TEMP_FILE_EXTENSION)
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::testing::TempDir;
+
+    #[test]
+    fn dot_directories_and_temp_files_are_skipped() {
+        let root = TempDir::new("walk");
+        for dir in ["a", ".versions/object.tuxio", ".multipart/upload"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "object.tuxio",
+            "a/nested.tuxio",
+            "a/.nested.tuxio.1.2.tuxtmp",
+            ".versions/object.tuxio/version",
+            ".multipart/upload/00001.part",
+        ] {
+            std::fs::write(root.join(file), b"").unwrap();
+        }
+
+        assert_eq!(
+            object_files(&root.0).unwrap(),
+            [root.join("a/nested.tuxio"), root.join("object.tuxio")]
+        );
+        assert!(object_files(root.join("missing")).unwrap().is_empty());
+    }
+}
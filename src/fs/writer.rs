@@ -1,15 +1,26 @@
 use std::{
     fs::{File, OpenOptions},
-    io::{Cursor, Seek, SeekFrom, Write},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     sync::atomic::{AtomicU64, Ordering},
 };
 
 use crate::{
     CompressionTypes, MetadataMap, ObjectHeader, Tags, TuxIOType, WritableObjectType,
-    fs::{HEADER_SIZE, LayoutOptions, ObjectFileError, ObjectFileResult, SectionLayout, TuxObject},
+    fs::{
+        CompressionPolicy, HEADER_SIZE, LayoutOptions, LockMode, ObjectFileError, ObjectFileResult,
+        SectionLayout, TuxObject,
+        lock::{LockWait, lock_file, same_file},
+        shared::read_exact_at,
+        sniff::ContentSniffer,
+        versions::{is_versioned, keep_published, stamp_version_id, stamped_version_id},
+    },
 };
 
+/// Extension of the temporary files writers stream into before publishing. Their full name is
+/// `.{file name}.{pid}.{counter}.tuxtmp`, which [crate::fs::sweep_temp_files] relies on.
+pub const TEMP_FILE_EXTENSION: &str = "tuxtmp";
+
 /// Distinguishes temp files created by concurrent writers in the same directory.
 static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
 
@@ -25,9 +36,28 @@ pub struct CreateOptions {
     pub tags: Tags,
     pub layout: LayoutOptions,
     pub compression: CompressionTypes,
+    /// Whether `compression` is applied unconditionally or only when the content proves compressible.
+    pub compression_policy: CompressionPolicy,
+    /// Threads to compress with. Zero or one compresses on the writing thread; more runs zstd in its
+    /// multithreaded mode, or compresses gzip in independent blocks side by side.
+    ///
+    /// Parallel gzip writes each block as a gzip member of its own. That is still gzip, but only
+    /// readers that decode every member read it back whole: one that stops after the first member,
+    /// as earlier releases of this crate did, silently returns the first block alone, and nothing
+    /// in the object marks it. Keep this at zero or one while such readers remain. Parallel zstd
+    /// is a single frame, which every reader decodes.
+    pub compression_workers: u32,
+    /// Seal the content with this key. Applied after compression, to the stored bytes.
+    #[cfg(feature = "encryption")]
+    pub encryption: Option<crate::fs::ContentEncryption>,
     /// `fsync` the file before publishing it. Costs a flush per object but means a completed write
     /// survives a power loss.
     pub sync: bool,
+    /// Keep the version this write replaces, and every later one. See [crate::fs::versions].
+    pub versioned: bool,
+    /// Fill in `Content-Type` from the start of the content when the metadata has none. See
+    /// [crate::fs::sniff_content_type].
+    pub sniff_content_type: bool,
 }
 
 impl CreateOptions {
@@ -50,6 +80,19 @@ impl CreateOptions {
         self.compression = compression;
         self
     }
+    pub fn with_compression_policy(mut self, compression_policy: CompressionPolicy) -> Self {
+        self.compression_policy = compression_policy;
+        self
+    }
+    pub fn with_compression_workers(mut self, compression_workers: u32) -> Self {
+        self.compression_workers = compression_workers;
+        self
+    }
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, encryption: crate::fs::ContentEncryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
     pub fn with_sync(mut self, sync: bool) -> Self {
         self.sync = sync;
         self
@@ -74,10 +117,34 @@ pub struct ObjectWriter {
     layout: SectionLayout,
     layout_options: LayoutOptions,
     compression: CompressionTypes,
+    compression_policy: CompressionPolicy,
+    compression_workers: u32,
     metadata: MetadataMap,
     tags: Tags,
+    /// Content bytes written — before encryption, for an encrypted object.
     content_length: u64,
+    /// Bytes that have reached the file, which is what the header records.
+    stored_length: u64,
+    bit_flags: u8,
+    /// Taken on the temp file before it is published, so a lock held on the object being replaced
+    /// carries over to the replacement without a gap.
+    lock: Option<LockMode>,
+    /// The file of the object being replaced, on which the carried lock is held. The generation is
+    /// read through it: locking the object again from another open file would wait on that very
+    /// lock.
+    locked_file: Option<File>,
+    /// What a conditional finish requires of the object it replaces.
+    precondition: Precondition,
+    /// Settled at publishing, from the object being replaced.
+    generation: u64,
+    /// Seals the content on its way to the file, when encrypting.
+    #[cfg(feature = "encryption")]
+    sealer: Option<crate::fs::encryption::SegmentSealer>,
     sync: bool,
+    /// Whether the object is published as a new version, keeping the one it replaces.
+    versioned: bool,
+    /// Collects the start of the content, when its type is to be sniffed.
+    sniffer: Option<ContentSniffer>,
     /// Guards against writing raw bytes into an object whose header claims a codec, which would
     /// leave a file that reads back as garbage. Cleared for compressed objects until
     /// [ObjectWriter::content_encoder] hands out an encoder.
@@ -92,9 +159,30 @@ impl ObjectWriter {
     pub fn create(path: impl Into<PathBuf>, options: CreateOptions) -> ObjectFileResult<Self> {
         let final_path = path.into();
         crate::fs::ensure_supported(options.compression)?;
-        let layout = options
-            .layout
-            .compute(options.metadata.size(), options.tags.size())?;
+        #[allow(unused_mut)]
+        let mut metadata = options.metadata;
+        let versioned = options.versioned || is_versioned(&final_path);
+        stamp_version_id(&mut metadata, versioned, &final_path);
+        #[allow(unused_mut)]
+        let mut bit_flags = 0;
+        #[cfg(feature = "encryption")]
+        let sealer = match &options.encryption {
+            Some(encryption) => {
+                let cipher = crate::fs::encryption::SegmentCipher::create(encryption)?;
+                // Described before the layout is computed, so the reserve is measured with it.
+                cipher.describe(&mut metadata);
+                bit_flags |= ObjectHeader::ENCRYPTED_CONTENT;
+                Some(crate::fs::encryption::SegmentSealer::new(cipher))
+            }
+            None => None,
+        };
+        let sniffer = options.sniff_content_type.then(ContentSniffer::default);
+        let mut layout_options = options.layout;
+        if sniffer.is_some() {
+            // Room for the type to be filled in without the content having to move.
+            layout_options.metadata_reserve += ContentSniffer::reserve();
+        }
+        let layout = layout_options.compute(metadata.size(), options.tags.size())?;
 
         let (file, temp_path) = create_temp_file(&final_path)?;
         let allow_raw_writes = matches!(options.compression, CompressionTypes::None(_));
@@ -106,10 +194,22 @@ impl ObjectWriter {
             layout,
             layout_options: options.layout,
             compression: options.compression,
-            metadata: options.metadata,
+            compression_policy: options.compression_policy,
+            compression_workers: options.compression_workers,
+            metadata,
             tags: options.tags,
             content_length: 0,
+            stored_length: 0,
+            bit_flags,
+            lock: None,
+            locked_file: None,
+            precondition: Precondition::None,
+            generation: 1,
+            #[cfg(feature = "encryption")]
+            sealer,
             sync: options.sync,
+            versioned,
+            sniffer,
             allow_raw_writes,
         };
         // Leave the prefix untouched for now; it gets written by `finish`.
@@ -133,7 +233,7 @@ impl ObjectWriter {
     pub fn tags_mut(&mut self) -> &mut Tags {
         &mut self.tags
     }
-    /// Content bytes written so far.
+    /// Content bytes written so far, before encryption.
     pub fn content_length(&self) -> u64 {
         self.content_length
     }
@@ -141,15 +241,34 @@ impl ObjectWriter {
     pub fn path(&self) -> &Path {
         &self.final_path
     }
+    /// The `Content-Type` [ObjectWriter::finish] would fill in from the content written so far, if
+    /// the writer sniffs and the metadata has none by then.
+    pub fn sniffed_content_type(&self) -> Option<&'static str> {
+        self.sniffer.as_ref()?.content_type()
+    }
+    /// Hands content on its way in, before compression, to the sniffer.
+    pub(crate) fn sniff(&mut self, content: &[u8]) {
+        if let Some(sniffer) = &mut self.sniffer {
+            sniffer.feed(content);
+        }
+    }
     /// Bytes still available to the metadata and tag sections without moving the content.
     pub fn reserved_space(&self) -> usize {
         self.layout.prefix_size() - HEADER_SIZE
     }
     /// The compression the finished object will declare.
+    ///
+    /// Under [CompressionPolicy::Auto] this is the configured codec until the content encoder has
+    /// sampled enough content to decide, and may become uncompressed after that.
     pub fn compression(&self) -> CompressionTypes {
         self.compression
     }
 
+    /// Threads the content encoder compresses with. See [CreateOptions::compression_workers].
+    pub fn compression_workers(&self) -> u32 {
+        self.compression_workers
+    }
+
     /// An encoder that compresses content on its way into this writer.
     ///
     /// This is the only way to write the content of a compressed object — writing to the
@@ -157,17 +276,144 @@ impl ObjectWriter {
     /// bytes under a header claiming they were compressed. For an uncompressed object the encoder
     /// is a pass-through, so the same code path works either way.
     ///
+    /// Under [CompressionPolicy::Auto] the encoder holds back the start of the content until it has
+    /// judged the codec, so nothing reaches the file before that.
+    ///
     /// [crate::fs::ContentEncoder::finish] must be called to flush the codec.
     pub fn content_encoder(&mut self) -> ObjectFileResult<crate::fs::ContentEncoder<'_>> {
         let compression = self.compression;
         // The encoder borrows the writer exclusively, so nothing else can write raw bytes while it
         // is alive.
         self.allow_raw_writes = true;
-        crate::fs::ContentEncoder::new(self, compression)
+        match self.compression_policy {
+            CompressionPolicy::Auto {
+                min_ratio,
+                sample_bytes,
+            } if sample_bytes > 0 && !matches!(compression, CompressionTypes::None(_)) => {
+                crate::fs::ContentEncoder::sampling(self, compression, min_ratio, sample_bytes)
+            }
+            _ => crate::fs::ContentEncoder::new(self, compression),
+        }
+    }
+
+    /// Settles the codec the object will declare, once a [CompressionPolicy::Auto] sample has
+    /// decided it. Later encoders use it as given rather than sampling again.
+    pub(crate) fn commit_compression(&mut self, compression: CompressionTypes) {
+        self.compression = compression;
+        self.compression_policy = CompressionPolicy::Fixed;
+    }
+
+    /// Copies content that is already in its stored form — compressed, if the object is — straight
+    /// into the file.
+    ///
+    /// This is how a rewrite carries an object's content across: the bytes were encoded when they
+    /// were first written, so they must bypass the guard on the [Write] impl rather than be rejected
+    /// by it, and must not be encoded a second time.
+    pub(crate) fn copy_stored_content(&mut self, source: &mut impl Read) -> ObjectFileResult<u64> {
+        let copied = std::io::copy(source, &mut self.file)?;
+        self.content_length += copied;
+        self.stored_length += copied;
+        Ok(copied)
+    }
+
+    /// [ObjectWriter::copy_stored_content], but from the content of another object's file, copied
+    /// in the kernel — its blocks shared, on a filesystem that can. Returns whether it was; it can
+    /// only be while nothing has been written yet and the content stays at the same offset.
+    pub(crate) fn copy_stored_content_in_kernel(
+        &mut self,
+        source: &File,
+        source_layout: SectionLayout,
+        length: u64,
+    ) -> ObjectFileResult<bool> {
+        let content_start = source_layout.content_start as u64;
+        if self.stored_length > 0
+            || self.layout.content_start != source_layout.content_start
+            || !crate::fs::transfer::copy_range_in_kernel(
+                source,
+                &self.file,
+                content_start,
+                length,
+            )?
+        {
+            return Ok(false);
+        }
+        self.file.seek(SeekFrom::Start(content_start + length))?;
+        self.content_length += length;
+        self.stored_length += length;
+        Ok(true)
+    }
+
+    /// Carries header flags describing content copied across in its stored form — an encrypted
+    /// object's ciphertext stays encrypted through a rewrite.
+    pub(crate) fn carry_bit_flags(&mut self, bit_flags: u8) {
+        self.bit_flags |= bit_flags;
+    }
+
+    /// Carries the lock held on the object this one replaces, through `file`, the replaced object's
+    /// open file.
+    pub(crate) fn carry_lock(
+        &mut self,
+        lock: Option<LockMode>,
+        file: &File,
+    ) -> ObjectFileResult<()> {
+        self.lock = lock;
+        self.locked_file = match lock {
+            Some(_) => Some(file.try_clone()?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// [ObjectWriter::finish], but only if there is nothing at the destination yet — S3's
+    /// `If-None-Match: *`. Otherwise fails with [ObjectFileError::PreconditionFailed] and the write
+    /// is abandoned.
+    ///
+    /// The object is published with a hard link, which cannot replace an existing file, so two
+    /// writers racing to create the same object cannot both succeed.
+    pub fn finish_if_absent(mut self) -> ObjectFileResult<TuxObject> {
+        self.precondition = Precondition::Absent;
+        self.finish()
+    }
+
+    /// [ObjectWriter::finish], but only if the object at the destination is at generation
+    /// `expected` — S3's `If-Match`. Otherwise, or when there is no object, fails with
+    /// [ObjectFileError::PreconditionFailed] and the write is abandoned.
+    ///
+    /// The object being replaced is held under an exclusive lock from the check until the new one
+    /// is published, so this is only airtight against other writers that lock: conditional
+    /// finishes and updates, and [TuxObject::lock] holders. A writer from a handle that holds the
+    /// exclusive lock already, such as [TuxObject::content_writer] after [TuxObject::lock], checks
+    /// through that handle's lock instead. One from a handle holding a shared lock fails with
+    /// [ObjectFileError::ConditionalWriteUnderSharedLock]: other shared holders could be checking
+    /// at the same time.
+    ///
+    /// Generations count from 1 again for an object deleted and created anew, so a generation only
+    /// names one version of an object that has not been deleted since it was read. Check
+    /// something that identifies the content as well, an ETag say, where that can happen.
+    pub fn finish_if_generation(mut self, expected: u64) -> ObjectFileResult<TuxObject> {
+        self.precondition = Precondition::Generation(expected);
+        self.finish()
     }
 
     /// Writes the prefix, publishes the object, and reopens it for reading.
+    ///
+    /// The new object's generation continues from the one it replaces, read from its header just
+    /// before publishing. Without a lock that read can race another writer, so two unconditional
+    /// writes may publish the same generation; only conditional finishes, and writers from a
+    /// handle holding an exclusive lock, are sure of a generation no other write has.
     pub fn finish(mut self) -> ObjectFileResult<TuxObject> {
+        // The last segment is sealed differently, so it could not be written until now.
+        #[cfg(feature = "encryption")]
+        if let Some(sealer) = &mut self.sealer {
+            self.stored_length += sealer.finish(&mut self.file)?;
+        }
+        // Versioning may have been turned on for the path since the writer was created.
+        self.versioned |= is_versioned(&self.final_path);
+        stamp_version_id(&mut self.metadata, self.versioned, &self.final_path);
+        if let Some(sniffer) = self.sniffer.take() {
+            sniffer.fill_in(&mut self.metadata);
+        }
+
         let metadata_size = self.metadata.size();
         let tags_size = self.tags.size();
 
@@ -184,6 +430,7 @@ impl ObjectWriter {
         };
         self.layout = layout;
 
+        let replaced = self.check_precondition()?;
         let header = self.build_header();
         let prefix = encode_prefix(&header, &self.metadata, &self.tags, layout)?;
 
@@ -196,14 +443,22 @@ impl ObjectWriter {
         }
 
         self.publish()?;
+        // Releases the lock on the replaced object, if the precondition took one.
+        drop(replaced);
 
-        Ok(TuxObject::from_parts(
+        #[allow(unused_mut)]
+        let mut object = TuxObject::from_parts(
             self.file.try_clone()?,
             self.final_path.clone(),
             header,
             self.metadata.clone(),
             true,
-        ))
+        );
+        object.set_lock(self.lock);
+        // The writer knows the key, so the object it hands back can read its own content.
+        #[cfg(feature = "encryption")]
+        object.set_cipher(self.sealer.take().map(|sealer| sealer.into_cipher()));
+        Ok(object)
     }
 
     /// Abandons the write, removing the temporary file.
@@ -224,17 +479,123 @@ impl ObjectWriter {
             compression_type: self.compression,
             tags_start: self.layout.tags_start,
             content_start: self.layout.content_start,
-            content_length: self.content_length,
-            bit_flags: 0,
+            content_length: self.stored_length,
+            bit_flags: self.bit_flags,
+            generation: self.generation,
+        }
+    }
+
+    /// Settles the generation from the object at the destination, failing when the precondition
+    /// does not hold. A generation precondition returns the object it checked, locked until the
+    /// replacement is published.
+    fn check_precondition(&mut self) -> ObjectFileResult<Option<TuxObject>> {
+        match self.precondition {
+            Precondition::None => {
+                // An unreadable file in the way is replaced like any other.
+                let replaced = match generation_under_lock(&self.final_path, &self.locked_file) {
+                    Ok(Some(generation)) => Some(generation),
+                    _ => current_generation(&self.final_path).ok().flatten(),
+                };
+                self.generation = next_generation(replaced);
+                Ok(None)
+            }
+            Precondition::Absent => {
+                if std::fs::exists(&self.final_path)? {
+                    return Err(ObjectFileError::PreconditionFailed {
+                        expected: None,
+                        found: current_generation(&self.final_path).ok().flatten(),
+                    });
+                }
+                self.generation = 1;
+                Ok(None)
+            }
+            Precondition::Generation(expected) => {
+                // Only an exclusive lock keeps other lockers out from the check to the publish.
+                if self.lock == Some(LockMode::Shared) {
+                    return Err(ObjectFileError::ConditionalWriteUnderSharedLock);
+                }
+                if let Some(found) = generation_under_lock(&self.final_path, &self.locked_file)? {
+                    if found != expected {
+                        return Err(ObjectFileError::PreconditionFailed {
+                            expected: Some(expected),
+                            found: Some(found),
+                        });
+                    }
+                    self.generation = next_generation(Some(found));
+                    return Ok(None);
+                }
+                let missing = ObjectFileError::PreconditionFailed {
+                    expected: Some(expected),
+                    found: None,
+                };
+                let mut replaced = match TuxObject::open(&self.final_path) {
+                    Ok(replaced) => replaced,
+                    Err(err) if err.is_not_found() => return Err(missing),
+                    Err(err) => return Err(err),
+                };
+                match replaced.lock(LockMode::Exclusive) {
+                    Ok(()) => {}
+                    Err(err) if err.is_not_found() => return Err(missing),
+                    Err(err) => return Err(err),
+                }
+                let found = replaced.generation();
+                if found != expected {
+                    return Err(ObjectFileError::PreconditionFailed {
+                        expected: Some(expected),
+                        found: Some(found),
+                    });
+                }
+                self.generation = next_generation(Some(found));
+                Ok(Some(replaced))
+            }
         }
     }
 
-    /// Renames the temporary file over the destination.
+    /// Moves the temporary file to the destination.
     fn publish(&mut self) -> ObjectFileResult<()> {
-        let Some(temp_path) = self.temp_path.take() else {
+        let Some(temp_path) = &self.temp_path else {
             return Ok(());
         };
-        std::fs::rename(&temp_path, &self.final_path)?;
+        // Nobody else can have the temp file open, so this never waits.
+        if let Some(mode) = self.lock {
+            lock_file(&self.file, mode, LockWait::Forever)?;
+        }
+        let replacing = self.precondition != Precondition::Absent;
+        let kept = match stamped_version_id(&self.metadata) {
+            Some(version_id) if self.versioned => Some(keep_published(
+                &self.final_path,
+                temp_path,
+                version_id,
+                replacing,
+            )?),
+            _ => None,
+        };
+        let published = if replacing {
+            std::fs::rename(temp_path, &self.final_path).map_err(ObjectFileError::from)
+        } else {
+            // Unlike a rename, a link fails rather than replace whatever got there first.
+            match std::fs::hard_link(temp_path, &self.final_path) {
+                Ok(()) => {
+                    let _ = std::fs::remove_file(temp_path);
+                    Ok(())
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Err(ObjectFileError::PreconditionFailed {
+                        expected: None,
+                        found: current_generation(&self.final_path).ok().flatten(),
+                    })
+                }
+                Err(err) => Err(err.into()),
+            }
+        };
+        if let Err(err) = published {
+            // Never published, so it is no version of the object.
+            if let Some(kept) = kept {
+                let _ = std::fs::remove_file(kept);
+            }
+            return Err(err);
+        }
+        self.temp_path = None;
         // Renames are only durable once the directory entry itself is flushed.
         if self.sync
             && let Some(parent) = self.final_path.parent()
@@ -253,7 +614,15 @@ impl ObjectWriter {
             tags: std::mem::take(&mut self.tags),
             layout: self.layout_options,
             compression: self.compression,
+            // Already decided while the content was streamed; the copy below is in stored form.
+            compression_policy: CompressionPolicy::Fixed,
+            compression_workers: 0,
+            #[cfg(feature = "encryption")]
+            encryption: None,
             sync: self.sync,
+            versioned: self.versioned,
+            // Filled in by now, if it was going to be.
+            sniff_content_type: false,
         };
         // Make sure the fresh layout actually has room for what we are carrying over, even if the
         // caller configured no reserve at all.
@@ -262,21 +631,96 @@ impl ObjectWriter {
         options.layout.tag_reserve = options.layout.tag_reserve.max(DEFAULT_REWRITE_RESERVE);
 
         let mut replacement = ObjectWriter::create(&self.final_path, options)?;
+        replacement.carry_bit_flags(self.bit_flags);
+        replacement.lock = self.lock;
+        replacement.locked_file = self.locked_file.take();
+        replacement.precondition = self.precondition;
 
         self.file.flush()?;
         self.file
             .seek(SeekFrom::Start(self.layout.content_start as u64))?;
-        let mut source = crate::fs::ContentReader::new(&mut self.file, self.content_length);
-        std::io::copy(&mut source, &mut replacement)?;
+        let mut source = crate::fs::ContentReader::new(
+            &mut self.file,
+            self.layout.content_start as u64,
+            0,
+            self.stored_length,
+        );
+        replacement.copy_stored_content(&mut source)?;
 
         // `self` still owns the original temp file; dropping it removes it.
-        replacement.finish()
+        #[allow(unused_mut)]
+        let mut object = replacement.finish()?;
+        #[cfg(feature = "encryption")]
+        object.set_cipher(self.sealer.take().map(|sealer| sealer.into_cipher()));
+        Ok(object)
+    }
+}
+
+/// What the object at a writer's destination must be for it to publish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Precondition {
+    None,
+    Absent,
+    Generation(u64),
+}
+
+/// The generation of the object at `path`, or `None` when there is none.
+pub(crate) fn current_generation(path: &Path) -> ObjectFileResult<Option<u64>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let header = <ObjectHeader as crate::ReadableObjectType>::read_from_reader(&mut file)?;
+    Ok(Some(header.generation))
+}
+
+/// The generation of the object at `path`, read through `locked_file`, the file a carried lock is
+/// held on. `None` without one, or when the object at `path` is no longer that file: replaced or
+/// removed by a writer that does not lock, it is checked as though no lock were held.
+pub(crate) fn generation_under_lock(
+    path: &Path,
+    locked_file: &Option<File>,
+) -> ObjectFileResult<Option<u64>> {
+    let Some(file) = locked_file else {
+        return Ok(None);
+    };
+    match std::fs::metadata(path) {
+        Ok(current) if same_file(&file.metadata()?, &current) => {}
+        _ => return Ok(None),
     }
+    let mut header = [0u8; HEADER_SIZE];
+    read_exact_at(file, &mut header, 0)?;
+    Ok(Some(
+        <ObjectHeader as crate::ReadableObjectType>::read_from_bytes(&header)?.generation,
+    ))
+}
+
+/// The generation of an object replacing one at `replaced`, or created where there was none.
+pub(crate) fn next_generation(replaced: Option<u64>) -> u64 {
+    replaced.map_or(1, |generation| generation.saturating_add(1))
 }
 
 /// Extra slack handed to a rewrite so the very next metadata update does not trigger another one.
 const DEFAULT_REWRITE_RESERVE: usize = 256;
 
+impl ObjectWriter {
+    /// Writes content, sealing it first when encrypting, and returns how much of `buf` was taken.
+    fn write_content(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        #[cfg(feature = "encryption")]
+        if let Some(sealer) = &mut self.sealer {
+            let (taken, stored) = sealer.write(&mut self.file, buf)?;
+            self.content_length += taken as u64;
+            self.stored_length += stored;
+            return Ok(taken);
+        }
+        let written = self.file.write(buf)?;
+        self.content_length += written as u64;
+        self.stored_length += written as u64;
+        Ok(written)
+    }
+}
+
 impl Write for ObjectWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         if !self.allow_raw_writes {
@@ -285,8 +729,11 @@ impl Write for ObjectWriter {
                  ObjectWriter::content_encoder",
             ));
         }
-        let written = self.file.write(buf)?;
-        self.content_length += written as u64;
+        let written = self.write_content(buf)?;
+        // Compressed content is sniffed by its encoder, before it is compressed.
+        if matches!(self.compression, CompressionTypes::None(_)) {
+            self.sniff(&buf[..written]);
+        }
         Ok(written)
     }
     fn flush(&mut self) -> std::io::Result<()> {
@@ -336,20 +783,24 @@ pub(crate) fn encode_prefix(
     Ok(buffer)
 }
 
-/// Creates a uniquely named temp file next to `final_path`, so the eventual rename stays within one
-/// filesystem and is therefore atomic.
-fn create_temp_file(final_path: &Path) -> ObjectFileResult<(File, PathBuf)> {
+/// A temp file name next to `final_path` that no other writer in this process will use.
+pub(crate) fn temp_path_for(final_path: &Path) -> PathBuf {
     let parent = final_path.parent().unwrap_or_else(|| Path::new("."));
     let stem = final_path
         .file_name()
         .map(|name| name.to_string_lossy().into_owned())
         .unwrap_or_else(|| "object".to_owned());
     let pid = std::process::id();
+    let counter = next_temp_counter();
+    parent.join(format!(".{stem}.{pid}.{counter}.{TEMP_FILE_EXTENSION}"))
+}
 
+/// Creates a uniquely named temp file next to `final_path`, so the eventual rename stays within one
+/// filesystem and is therefore atomic.
+pub(crate) fn create_temp_file(final_path: &Path) -> ObjectFileResult<(File, PathBuf)> {
     // Retry rather than trust the counter alone: another process could hold the same name.
     for _ in 0..32 {
-        let counter = next_temp_counter();
-        let candidate = parent.join(format!(".{stem}.{pid}.{counter}.tuxtmp"));
+        let candidate = temp_path_for(final_path);
         match OpenOptions::new()
             .read(true)
             .write(true)
@@ -23,10 +23,24 @@ pub struct ObjectHeader {
     pub content_length: u64,
     /// Bit flags for the object
     ///
-    /// Currently unused
+    /// See [ObjectHeader::ENCRYPTED_CONTENT]. Undefined bits are zero.
     pub bit_flags: u8,
+    /// Counts the writes to the object: 1 when first published, then one more for each
+    /// replacement or section update. An object deleted and created again starts from 1, so the
+    /// count only orders the writes since the object was last created.
+    ///
+    /// Zero for objects written before the counter existed, whose header kept these bytes zeroed.
+    pub generation: u64,
 }
 impl ObjectHeader {
+    /// The content section is encrypted. Its algorithm, key and nonce are described by the
+    /// `x-tuxio-encryption*` metadata keys.
+    pub const ENCRYPTED_CONTENT: u8 = 0b0000_0001;
+
+    /// True when [ObjectHeader::ENCRYPTED_CONTENT] is set.
+    pub fn is_encrypted(&self) -> bool {
+        self.bit_flags & Self::ENCRYPTED_CONTENT != 0
+    }
     /// Returns the amount of space reserved for tags in the file.
     ///
     /// ### Note
@@ -61,6 +75,7 @@ impl Default for ObjectHeader {
             content_start: 0,
             content_length: 0,
             bit_flags: 0,
+            generation: 0,
         }
     }
 }
@@ -132,6 +147,11 @@ impl ReadableObjectType for ObjectHeader {
                 .map_err(|_| EncodingError::UnexpectedEof)?,
         );
         let bit_flags = content[23];
+        let generation = u64::from_le_bytes(
+            content[24..32]
+                .try_into()
+                .map_err(|_| EncodingError::UnexpectedEof)?,
+        );
         Ok(ObjectHeader {
             version,
             compression_type,
@@ -139,6 +159,7 @@ impl ReadableObjectType for ObjectHeader {
             content_start,
             content_length,
             bit_flags,
+            generation,
         })
     }
 }
@@ -152,12 +173,7 @@ impl WritableObjectType for ObjectHeader {
         self.content_start.write_to_writer(writer)?;
         self.content_length.write_to_writer(writer)?;
         writer.write_all(&[self.bit_flags])?;
-        if self.const_size().is_some() {
-            let padding = 32 - (MAGIC_VALUE.len() + 1 + 5 + 2 + 4 + 8 + 1);
-            if padding > 0 {
-                writer.write_all(&vec![0; padding])?;
-            }
-        }
+        self.generation.write_to_writer(writer)?;
         Ok(())
     }
 }
@@ -206,6 +222,7 @@ mod tests {
             content_start: 20,
             content_length: 100,
             bit_flags: 0,
+            generation: 7,
         };
         let mut buffer = Vec::new();
         header.write_to_writer(&mut buffer).unwrap();
@@ -232,6 +249,7 @@ mod tests {
             content_start: 0x0304_0506,
             content_length: 0x0708_090A_0B0C_0D0E,
             bit_flags: 0xFF,
+            generation: 0x1011_1213_1415_1617,
         };
 
         let encoded = header.write_to_bytes().unwrap();
@@ -248,8 +266,7 @@ mod tests {
         assert_eq!(&encoded[11..15], &0x0304_0506u32.to_le_bytes());
         assert_eq!(&encoded[15..23], &0x0708_090A_0B0C_0D0Eu64.to_le_bytes());
         assert_eq!(encoded[23], 0xFF, "bit flags");
-        // The rest is reserved and must be zeroed, so a future field starts from a known state.
-        assert_eq!(&encoded[24..32], &[0u8; 8], "reserved bytes must be zero");
+        assert_eq!(&encoded[24..32], &0x1011_1213_1415_1617u64.to_le_bytes());
 
         assert_eq!(ObjectHeader::read_from_bytes(&encoded).unwrap(), header);
     }
@@ -296,6 +313,7 @@ mod tests {
             content_start: 256,
             content_length: 256,
             bit_flags: 0,
+            generation: 0,
         };
         assert_eq!(header.tags_space(), 192); // 256 - 64
         assert_eq!(header.meta_and_tag_space(), 224); // 256 - 32
@@ -55,6 +55,12 @@ Implement [TuxIOType] plus the two directions for a new type; add [TypedObjectTy
 to be storable as a [ValueType]. **The three must agree on one number** — see the invariant documented
 on [TuxIOType].
 
+A struct that is just its fields one after another can derive all three instead, with
+[`#[derive(TuxEncode, TuxDecode)]`](TuxEncode).
+
+A struct whose fields are metadata or tag entries can derive [TuxMetadata], which converts it to and
+from a [MetadataMap] or [Tags] with its keys and value types checked in one place.
+
 # Type keys
 
 Every [ValueType] is written as a one-byte type key followed by the value. The keys are part of the
@@ -103,6 +109,9 @@ header can *name* a codec regardless — these are what make it readable); `get-
 usage.
 */
 
+// Lets the derives' `::tux_io_encoding` paths resolve inside this crate too.
+extern crate self as tux_io_encoding;
+
 pub mod compression_types;
 pub mod fs;
 mod header;
@@ -116,6 +125,14 @@ use std::io::{Read, Seek, SeekFrom};
 pub use compression_types::CompressionTypes;
 pub use header::*;
 pub use tags::*;
+pub use tux_io_encoding_macros::{TuxDecode, TuxEncode, TuxMetadata, ValueEnum};
+
+/// What the derives' generated code refers to. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::tags::schema::{optional, required};
+    pub use http::HeaderName;
+}
 pub use types::{RawDate, RawDateTime, RawTime, RawTimeZone};
 
 pub use value::*;
@@ -493,6 +510,102 @@ mod invariants {
         sizes_agree(ObjectHeader::default());
     }
 
+    /// A record as the derives lay it out: its encoded fields back to back.
+    #[derive(Debug, Default, PartialEq, TuxEncode, TuxDecode)]
+    struct PartRecord {
+        number: u32,
+        etag: String,
+        uploaded: i64,
+        #[tux(skip)]
+        cached: Option<u64>,
+        #[tux(with = as_u8)]
+        flags: u32,
+    }
+
+    /// Stores a `u32` that never exceeds a byte in one.
+    mod as_u8 {
+        use crate::*;
+
+        pub fn size(_: &u32) -> usize {
+            1
+        }
+        pub fn write<W: std::io::Write>(value: &u32, writer: &mut W) -> Result<(), EncodingError> {
+            (*value as u8).write_to_writer(writer)
+        }
+        pub fn read<R: std::io::Read>(reader: &mut R) -> Result<u32, EncodingError> {
+            u8::read_from_reader(reader).map(u32::from)
+        }
+        pub fn read_size<R: std::io::Read + std::io::Seek>(
+            reader: &mut R,
+        ) -> Result<usize, EncodingError> {
+            u8::read_size(reader)
+        }
+    }
+
+    #[derive(Debug, PartialEq, TuxEncode, TuxDecode)]
+    struct Fixed(u16, bool, RawTime);
+
+    /// A generic record, whose impls each bound the parameter by what they need of it.
+    #[derive(Debug, PartialEq, TuxEncode, TuxDecode)]
+    struct Wrapper<T> {
+        inner: T,
+        count: u32,
+    }
+
+    #[test]
+    fn derived_records_agree() {
+        let record = PartRecord {
+            number: 7,
+            etag: "\"abc\"".to_owned(),
+            uploaded: 1_800_000_000,
+            cached: Some(12),
+            flags: 3,
+        };
+        sizes_agree(PartRecord::default());
+        record.assert_encoding_invariants();
+        assert_eq!(record.size(), 4 + (2 + 5) + 8 + 1);
+        assert_eq!(record.const_size(), None);
+
+        // Everything but the skipped field comes back.
+        let decoded = PartRecord::read_from_bytes(&record.write_to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            decoded,
+            PartRecord {
+                cached: None,
+                ..record
+            }
+        );
+
+        let fixed = Fixed(
+            1,
+            true,
+            RawTime {
+                seconds_from_midnight: 43_200,
+                nanoseconds: 1,
+            },
+        );
+        fixed.assert_encoding_invariants();
+        assert_eq!(fixed.const_size(), Some(2 + 1 + 8));
+        assert_eq!(
+            Fixed::read_from_bytes(&fixed.write_to_bytes().unwrap()).unwrap(),
+            fixed
+        );
+
+        let wrapper = Wrapper {
+            inner: "inner".to_owned(),
+            count: 2,
+        };
+        sizes_agree(Wrapper {
+            inner: 7u64,
+            count: 1,
+        });
+        wrapper.assert_encoding_invariants();
+        assert_eq!(
+            Wrapper::<String>::read_from_bytes(&wrapper.write_to_bytes().unwrap()).unwrap(),
+            wrapper
+        );
+    }
+
     #[cfg(feature = "bytes")]
     #[test]
     fn bytes_agree() {
@@ -5,11 +5,13 @@ use std::{
     io::{Read, Seek, Write},
 };
 mod meta_key;
+pub(crate) mod schema;
 use crate::{
     EncodingError, ReadableObjectType, TuxIOType, ValueType, WritableObjectType,
     types::count_is_allowed,
 };
 pub use meta_key::*;
+pub use schema::MetadataError;
 pub trait TagKeyType:
     Hash
     + PartialEq
@@ -0,0 +1,137 @@
+//! Support for `#[derive(TuxMetadata)]`, which maps a struct's fields to keys of a
+//! [crate::MetadataMap] or [crate::Tags].
+
+use crate::ValueType;
+
+/// Why a [crate::MetadataMap] or [crate::Tags] could not be read as a struct deriving
+/// [crate::TuxMetadata].
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum MetadataError {
+    /// A key for a field that is not an [Option] was absent.
+    #[error("the {0} key is missing")]
+    MissingKey(&'static str),
+    /// A key held a value of another type than its field's.
+    #[error("the {key} key holds {found:?}, which is not a {expected}")]
+    WrongType {
+        key: &'static str,
+        /// The field's type.
+        expected: &'static str,
+        found: ValueType,
+    },
+}
+
+/// Reads the value under `key` for a required field.
+pub fn required<T>(key: &'static str, value: Option<&ValueType>) -> Result<T, MetadataError>
+where
+    Option<T>: From<ValueType>,
+{
+    optional(key, value)?.ok_or(MetadataError::MissingKey(key))
+}
+
+/// Reads the value under `key` for an [Option] field, which an absent key leaves `None`.
+pub fn optional<T>(key: &'static str, value: Option<&ValueType>) -> Result<Option<T>, MetadataError>
+where
+    Option<T>: From<ValueType>,
+{
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    match Option::<T>::from(value.clone()) {
+        Some(value) => Ok(Some(value)),
+        None => Err(MetadataError::WrongType {
+            key,
+            expected: std::any::type_name::<T>(),
+            found: value.clone(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{CONTENT_TYPE, ETAG};
+
+    use crate::{MetadataError, MetadataMap, RawDateTime, Tags, TuxMetadata, ValueType};
+
+    #[derive(Debug, Clone, PartialEq, TuxMetadata)]
+    struct ObjectMetadata {
+        #[tux(key = "content-type")]
+        content_type: String,
+        etag: Option<String>,
+        #[tux(key = "x-tuxio-size")]
+        size: u64,
+        last_modified: Option<RawDateTime>,
+        #[tux(skip)]
+        cached: bool,
+    }
+
+    fn example() -> ObjectMetadata {
+        ObjectMetadata {
+            content_type: "text/plain".to_owned(),
+            etag: Some("\"abc\"".to_owned()),
+            size: 42,
+            last_modified: None,
+            cached: true,
+        }
+    }
+
+    #[test]
+    fn fields_round_trip_through_metadata() {
+        let metadata = example().into_metadata_map();
+        assert_eq!(
+            metadata.get_header(&CONTENT_TYPE),
+            Some(&ValueType::String("text/plain".to_owned()))
+        );
+        assert_eq!(
+            metadata.get_header(&ETAG),
+            Some(&ValueType::String("\"abc\"".to_owned()))
+        );
+        assert_eq!(
+            metadata.get(&"x-tuxio-size".try_into().unwrap()),
+            Some(&ValueType::U64(42))
+        );
+        // `None` and skipped fields are left out.
+        assert_eq!(metadata.number_of_tags(), 3);
+
+        let read = ObjectMetadata::try_from(&metadata).unwrap();
+        assert_eq!(
+            read,
+            ObjectMetadata {
+                cached: false,
+                ..example()
+            }
+        );
+
+        let tags = example().into_tags();
+        assert_eq!(tags.get("x-tuxio-size"), Some(&ValueType::U64(42)));
+        assert_eq!(ObjectMetadata::try_from(&tags).unwrap(), read);
+    }
+
+    #[test]
+    fn missing_keys_and_wrong_types_are_errors() {
+        let mut metadata = example().into_metadata_map();
+        metadata.remove_header(&ETAG);
+        assert_eq!(ObjectMetadata::try_from(&metadata).unwrap().etag, None);
+
+        metadata.remove_header(&CONTENT_TYPE);
+        assert_eq!(
+            ObjectMetadata::try_from(&metadata),
+            Err(MetadataError::MissingKey("content-type"))
+        );
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert_header(CONTENT_TYPE, "text/plain".to_owned().into());
+        metadata.insert_header(
+            http::HeaderName::from_static("x-tuxio-size"),
+            "42".to_owned().into(),
+        );
+        assert_eq!(
+            ObjectMetadata::try_from(&metadata),
+            Err(MetadataError::WrongType {
+                key: "x-tuxio-size",
+                expected: "u64",
+                found: ValueType::String("42".to_owned()),
+            })
+        );
+        assert!(ObjectMetadata::try_from(&Tags::<String>::new()).is_err());
+    }
+}
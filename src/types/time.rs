@@ -1,5 +1,8 @@
 //! Implementation of the time types used in tux-io. These are written as library agnostic.
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use tux_io_encoding_macros::ObjectType;
 #[cfg(feature = "chrono")]
@@ -129,6 +132,93 @@ impl ReadableObjectType for RawDateTime {
     }
 }
 
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+impl RawDateTime {
+    /// The instant as seconds since the Unix epoch, and nanoseconds past that second.
+    ///
+    /// The timezone offset is taken out, so values for one instant in different zones agree — which
+    /// is how two are compared, since the derived [PartialEq] also compares the zone.
+    pub fn unix_timestamp(&self) -> (i64, u32) {
+        let days = days_from_civil(
+            self.date.year as i64,
+            self.date.month as i64,
+            self.date.day as i64,
+        );
+        let seconds = days * SECONDS_PER_DAY + self.time.seconds_from_midnight as i64
+            - self.timezone.offset as i64;
+        (seconds, self.time.nanoseconds)
+    }
+
+    /// The instant `seconds` and `nanoseconds` after the Unix epoch, in UTC.
+    pub fn from_unix_timestamp(seconds: i64, nanoseconds: u32) -> Self {
+        let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+        RawDateTime {
+            date: RawDate {
+                year: year.clamp(0, u16::MAX as i64) as u16,
+                month,
+                day,
+            },
+            time: RawTime {
+                seconds_from_midnight: seconds.rem_euclid(SECONDS_PER_DAY) as u32,
+                nanoseconds,
+            },
+            timezone: RawTimeZone { offset: 0 },
+        }
+    }
+
+    /// The current time, in UTC.
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+}
+
+impl From<SystemTime> for RawDateTime {
+    fn from(value: SystemTime) -> Self {
+        match value.duration_since(UNIX_EPOCH) {
+            Ok(since) => Self::from_unix_timestamp(since.as_secs() as i64, since.subsec_nanos()),
+            Err(err) => {
+                let before = err.duration();
+                let seconds = -(before.as_secs() as i64);
+                match before.subsec_nanos() {
+                    0 => Self::from_unix_timestamp(seconds, 0),
+                    nanos => Self::from_unix_timestamp(seconds - 1, 1_000_000_000 - nanos),
+                }
+            }
+        }
+    }
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar. Howard Hinnant's
+/// `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of [days_from_civil]: the year, month and day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u8, day as u8)
+}
+
 impl Debug for RawDateTime {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let RawDateTime {
@@ -209,3 +299,61 @@ mod get_size_impl {
     impl GetSize for RawTime {}
     impl GetSize for RawTimeZone {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_timestamps_round_trip() {
+        let leap_day = RawDateTime {
+            date: RawDate {
+                year: 2024,
+                month: 2,
+                day: 29,
+            },
+            time: RawTime {
+                seconds_from_midnight: 12 * 3600 + 34 * 60 + 56,
+                nanoseconds: 789,
+            },
+            timezone: RawTimeZone { offset: 0 },
+        };
+        assert_eq!(leap_day.unix_timestamp(), (1_709_210_096, 789));
+        assert_eq!(
+            RawDateTime::from_unix_timestamp(1_709_210_096, 789),
+            leap_day
+        );
+
+        for seconds in [0, -1, 86_399, 951_782_400, -2_208_988_800, 4_102_444_800] {
+            let date_time = RawDateTime::from_unix_timestamp(seconds, 0);
+            assert_eq!(date_time.unix_timestamp(), (seconds, 0));
+        }
+        let before_epoch = RawDateTime::from_unix_timestamp(-1, 0);
+        assert_eq!(
+            (
+                before_epoch.date.year,
+                before_epoch.date.month,
+                before_epoch.date.day
+            ),
+            (1969, 12, 31)
+        );
+    }
+
+    #[test]
+    fn offsets_are_taken_out_of_timestamps() {
+        let utc = RawDateTime::from_unix_timestamp(1_000_000, 0);
+        let mut ahead = utc;
+        ahead.time.seconds_from_midnight += 3600;
+        ahead.timezone.offset = 3600;
+        assert_ne!(ahead, utc);
+        assert_eq!(ahead.unix_timestamp(), utc.unix_timestamp());
+
+        let moment = UNIX_EPOCH + std::time::Duration::new(1_000_000, 5);
+        assert_eq!(RawDateTime::from(moment).unix_timestamp(), (1_000_000, 5));
+        let earlier = UNIX_EPOCH - std::time::Duration::new(1, 5);
+        assert_eq!(
+            RawDateTime::from(earlier).unix_timestamp(),
+            (-2, 999_999_995)
+        );
+    }
+}
@@ -1,10 +1,8 @@
 use tux_io_encoding_macros::ValueEnum;
 
-use crate::{
-    ConstTypedObjectType, EncodingError, RawDate, RawDateTime, RawTime, ReadableObjectType,
-    TuxIOType, WritableObjectType,
-};
+use crate::{RawDate, RawDateTime, RawTime};
 #[derive(Debug, Clone, PartialEq, ValueEnum)]
+#[value_enum(crate_value_type)]
 pub enum ValueType {
     String(String),
     Bytes(Vec<u8>),
@@ -71,7 +69,10 @@ mod tokio_async {
     use tokio::io::{AsyncRead, AsyncReadExt};
 
     use super::*;
-    use crate::tokio_io::{AsyncReadableObjectType, AsyncWritableObjectType};
+    use crate::{
+        ConstTypedObjectType, EncodingError,
+        tokio_io::{AsyncReadableObjectType, AsyncWritableObjectType},
+    };
 
     impl AsyncWritableObjectType for ValueType {}
     impl AsyncReadableObjectType for ValueType {
@@ -147,3 +148,84 @@ mod tokio_async {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncodingError, ReadableObjectType, WritableObjectType};
+
+    /// A restricted value enum, as a service might accept from its users.
+    #[derive(Debug, Clone, PartialEq, ValueEnum)]
+    enum PublicTagValue {
+        Text(String),
+        Number(u64),
+        Signed(i64),
+    }
+
+    #[test]
+    fn restricted_enums_share_the_wire_format() {
+        let value = PublicTagValue::from("public".to_owned());
+        let encoded = value.write_to_bytes().unwrap();
+        assert_eq!(
+            encoded,
+            ValueType::from("public".to_owned())
+                .write_to_bytes()
+                .unwrap()
+        );
+        assert_eq!(
+            ValueType::read_from_bytes(&encoded).unwrap(),
+            ValueType::String("public".to_owned())
+        );
+        assert_eq!(PublicTagValue::read_from_bytes(&encoded).unwrap(), value);
+        assert_eq!(
+            value.type_key(),
+            ValueType::String(String::new()).type_key()
+        );
+
+        // A value of a type the enum leaves out does not read as one.
+        let encoded = ValueType::Bool(true).write_to_bytes().unwrap();
+        assert!(matches!(
+            PublicTagValue::read_from_bytes(&encoded),
+            Err(EncodingError::UnknownTypeKey(_))
+        ));
+    }
+
+    #[test]
+    fn restricted_enums_convert_to_and_from_value_type() {
+        assert_eq!(
+            ValueType::from(PublicTagValue::Number(7)),
+            ValueType::U64(7)
+        );
+        assert_eq!(
+            PublicTagValue::try_from(ValueType::I64(-7)),
+            Ok(PublicTagValue::Signed(-7))
+        );
+        assert_eq!(
+            PublicTagValue::try_from(ValueType::U8(7)),
+            Err(ValueType::U8(7))
+        );
+        assert_eq!(Option::<u64>::from(PublicTagValue::Number(7)), Some(7));
+        assert_eq!(Option::<String>::from(PublicTagValue::Number(7)), None);
+    }
+
+    /// A user's enum that happens to share the crate's enum's name.
+    mod shadowing {
+        #[derive(Debug, Clone, PartialEq, tux_io_encoding_macros::ValueEnum)]
+        pub(super) enum ValueType {
+            Text(String),
+        }
+    }
+
+    #[test]
+    fn the_name_alone_does_not_make_an_enum_the_crates_own() {
+        let value = shadowing::ValueType::Text("text".to_owned());
+        assert_eq!(
+            ValueType::from(value.clone()),
+            ValueType::String("text".to_owned())
+        );
+        assert_eq!(
+            shadowing::ValueType::try_from(ValueType::String("text".to_owned())),
+            Ok(value)
+        );
+    }
+}